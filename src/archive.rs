@@ -0,0 +1,558 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::crc32::Crc32;
+use crate::inflate::Inflate;
+
+/// The signatures of the records of a zip file
+const ZIP_END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR: u32 = 0x0706_4b50;
+const ZIP_CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+
+/// The IDs of the extra fields of a zip entry with its 64-bit sizes and its Unix modification time
+const ZIP64_EXTRA_FIELD: u16 = 0x0001;
+const EXTENDED_TIMESTAMP_EXTRA_FIELD: u16 = 0x5455;
+
+/// The size of the blocks of a tar file
+const TAR_BLOCK_SIZE: u64 = 512;
+
+/// An error for an archive that can't be read
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// A file in an archive
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    /// The path of the file in the archive, with `/` separators
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// The kind of archive a file is, from its extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Tgz,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tgz") || name.ends_with(".tar.gz") {
+        Some(ArchiveKind::Tgz)
+    } else {
+        None
+    }
+}
+
+/// Check whether a file is a zip or gzipped tar archive, such as the ones of a Takeout export
+pub fn is_archive(path: &Path) -> bool {
+    archive_kind(path).is_some()
+}
+
+/// Call `visit` with each file of an archive and a reader of its content, in the order of the archive
+/// The content is decompressed as it is read, and a visitor can leave it unread to skip the file.
+/// Directories are left out.
+pub fn for_each_entry(path: &Path, mut visit: impl FnMut(&ArchiveEntry, &mut dyn Read) -> io::Result<()>) -> io::Result<()> {
+    match archive_kind(path) {
+        Some(ArchiveKind::Zip) => for_each_zip_entry(path, &mut visit),
+        Some(ArchiveKind::Tgz) => for_each_tar_entry(gzip_reader(BufReader::new(File::open(path)?))?, &mut visit),
+        None => Err(invalid(format!("{:?} is not a zip or tgz archive", path))),
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> io::Result<u16> {
+    let bytes = data.get(pos..pos + 2).ok_or_else(|| invalid("Truncated zip record"))?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> io::Result<u32> {
+    let bytes = data.get(pos..pos + 4).ok_or_else(|| invalid("Truncated zip record"))?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> io::Result<u64> {
+    Ok(u32_at(data, pos)? as u64 | (u32_at(data, pos + 4)? as u64) << 32)
+}
+
+/// A file of a zip archive, as listed in its central directory
+struct ZipEntry {
+    entry: ArchiveEntry,
+    method: u16,
+    encrypted: bool,
+    crc: u32,
+    compressed_size: u64,
+    local_header_offset: u64,
+}
+
+fn for_each_zip_entry(path: &Path, visit: &mut dyn FnMut(&ArchiveEntry, &mut dyn Read) -> io::Result<()>) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    for zip_entry in read_central_directory(&mut file)? {
+        if zip_entry.entry.name.ends_with('/') {
+            continue;
+        }
+        if zip_entry.encrypted {
+            return Err(invalid(format!("{} is encrypted", zip_entry.entry.name)));
+        }
+        // The data follows the local header, whose extra field may differ from the central one
+        file.seek(SeekFrom::Start(zip_entry.local_header_offset))?;
+        let mut header = [0; 30];
+        file.read_exact(&mut header)?;
+        if u32_at(&header, 0)? != ZIP_LOCAL_HEADER {
+            return Err(invalid(format!("No local header for {}", zip_entry.entry.name)));
+        }
+        let data_offset = zip_entry.local_header_offset + 30 + u16_at(&header, 26)? as u64 + u16_at(&header, 28)? as u64;
+        file.seek(SeekFrom::Start(data_offset))?;
+        let data = BufReader::new(&mut file).take(zip_entry.compressed_size);
+        let name = &zip_entry.entry.name;
+        match zip_entry.method {
+            0 => visit(&zip_entry.entry, &mut CrcCheck::new(data, zip_entry.crc, name))?,
+            8 => visit(&zip_entry.entry, &mut CrcCheck::new(Inflate::new(data), zip_entry.crc, name))?,
+            method => return Err(invalid(format!("Unsupported compression method {} for {}", method, zip_entry.entry.name))),
+        }
+    }
+    Ok(())
+}
+
+/// A reader of the content of a zip entry, failing at its end if the content doesn't have the
+/// CRC-32 of the entry
+struct CrcCheck<'a, R> {
+    input: R,
+    expected: u32,
+    crc: Crc32,
+    name: &'a str,
+}
+
+impl<'a, R: Read> CrcCheck<'a, R> {
+    fn new(input: R, expected: u32, name: &'a str) -> Self {
+        CrcCheck { input, expected, crc: Crc32::new(), name }
+    }
+}
+
+impl<R: Read> Read for CrcCheck<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.input.read(buf)?;
+        self.crc.update(&buf[..read]);
+        if read == 0 && !buf.is_empty() && self.crc.value() != self.expected {
+            return Err(invalid(format!("The content of {} doesn't match its CRC-32, the zip file is probably corrupt", self.name)));
+        }
+        Ok(read)
+    }
+}
+
+/// Read the list of files of a zip archive from the central directory at its end
+fn read_central_directory(file: &mut File) -> io::Result<Vec<ZipEntry>> {
+    // The end of central directory record is followed by a comment of up to 64 KiB
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let tail_len = file_len.min(22 + 0xFFFF);
+    file.seek(SeekFrom::Start(file_len - tail_len))?;
+    let mut tail = vec![0; tail_len as usize];
+    file.read_exact(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&pos| u32_at(&tail, pos).ok() == Some(ZIP_END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| invalid("No end of central directory record, the zip file is probably truncated"))?;
+    let mut entry_count = u16_at(&tail, end + 10)? as u64;
+    let mut directory_size = u32_at(&tail, end + 12)? as u64;
+    let mut directory_offset = u32_at(&tail, end + 16)? as u64;
+
+    // Archives over 4 GiB or with over 65535 files, as Takeout exports often are, use the zip64 records
+    if end >= 20 && u32_at(&tail, end - 20)? == ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR {
+        file.seek(SeekFrom::Start(u64_at(&tail, end - 12)?))?;
+        let mut record = [0; 56];
+        file.read_exact(&mut record)?;
+        if u32_at(&record, 0)? != ZIP64_END_OF_CENTRAL_DIRECTORY {
+            return Err(invalid("Malformed zip64 end of central directory record"));
+        }
+        entry_count = u64_at(&record, 32)?;
+        directory_size = u64_at(&record, 40)?;
+        directory_offset = u64_at(&record, 48)?;
+    }
+
+    file.seek(SeekFrom::Start(directory_offset))?;
+    let mut directory = Vec::new();
+    file.take(directory_size).read_to_end(&mut directory)?;
+
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    for _ in 0..entry_count {
+        if u32_at(&directory, pos)? != ZIP_CENTRAL_DIRECTORY_HEADER {
+            return Err(invalid("Malformed zip central directory"));
+        }
+        let flags = u16_at(&directory, pos + 8)?;
+        let method = u16_at(&directory, pos + 10)?;
+        let dos_time = u16_at(&directory, pos + 12)?;
+        let dos_date = u16_at(&directory, pos + 14)?;
+        let crc = u32_at(&directory, pos + 16)?;
+        let mut compressed_size = u32_at(&directory, pos + 20)? as u64;
+        let mut size = u32_at(&directory, pos + 24)? as u64;
+        let name_len = u16_at(&directory, pos + 28)? as usize;
+        let extra_len = u16_at(&directory, pos + 30)? as usize;
+        let comment_len = u16_at(&directory, pos + 32)? as usize;
+        let mut local_header_offset = u32_at(&directory, pos + 42)? as u64;
+        let name = directory.get(pos + 46..pos + 46 + name_len).ok_or_else(|| invalid("Truncated zip central directory"))?;
+        let name = String::from_utf8_lossy(name).to_string();
+        let extra = directory.get(pos + 46 + name_len..pos + 46 + name_len + extra_len).unwrap_or_default();
+
+        let mut modified = dos_date_time(dos_date, dos_time);
+        let mut field_pos = 0;
+        while field_pos + 4 <= extra.len() {
+            let id = u16_at(extra, field_pos)?;
+            let field_len = u16_at(extra, field_pos + 2)? as usize;
+            let field = extra.get(field_pos + 4..field_pos + 4 + field_len).unwrap_or_default();
+            match id {
+                // Only the values that don't fit in the header are in the field, in this order
+                ZIP64_EXTRA_FIELD => {
+                    let mut values = field.chunks_exact(8).map(|value| u64_at(value, 0));
+                    if size == 0xFFFF_FFFF {
+                        size = values.next().ok_or_else(|| invalid("Truncated zip64 field"))??;
+                    }
+                    if compressed_size == 0xFFFF_FFFF {
+                        compressed_size = values.next().ok_or_else(|| invalid("Truncated zip64 field"))??;
+                    }
+                    if local_header_offset == 0xFFFF_FFFF {
+                        local_header_offset = values.next().ok_or_else(|| invalid("Truncated zip64 field"))??;
+                    }
+                }
+                // The modification time comes first when its flag is set
+                EXTENDED_TIMESTAMP_EXTRA_FIELD if field.first().is_some_and(|flags| flags & 1 == 1) => {
+                    if let Ok(seconds) = u32_at(field, 1) {
+                        modified = DateTime::from_timestamp(seconds as i32 as i64, 0);
+                    }
+                }
+                _ => {}
+            }
+            field_pos += 4 + field_len;
+        }
+
+        entries.push(ZipEntry {
+            entry: ArchiveEntry { name, size, modified },
+            method,
+            encrypted: flags & 1 == 1,
+            crc,
+            compressed_size,
+            local_header_offset,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// Convert the MS-DOS date and time of a zip entry, which has no time zone and is taken as UTC
+fn dos_date_time(date: u16, time: u16) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::from_ymd_opt(1980 + (date >> 9) as i32, (date >> 5 & 0xF) as u32, (date & 0x1F) as u32)?;
+    let time = date.and_hms_opt((time >> 11) as u32, (time >> 5 & 0x3F) as u32, (time & 0x1F) as u32 * 2)?;
+    Some(time.and_utc())
+}
+
+/// Skip the header of a gzip file, returning a reader of its decompressed content
+fn gzip_reader<R: Read>(mut input: R) -> io::Result<Inflate<R>> {
+    let mut header = [0; 10];
+    input.read_exact(&mut header)?;
+    if header[..3] != [0x1F, 0x8B, 8] {
+        return Err(invalid("Not a gzip file"));
+    }
+    let flags = header[3];
+    if flags & 4 != 0 {
+        let mut len = [0; 2];
+        input.read_exact(&mut len)?;
+        io::copy(&mut (&mut input).take(u16::from_le_bytes(len) as u64), &mut io::sink())?;
+    }
+    // The original file name and a comment, each ending with a zero byte
+    for flag in [8, 16] {
+        if flags & flag != 0 {
+            let mut byte = [0; 1];
+            loop {
+                input.read_exact(&mut byte)?;
+                if byte[0] == 0 {
+                    break;
+                }
+            }
+        }
+    }
+    if flags & 2 != 0 {
+        input.read_exact(&mut [0; 2])?;
+    }
+    Ok(Inflate::new(input))
+}
+
+/// Parse a number of a tar header, in octal or in the base-256 encoding of large GNU numbers
+fn tar_number(field: &[u8]) -> io::Result<u64> {
+    if field.first().is_some_and(|byte| byte & 0x80 != 0) {
+        return Ok(field[1..].iter().fold(0, |value, &byte| value << 8 | byte as u64));
+    }
+    let digits = String::from_utf8_lossy(field);
+    let digits = digits.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| invalid("Malformed number in a tar header"))
+}
+
+/// Get a string field of a tar header, which ends at the first zero byte
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+/// Get the path of a PAX extended header, made of `<length> <key>=<value>\n` records
+fn pax_path(records: &[u8]) -> Option<String> {
+    let records = String::from_utf8_lossy(records);
+    records.lines().find_map(|record| {
+        let (_, key_value) = record.split_once(' ')?;
+        key_value.strip_prefix("path=").map(str::to_string)
+    })
+}
+
+fn for_each_tar_entry(mut input: impl Read, visit: &mut dyn FnMut(&ArchiveEntry, &mut dyn Read) -> io::Result<()>) -> io::Result<()> {
+    // The name given by a GNU long name or PAX header to the next entry
+    let mut next_name = None;
+    loop {
+        let mut header = [0; TAR_BLOCK_SIZE as usize];
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        // The archive ends with empty blocks
+        if header.iter().all(|&byte| byte == 0) {
+            return Ok(());
+        }
+        let size = tar_number(&header[124..136])?;
+        let kind = header[156];
+        let padding = (TAR_BLOCK_SIZE - size % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+        let mut data = (&mut input).take(size);
+
+        match kind {
+            b'L' | b'x' => {
+                let mut contents = Vec::new();
+                data.read_to_end(&mut contents)?;
+                next_name = if kind == b'L' { Some(tar_string(&contents)) } else { pax_path(&contents).or(next_name) };
+            }
+            b'0' | 0 | b'7' => {
+                let name = next_name.take().unwrap_or_else(|| {
+                    let name = tar_string(&header[0..100]);
+                    // The ustar format splits long names into a prefix and a name
+                    match tar_string(&header[345..500]) {
+                        prefix if &header[257..262] == b"ustar" && !prefix.is_empty() => format!("{}/{}", prefix, name),
+                        _ => name,
+                    }
+                });
+                let modified = DateTime::from_timestamp(tar_number(&header[136..148])? as i64, 0);
+                visit(&ArchiveEntry { name, size, modified }, &mut data)?;
+            }
+            _ => next_name = None,
+        }
+        // Skip what the visitor left unread, and the padding to the next block
+        io::copy(&mut data, &mut io::sink())?;
+        io::copy(&mut (&mut input).take(padding), &mut io::sink())?;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use chrono::TimeZone;
+
+    /// The MS-DOS date and time of the zip entries, 2019-05-06 07:08:08
+    const DOS_DATE: u16 = 39 << 9 | 5 << 5 | 6;
+    const DOS_TIME: u16 = 7 << 11 | 8 << 5 | 4;
+
+    /// A file of a test zip archive, with its data as stored and the CRC-32 and size of its content
+    struct TestEntry {
+        name: &'static str,
+        method: u16,
+        data: &'static [u8],
+        crc: u32,
+        size: u32,
+    }
+
+    const METADATA: TestEntry = TestEntry {
+        name: "Takeout/Google Photos/Photos from 2019/IMG_0001.jpg.json",
+        method: 0,
+        data: br#"{"title": "IMG_0001.jpg"}"#,
+        crc: 0xaf36_d14d,
+        size: 25,
+    };
+
+    /// `abcabcabcabcabc hello hello`, compressed with a fixed Huffman block
+    const PHOTO: TestEntry = TestEntry {
+        name: "Takeout/Google Photos/Photos from 2019/IMG_0001.jpg",
+        method: 8,
+        data: &[0x4b, 0x4c, 0x4a, 0x4e, 0x44, 0x42, 0x0a, 0x19, 0xa9, 0x39, 0x39, 0xf9, 0x10, 0x12, 0x00],
+        crc: 0xfbd6_aede,
+        size: 27,
+    };
+
+    const DIRECTORY: TestEntry = TestEntry { name: "Takeout/Google Photos/", method: 0, data: b"", crc: 0, size: 0 };
+
+    /// A path for a test archive in the temporary directory
+    fn archive_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("takeout-exif-fix-{}-{}", std::process::id(), name))
+    }
+
+    fn zip_archive(entries: &[TestEntry]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut directory = Vec::new();
+        for entry in entries {
+            let offset = archive.len() as u32;
+            let fields = |record: &mut Vec<u8>| {
+                for value in [entry.method, DOS_TIME, DOS_DATE] {
+                    record.extend_from_slice(&value.to_le_bytes());
+                }
+                for value in [entry.crc, entry.data.len() as u32, entry.size] {
+                    record.extend_from_slice(&value.to_le_bytes());
+                }
+                record.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+                record.extend_from_slice(&0u16.to_le_bytes());
+            };
+            archive.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+            archive.extend_from_slice(&[20, 0, 0, 0]);
+            fields(&mut archive);
+            archive.extend_from_slice(entry.name.as_bytes());
+            archive.extend_from_slice(entry.data);
+
+            directory.extend_from_slice(&ZIP_CENTRAL_DIRECTORY_HEADER.to_le_bytes());
+            directory.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+            fields(&mut directory);
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+        let directory_offset = archive.len() as u32;
+        archive.extend_from_slice(&directory);
+        archive.extend_from_slice(&ZIP_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        archive.extend_from_slice(&[0; 4]);
+        archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&directory_offset.to_le_bytes());
+        archive.extend_from_slice(&[0; 2]);
+        archive
+    }
+
+    /// A tar block holding the header of a file
+    fn tar_header(name: &str, kind: u8, size: usize, modified: i64) -> [u8; TAR_BLOCK_SIZE as usize] {
+        let mut header = [0; TAR_BLOCK_SIZE as usize];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        header[136..148].copy_from_slice(format!("{:011o}\0", modified).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    pub(crate) fn tar_archive(entries: &[(&str, u8, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for &(name, kind, contents) in entries {
+            archive.extend_from_slice(&tar_header(name, kind, contents.len(), 1_557_126_489));
+            archive.extend_from_slice(contents);
+            archive.resize(archive.len().next_multiple_of(TAR_BLOCK_SIZE as usize), 0);
+        }
+        archive.extend_from_slice(&[0; 2 * TAR_BLOCK_SIZE as usize]);
+        archive
+    }
+
+    /// Gzip data in a single stored DEFLATE block, with a trailer that is not checked
+    pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut gzip = vec![0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF, 1];
+        gzip.extend_from_slice(&len.to_le_bytes());
+        gzip.extend_from_slice(&(!len).to_le_bytes());
+        gzip.extend_from_slice(data);
+        gzip.extend_from_slice(&[0; 8]);
+        gzip
+    }
+
+    /// Read every file of an archive with its content
+    fn entries(path: &Path) -> io::Result<Vec<(ArchiveEntry, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        for_each_entry(path, |entry, contents| {
+            let mut data = Vec::new();
+            contents.read_to_end(&mut data)?;
+            entries.push((entry.clone(), data));
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    #[test]
+    fn recognizes_archives_by_extension() {
+        assert!(is_archive(Path::new("takeout-001.zip")));
+        assert!(is_archive(Path::new("takeout-001.TGZ")));
+        assert!(is_archive(Path::new("takeout-001.tar.gz")));
+        assert!(!is_archive(Path::new("takeout-001.tar")));
+    }
+
+    #[test]
+    fn reads_stored_and_deflated_zip_entries() {
+        let path = archive_path("entries.zip");
+        std::fs::write(&path, zip_archive(&[DIRECTORY, METADATA, PHOTO])).unwrap();
+        let entries = entries(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let names: Vec<_> = entries.iter().map(|(entry, _)| entry.name.as_str()).collect();
+        assert_eq!(names, [METADATA.name, PHOTO.name]);
+        assert_eq!(entries[0].1, METADATA.data);
+        assert_eq!(entries[1].1, b"abcabcabcabcabc hello hello");
+        assert_eq!(entries[1].0.size, 27);
+        assert_eq!(entries[1].0.modified, Some(Utc.with_ymd_and_hms(2019, 5, 6, 7, 8, 8).unwrap()));
+    }
+
+    #[test]
+    fn fails_on_corrupt_zip_entries() {
+        let path = archive_path("corrupt.zip");
+        let corrupt = TestEntry { crc: PHOTO.crc ^ 1, ..PHOTO };
+        std::fs::write(&path, zip_archive(&[METADATA, corrupt])).unwrap();
+        let mut read = Vec::new();
+        let result = for_each_entry(&path, |entry, contents| {
+            read.push((entry.name.clone(), std::io::copy(contents, &mut std::io::sink()).is_ok()));
+            Ok(())
+        });
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(read, [(METADATA.name.to_string(), true), (PHOTO.name.to_string(), false)]);
+    }
+
+    #[test]
+    fn fails_on_truncated_zip_files() {
+        let path = archive_path("truncated.zip");
+        let archive = zip_archive(&[METADATA]);
+        std::fs::write(&path, &archive[..archive.len() - 10]).unwrap();
+        let read = entries(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(read.is_err());
+    }
+
+    #[test]
+    fn reads_gzipped_tar_entries() {
+        let long_name = format!("Takeout/Google Photos/{}/IMG_0002.jpg", "Album".repeat(30));
+        let archive = tar_archive(&[
+            ("Takeout/Google Photos/", b'5', b""),
+            ("Takeout/Google Photos/IMG_0001.jpg", b'0', b"first photo"),
+            ("././@LongLink", b'L', long_name.as_bytes()),
+            ("Takeout/Google Photos/truncated", b'0', b"second photo"),
+        ]);
+        let path = archive_path("entries.tgz");
+        std::fs::write(&path, gzip(&archive)).unwrap();
+        let entries = entries(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0.name, "Takeout/Google Photos/IMG_0001.jpg");
+        assert_eq!(entries[0].1, b"first photo");
+        assert_eq!(entries[0].0.modified, DateTime::from_timestamp(1_557_126_489, 0));
+        assert_eq!(entries[1].0.name, long_name);
+        assert_eq!(entries[1].1, b"second photo");
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use filetime::FileTime;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ProcessError;
+use crate::PhotoMetadata;
+
+/// The version of the cache format, bumped whenever it or the way metadata files are parsed changes
+const CACHE_VERSION: u32 = 7;
+
+/// The size and modification time of a metadata file, used to tell whether it changed since it was cached
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    size: u64,
+    modified_seconds: i64,
+    modified_nanos: u32,
+}
+
+impl Fingerprint {
+    /// Read the fingerprint of a file, or None if its metadata can't be read
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = FileTime::from_last_modification_time(&metadata);
+        Some(Fingerprint {
+            size: metadata.len(),
+            modified_seconds: modified.unix_seconds(),
+            modified_nanos: modified.nanoseconds(),
+        })
+    }
+}
+
+/// What a metadata file was parsed into, along with its fingerprint at the time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub fingerprint: Fingerprint,
+    /// The name of the photo the file describes and its metadata, or None if it had no usable date
+    pub photo: Option<(String, PhotoMetadata)>,
+}
+
+/// The parsed metadata files of an input directory, saved between runs so that only the files that
+/// changed are parsed again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MetadataCache {
+    version: u32,
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+impl Default for MetadataCache {
+    fn default() -> Self {
+        MetadataCache { version: CACHE_VERSION, entries: HashMap::new() }
+    }
+}
+
+impl MetadataCache {
+    /// Load a cache written by a previous run
+    /// A missing, unreadable or outdated cache is replaced by an empty one, so every file is parsed
+    pub fn load(path: &Path) -> Self {
+        if !path.exists() {
+            info!("No metadata cache at {:?} yet, parsing every metadata file", path);
+            return MetadataCache::default();
+        }
+        let cache = File::open(path)
+            .map_err(ProcessError::from)
+            .and_then(|file| Ok(serde_json::from_reader::<_, MetadataCache>(BufReader::new(file))?));
+        match cache {
+            Ok(cache) if cache.version == CACHE_VERSION => cache,
+            Ok(cache) => {
+                warn!("Ignoring the metadata cache {:?} of version {}, expected {}", path, cache.version, CACHE_VERSION);
+                MetadataCache::default()
+            }
+            Err(e) => {
+                warn!("Ignoring the metadata cache {:?}, it could not be read: {}", path, e);
+                MetadataCache::default()
+            }
+        }
+    }
+
+    /// Get the cached entry of a metadata file if it has not changed since it was cached
+    pub fn get(&self, path: &Path, fingerprint: Fingerprint) -> Option<&CacheEntry> {
+        self.entries.get(path).filter(|entry| entry.fingerprint == fingerprint)
+    }
+
+    /// Replace the entries of the cache, dropping those of the files that no longer exist
+    pub fn replace(&mut self, entries: HashMap<PathBuf, CacheEntry>) {
+        self.entries = entries;
+    }
+
+    /// Write the cache to a JSON file
+    pub fn write(&self, path: &Path) -> Result<(), ProcessError> {
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
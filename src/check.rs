@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use log::*;
+use walkdir::WalkDir;
+
+use crate::{lock, rewrites, same_content, PEOPLE_DIRECTORY, STAGING_DIRECTORY};
+
+/// The check being run, if any
+static ACTIVE: Mutex<Option<Check>> = Mutex::new(None);
+
+/// A comparison of where the files of the input are in the output with where they would be put
+struct Check {
+    /// The files of the output by name
+    files: HashMap<OsString, Vec<PathBuf>>,
+    in_place: usize,
+    misplaced: usize,
+    missing: usize,
+}
+
+/// Start checking the files of the input against the output, instead of organizing them
+/// The output is listed once, leaving out the folders of each person which only hold extra links
+/// and the list of rewritten files.
+pub fn start(output: &Path) {
+    let mut files: HashMap<OsString, Vec<PathBuf>> = HashMap::new();
+    let walk = WalkDir::new(output)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() != 1 || ![PEOPLE_DIRECTORY, STAGING_DIRECTORY, rewrites::INDEX_NAME].iter().any(|name| entry.file_name() == *name))
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.file_type().is_dir());
+    for entry in walk {
+        files.entry(entry.file_name().to_os_string()).or_default().push(entry.into_path());
+    }
+    *lock(&ACTIVE) = Some(Check { files, in_place: 0, misplaced: 0, missing: 0 });
+}
+
+/// Whether a check is being run
+pub fn is_active() -> bool {
+    lock(&ACTIVE).is_some()
+}
+
+/// Compare where a file is in the output with where it would be put
+/// The copies of the file are the output files with the name it would get and the same content.
+pub fn compare(photo_path: &Path, desired_path: &Path) {
+    let Some(name) = desired_path.file_name() else {
+        return;
+    };
+    let candidates = lock(&ACTIVE).as_ref()
+        .and_then(|check| check.files.get(name).cloned())
+        .unwrap_or_default();
+    let copies: Vec<PathBuf> = candidates.into_iter()
+        .filter(|candidate| same_content(photo_path, candidate).unwrap_or(false))
+        .collect();
+
+    let mut active = lock(&ACTIVE);
+    let Some(check) = active.as_mut() else {
+        return;
+    };
+    if copies.iter().any(|copy| copy == desired_path) {
+        debug!("{:?} is in place at {:?}", photo_path, desired_path);
+        check.in_place += 1;
+    } else if let Some(copy) = copies.first() {
+        warn!("{:?} is at {:?} but would be put at {:?}", photo_path, copy, desired_path);
+        check.misplaced += 1;
+    } else {
+        info!("{:?} is not in the output, it would be put at {:?}", photo_path, desired_path);
+        check.missing += 1;
+    }
+}
+
+/// Stop the check, returning a one-line summary of it if one was run
+pub fn finish() -> Option<String> {
+    let check = lock(&ACTIVE).take()?;
+    Some(format!(
+        "Check: {} files in place, {} in another folder than they would be put in, {} not in the output",
+        check.in_place, check.misplaced, check.missing,
+    ))
+}
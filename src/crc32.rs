@@ -0,0 +1,57 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The reversed polynomial of the CRC-32 used by zip, gzip and PNG
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// The CRC of each byte value, to process a byte at a time
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+};
+
+/// A CRC-32 computed over data given in parts
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn value(self) -> u32 {
+        !self.0
+    }
+}
+
+/// Compute the CRC-32 of the content of a file
+pub fn of_file(path: &Path) -> io::Result<u32> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut crc = Crc32::new();
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(crc.value());
+        }
+        crc.update(&buffer[..read]);
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+use crate::{local_time_to_utc, nfc};
+use crate::zone::Zone;
+
+/// The formats of the dates of a map file without an offset, tried in order
+const DATE_TIME_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"];
+
+/// Remove the quotes around a CSV field, if any
+fn unquote(field: &str) -> String {
+    let field = field.trim();
+    match field.strip_prefix('"').and_then(|field| field.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\"\"", "\""),
+        None => field.to_string(),
+    }
+}
+
+/// Parse the date of a row, as a day, a date and time, or an RFC 3339 date with an offset
+/// Dates without an offset are in the given time zone, or in UTC without one.
+fn parse_date(date: &str, time_zone: Option<&Zone>, path: &Path) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Some(date.with_timezone(&Utc));
+    }
+    let local_time = DATE_TIME_FORMATS.iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))?;
+    match time_zone {
+        Some(zone) => local_time_to_utc(zone, local_time, path),
+        None => local_time_to_utc(&Utc, local_time, path),
+    }
+}
+
+/// Read a CSV file of `filename,date` rows giving the dates of files by name
+/// The names are matched regardless of their Unicode normalization. A first row whose date can't be
+/// read is taken for a header, and any other is an error.
+pub fn load(path: &Path, time_zone: Option<&Zone>) -> Result<HashMap<String, DateTime<Utc>>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+    let mut dates = HashMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        // The name may hold commas, but the date can't
+        let Some((name, date)) = line.rsplit_once(',') else {
+            return Err(format!("line {} of {:?} is not filename,date", index + 1, path));
+        };
+        let (name, date) = (unquote(name), unquote(date));
+        let Some(date) = parse_date(&date, time_zone, path) else {
+            if index == 0 {
+                continue;
+            }
+            return Err(format!("line {} of {:?} has an invalid date {}", index + 1, path, date));
+        };
+        dates.insert(nfc::nfc(&name).into_owned(), date);
+    }
+    Ok(dates)
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// The ways processing a file can fail
+#[derive(Debug)]
+pub enum ProcessError {
+    /// Reading, writing or moving a file failed
+    Io(std::io::Error),
+    /// The EXIF data, or the JPEG or HEIF structure holding it, is invalid
+    ExifParse(String),
+    /// The atoms of a QuickTime or MP4 video are invalid
+    VideoParse(String),
+    /// A JSON file could not be read or written
+    JsonParse(serde_json::Error),
+    /// A date is out of range or could not be converted
+    TimestampParse(String),
+    /// An output path is already taken
+    PathConflict(PathBuf),
+    /// The modification time of an output file is not the one that was set
+    Verification(String),
+    /// The copy of a file doesn't have the same checksum as the file, with `--checksum`
+    Checksum(String),
+    /// Processing the file panicked, which is a bug
+    Panic(String),
+}
+
+impl ProcessError {
+    /// A short name of the kind of error, as used in the report
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProcessError::Io(_) => "io",
+            ProcessError::ExifParse(_) => "exif_parse",
+            ProcessError::VideoParse(_) => "video_parse",
+            ProcessError::JsonParse(_) => "json_parse",
+            ProcessError::TimestampParse(_) => "timestamp_parse",
+            ProcessError::PathConflict(_) => "path_conflict",
+            ProcessError::Verification(_) => "verification",
+            ProcessError::Checksum(_) => "checksum",
+            ProcessError::Panic(_) => "panic",
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::Io(e) => write!(f, "{}", e),
+            ProcessError::ExifParse(message) => write!(f, "Invalid EXIF data: {}", message),
+            ProcessError::VideoParse(message) => write!(f, "Invalid video: {}", message),
+            ProcessError::JsonParse(e) => write!(f, "Invalid JSON: {}", e),
+            ProcessError::TimestampParse(message) => write!(f, "Invalid timestamp: {}", message),
+            ProcessError::PathConflict(path) => write!(f, "{:?} already exists", path),
+            ProcessError::Verification(message) => write!(f, "Verification failed: {}", message),
+            ProcessError::Checksum(message) => write!(f, "Checksum mismatch: {}", message),
+            ProcessError::Panic(message) => write!(f, "Unexpected failure: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProcessError::Io(e) => Some(e),
+            ProcessError::JsonParse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ProcessError {
+    fn from(e: std::io::Error) -> Self {
+        // A checksum mismatch is reported through the I/O errors of the copy
+        if let Some(ProcessError::Checksum(message)) = e.get_ref().and_then(|inner| inner.downcast_ref::<ProcessError>()) {
+            return ProcessError::Checksum(message.clone());
+        }
+        ProcessError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ProcessError {
+    fn from(e: serde_json::Error) -> Self {
+        ProcessError::JsonParse(e)
+    }
+}
+
+impl From<exif::Error> for ProcessError {
+    fn from(e: exif::Error) -> Self {
+        match e {
+            exif::Error::Io(e) => ProcessError::Io(e),
+            e => ProcessError::ExifParse(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::json;
+
+use crate::error::ProcessError;
+use crate::{lock, manifest, DateSource};
+
+/// Where the events are written, if anywhere
+static ACTIVE: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+/// Start writing an event for each file as it is processed, appending to a file or to stdout if
+/// `path` is None
+pub fn start(path: Option<&Path>) -> std::io::Result<()> {
+    let writer: Box<dyn Write + Send> = match path {
+        Some(path) => Box::new(BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?)),
+        None => Box::new(std::io::stdout()),
+    };
+    manifest::track_destinations();
+    *lock(&ACTIVE) = Some(writer);
+    Ok(())
+}
+
+/// Write the event of a file once it has been processed, as a line of JSON
+/// The action is what happened to it, as in the manifest.
+pub fn emit(
+    photo_path: &Path,
+    destination: Option<&Path>,
+    taken_time: Option<DateTime<Utc>>,
+    source: Option<DateSource>,
+    action: &str,
+    error: Option<&ProcessError>,
+) -> std::io::Result<()> {
+    let mut active = lock(&ACTIVE);
+    let Some(writer) = active.as_mut() else {
+        return Ok(());
+    };
+    let event = json!({
+        "src": photo_path.to_string_lossy(),
+        "dst": destination.map(|destination| destination.to_string_lossy()),
+        "date": taken_time.map(|time| time.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        "source": source.map(DateSource::name),
+        "action": action,
+        "error": error.map(|error| error.to_string()),
+    });
+    writeln!(writer, "{}", event)?;
+    // Each event is flushed so that it can be followed live
+    writer.flush()
+}
+
+/// Flush the events and stop writing them
+pub fn finish() -> std::io::Result<()> {
+    match lock(&ACTIVE).take() {
+        Some(mut writer) => writer.flush(),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use exif::experimental::Writer;
+use exif::{Field, In, Tag};
+
+use crate::error::ProcessError;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// Maximum payload of a JPEG segment, excluding the two length bytes
+const MAX_SEGMENT_LEN: usize = 65533;
+
+/// An error for a JPEG file that can't be handled
+fn invalid(message: impl Into<String>) -> ProcessError {
+    ProcessError::ExifParse(message.into())
+}
+
+/// A marker segment of a JPEG file, as a range of bytes including the marker itself
+struct Segment {
+    marker: u8,
+    start: usize,
+    end: usize,
+}
+
+/// Check whether the data starts with the JPEG start-of-image marker
+pub fn is_jpeg(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0xD8])
+}
+
+/// Split the header of a JPEG file into its marker segments, up to the start of the scan data
+fn read_segments(data: &[u8]) -> Result<Vec<Segment>, ProcessError> {
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        if pos + 4 > data.len() || data[pos] != 0xFF {
+            return Err(invalid("Malformed JPEG marker segment"));
+        }
+        let marker = data[pos + 1];
+        // Fill bytes may precede any marker
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        // The start-of-scan segment is followed by the entropy-coded data
+        if marker == 0xDA {
+            return Ok(segments);
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > data.len() {
+            return Err(invalid("Truncated JPEG marker segment"));
+        }
+        segments.push(Segment { marker, start: pos, end });
+        pos = end;
+    }
+}
+
+/// Check whether a segment is the APP1 segment holding the EXIF data
+fn is_exif_segment(data: &[u8], segment: &Segment) -> bool {
+    segment.marker == 0xE1 && data[segment.start + 4..segment.end].starts_with(EXIF_HEADER)
+}
+
+/// Insert the given fields into the EXIF data of a JPEG file, keeping every field it already has
+/// Nothing is written if the file already has any of the key tags, such as the coordinates of a
+/// location, since the fields would describe another value than its own. Otherwise only the fields
+/// it doesn't have are added, so existing values are never overwritten. Returns whether the file
+/// was changed.
+pub fn insert_fields(jpeg_path: &Path, key_tags: &[Tag], fields: &[Field]) -> Result<bool, ProcessError> {
+    let data = fs::read(jpeg_path)?;
+    if !is_jpeg(&data) {
+        return Err(invalid("Not a JPEG file"));
+    }
+    let segments = read_segments(&data)?;
+    let exif_segment = segments.iter().find(|segment| is_exif_segment(&data, segment));
+
+    let existing = match exif_segment {
+        Some(segment) => {
+            let tiff = data[segment.start + 4 + EXIF_HEADER.len()..segment.end].to_vec();
+            Some(exif::Reader::new().read_raw(tiff)?)
+        }
+        None => None,
+    };
+
+    let existing_tags: HashSet<Tag> = existing.iter()
+        .flat_map(|existing| existing.fields())
+        .filter(|field| field.ifd_num == In::PRIMARY)
+        .map(|field| field.tag)
+        .collect();
+    let missing: Vec<&Field> = fields.iter().filter(|field| !existing_tags.contains(&field.tag)).collect();
+    if missing.is_empty() || key_tags.iter().any(|tag| existing_tags.contains(tag)) {
+        return Ok(false);
+    }
+
+    let mut writer = Writer::new();
+    let mut thumbnail = None;
+    if let Some(existing) = &existing {
+        // Only a JPEG thumbnail can be carried over, strip-based ones are dropped
+        if let Some(offset) = existing.get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)
+            .and_then(|field| field.value.get_uint(0))
+            && let Some(len) = existing.get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)
+                .and_then(|field| field.value.get_uint(0))
+        {
+            thumbnail = existing.buf().get(offset as usize..(offset + len) as usize);
+        }
+        for field in existing.fields() {
+            if field.ifd_num == In::PRIMARY || (field.ifd_num == In::THUMBNAIL && thumbnail.is_some()) {
+                writer.push_field(field);
+            }
+        }
+        if let Some(thumbnail) = thumbnail {
+            writer.set_jpeg(thumbnail, In::THUMBNAIL);
+        }
+    }
+    for field in missing {
+        writer.push_field(field);
+    }
+
+    let little_endian = existing.as_ref().is_some_and(|existing| existing.little_endian());
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, little_endian)?;
+    let tiff = tiff.into_inner();
+
+    let payload_len = EXIF_HEADER.len() + tiff.len();
+    if payload_len > MAX_SEGMENT_LEN {
+        return Err(invalid("EXIF data does not fit in a JPEG segment"));
+    }
+    let mut app1 = Vec::with_capacity(payload_len + 4);
+    app1.extend_from_slice(&[0xFF, 0xE1]);
+    app1.extend_from_slice(&((payload_len + 2) as u16).to_be_bytes());
+    app1.extend_from_slice(EXIF_HEADER);
+    app1.extend_from_slice(&tiff);
+
+    let mut output = Vec::with_capacity(data.len() + app1.len());
+    match exif_segment {
+        // Replace the existing segment in place
+        Some(segment) => {
+            output.extend_from_slice(&data[..segment.start]);
+            output.extend_from_slice(&app1);
+            output.extend_from_slice(&data[segment.end..]);
+        }
+        // Otherwise place it right after the SOI marker, or after the JFIF APP0 segment if any
+        None => {
+            let insert_at = match segments.first() {
+                Some(segment) if segment.marker == 0xE0 => segment.end,
+                _ => 2,
+            };
+            output.extend_from_slice(&data[..insert_at]);
+            output.extend_from_slice(&app1);
+            output.extend_from_slice(&data[insert_at..]);
+        }
+    }
+
+    fs::write(jpeg_path, output)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::{Rational, Value};
+
+    /// The smallest file read as a JPEG, with no segment before its empty scan
+    const EMPTY_JPEG: &[u8] = &[0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9];
+
+    fn field(tag: Tag, value: u32) -> Field {
+        Field { tag, ifd_num: In::PRIMARY, value: Value::Rational(vec![Rational { num: value, denom: 1 }]) }
+    }
+
+    fn value(path: &Path, tag: Tag) -> Option<u32> {
+        let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(fs::File::open(path).unwrap())).unwrap();
+        exif.get_field(tag, In::PRIMARY).and_then(|field| match &field.value {
+            Value::Rational(values) => Some(values[0].num),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn adds_only_the_missing_fields() {
+        let path = std::env::temp_dir().join(format!("takeout-exif-fix-{}-missing.jpg", std::process::id()));
+        fs::write(&path, EMPTY_JPEG).unwrap();
+        let key_tags = [Tag::GPSLatitude, Tag::GPSLongitude];
+
+        assert!(insert_fields(&path, &[], &[field(Tag::GPSAltitude, 10)]).unwrap());
+        assert!(insert_fields(&path, &key_tags, &[field(Tag::GPSLatitude, 45), field(Tag::GPSAltitude, 20)]).unwrap());
+        assert_eq!(value(&path, Tag::GPSLatitude), Some(45));
+        assert_eq!(value(&path, Tag::GPSAltitude), Some(10));
+        // A key tag keeps the whole set of fields out
+        assert!(!insert_fields(&path, &key_tags, &[field(Tag::GPSLongitude, 7)]).unwrap());
+        assert_eq!(value(&path, Tag::GPSLongitude), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
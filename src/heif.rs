@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::ProcessError;
+
+/// The extensions of the HEIF images whose EXIF data can be extracted
+/// AVIF images are HEIF files with AV1 coding, and store their EXIF data the same way.
+const HEIF_EXTENSIONS: &[&str] = &["heic", "heif", "hif", "avif"];
+
+/// An error for a HEIF file whose EXIF data can't be found
+fn invalid(message: impl Into<String>) -> ProcessError {
+    ProcessError::ExifParse(message.into())
+}
+
+/// A box of a HEIF file, as a type and the range of bytes of its content
+struct HeifBox {
+    kind: [u8; 4],
+    start: usize,
+    end: usize,
+}
+
+/// A reader over the content of a box
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn read(&mut self, len: usize) -> Result<&[u8], ProcessError> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or_else(|| invalid("Truncated HEIF box"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Read a big-endian integer of 0, 1, 2, 4 or 8 bytes
+    fn read_uint(&mut self, len: usize) -> Result<u64, ProcessError> {
+        Ok(self.read(len)?.iter().fold(0, |value, &byte| (value << 8) | byte as u64))
+    }
+}
+
+/// Check whether a file is a HEIF image, ignoring the case of the extension
+pub fn is_heif_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| HEIF_EXTENSIONS.iter().any(|heif| ext.eq_ignore_ascii_case(heif)))
+}
+
+/// Extract the EXIF data of a HEIF image, as the TIFF structure expected by `exif::Reader::read_raw`
+/// The `Exif` item is looked up in the `iinf` box and its bytes are located with the `iloc` box
+pub fn read_exif(path: &Path) -> Result<Vec<u8>, ProcessError> {
+    let data = fs::read(path)?;
+    let boxes = read_boxes(&data, 0, data.len())?;
+    let meta = boxes.iter().find(|heif_box| &heif_box.kind == b"meta").ok_or_else(|| invalid("No meta box found"))?;
+    // Skip the version and flags
+    let children = read_boxes(&data, meta.start + 4, meta.end)?;
+    let iinf = children.iter().find(|heif_box| &heif_box.kind == b"iinf").ok_or_else(|| invalid("No iinf box found"))?;
+    let iloc = children.iter().find(|heif_box| &heif_box.kind == b"iloc").ok_or_else(|| invalid("No iloc box found"))?;
+
+    let item_id = exif_item_id(&data, iinf)?;
+    let item = item_data(&data, iloc, item_id)?;
+
+    // The item starts with the offset of the TIFF header, after the `Exif\0\0` prefix
+    let mut cursor = Cursor { data: &item, pos: 0 };
+    let tiff_offset = cursor.read_uint(4)? as usize;
+    let tiff = item.get(4 + tiff_offset..).ok_or_else(|| invalid("Truncated EXIF item"))?;
+    Ok(tiff.to_vec())
+}
+
+/// Split a range of bytes into the boxes it contains
+fn read_boxes(data: &[u8], start: usize, end: usize) -> Result<Vec<HeifBox>, ProcessError> {
+    let mut boxes = Vec::new();
+    let mut cursor = Cursor { data: &data[..end], pos: start };
+    while cursor.pos + 8 <= end {
+        let box_start = cursor.pos;
+        let mut len = cursor.read_uint(4)? as usize;
+        let mut kind = [0; 4];
+        kind.copy_from_slice(cursor.read(4)?);
+        if len == 1 {
+            len = usize::try_from(cursor.read_uint(8)?).map_err(|_| invalid("HEIF box too large"))?;
+        } else if len == 0 {
+            len = end - box_start;
+        }
+        let header_len = cursor.pos - box_start;
+        if len < header_len || box_start + len > end {
+            return Err(invalid("Malformed HEIF box"));
+        }
+        boxes.push(HeifBox { kind, start: cursor.pos, end: box_start + len });
+        cursor.pos = box_start + len;
+    }
+    Ok(boxes)
+}
+
+/// Find the ID of the `Exif` item in the `iinf` box
+fn exif_item_id(data: &[u8], iinf: &HeifBox) -> Result<u32, ProcessError> {
+    let version = *data.get(iinf.start).ok_or_else(|| invalid("Truncated iinf box"))?;
+    let entry_count_len = if version == 0 { 2 } else { 4 };
+    let entries = read_boxes(data, iinf.start + 4 + entry_count_len, iinf.end)?;
+    for infe in entries.iter().filter(|heif_box| &heif_box.kind == b"infe") {
+        let mut cursor = Cursor { data: &data[..infe.end], pos: infe.start };
+        let version = cursor.read(4)?[0];
+        // Older versions of the box don't have an item type
+        if version < 2 {
+            continue;
+        }
+        let item_id = cursor.read_uint(if version == 2 { 2 } else { 4 })? as u32;
+        let _protection_index = cursor.read_uint(2)?;
+        if cursor.read(4)? == b"Exif" {
+            return Ok(item_id);
+        }
+    }
+    Err(invalid("No Exif item found"))
+}
+
+/// Read the bytes of an item, concatenating its extents as listed in the `iloc` box
+/// Only items stored in the file itself are supported
+fn item_data(data: &[u8], iloc: &HeifBox, item_id: u32) -> Result<Vec<u8>, ProcessError> {
+    let mut cursor = Cursor { data: &data[..iloc.end], pos: iloc.start };
+    let version = cursor.read(4)?[0];
+    let sizes = cursor.read_uint(2)?;
+    let offset_size = (sizes >> 12) as usize;
+    let length_size = (sizes >> 8 & 0xF) as usize;
+    let base_offset_size = (sizes >> 4 & 0xF) as usize;
+    let index_size = if version == 0 { 0 } else { (sizes & 0xF) as usize };
+    let id_size = if version < 2 { 2 } else { 4 };
+    let item_count = cursor.read_uint(id_size)?;
+
+    for _ in 0..item_count {
+        let id = cursor.read_uint(id_size)? as u32;
+        let construction_method = if version == 0 { 0 } else { cursor.read_uint(2)? & 0xF };
+        let _data_reference_index = cursor.read_uint(2)?;
+        let base_offset = cursor.read_uint(base_offset_size)?;
+        let extent_count = cursor.read_uint(2)?;
+        if id == item_id && construction_method != 0 {
+            return Err(invalid(format!("Unsupported construction method {} for the Exif item", construction_method)));
+        }
+        let mut item = Vec::new();
+        for _ in 0..extent_count {
+            let _extent_index = cursor.read_uint(index_size)?;
+            let offset = usize::try_from(base_offset + cursor.read_uint(offset_size)?).map_err(|_| invalid("Exif item offset too large"))?;
+            let length = usize::try_from(cursor.read_uint(length_size)?).map_err(|_| invalid("Exif item too large"))?;
+            if id == item_id {
+                // A length of 0 means the extent goes to the end of the file
+                let end = if length == 0 { data.len() } else { offset + length };
+                item.extend_from_slice(data.get(offset..end).ok_or_else(|| invalid("EXIF item extends past the end of the file"))?);
+            }
+        }
+        if id == item_id {
+            return Ok(item);
+        }
+    }
+    Err(invalid("The Exif item has no location"))
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::path::{Component, Path};
+
+/// The name of the file at the root of an input listing the paths to leave alone
+pub const IGNORE_FILE_NAME: &str = ".organizeignore";
+
+/// A line of an ignore file
+#[derive(Debug, Clone)]
+struct Rule {
+    /// The components of the pattern, where `**` matches any number of components
+    components: Vec<String>,
+    /// Whether the pattern started with `!`, bringing back what an earlier pattern ignored
+    negated: bool,
+    /// Whether the pattern ended with `/`, only matching directories
+    directory_only: bool,
+}
+
+/// Paths to leave out of a run, written like a `.gitignore` file
+///
+/// Each line is a pattern with the wildcards `*`, `?`, `[...]` and `**`. A pattern without a `/`
+/// matches a name at any depth, otherwise it is relative to the input directory. A pattern ending
+/// with `/` only matches directories, and one starting with `!` brings back what an earlier one
+/// ignored. Lines starting with `#` are comments.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Read the rules of an ignore file
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parse the rules of an ignore file, skipping blank lines and comments
+    pub fn parse(text: &str) -> Self {
+        let rules = text.lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let directory_only = pattern.ends_with('/');
+                let pattern = pattern.trim_end_matches('/');
+                // A pattern with a slash before its end is relative to the root, and one without
+                // matches at any depth
+                let anchored = pattern.contains('/');
+                let mut components: Vec<String> = pattern.trim_start_matches('/')
+                    .split('/')
+                    .filter(|component| !component.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if !anchored {
+                    components.insert(0, "**".to_string());
+                }
+                Rule { components, negated, directory_only }
+            })
+            .filter(|rule| rule.components.iter().any(|component| component != "**"))
+            .collect();
+        IgnoreRules { rules }
+    }
+
+    /// Whether a path relative to the input directory is ignored, the last matching rule winning
+    pub fn is_ignored(&self, relative_path: &Path, is_dir: bool) -> bool {
+        let components: Vec<String> = relative_path.components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+        if components.is_empty() {
+            return false;
+        }
+        self.rules.iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.directory_only) && matches_components(&rule.components, &components))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// Match the components of a path against the components of a pattern
+fn matches_components(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => (0..=path.len()).any(|skipped| matches_components(rest, &path[skipped..])),
+        Some((first, rest)) => path.split_first()
+            .is_some_and(|(name, path_rest)| matches_name(first.as_bytes(), name.as_bytes()) && matches_components(rest, path_rest)),
+    }
+}
+
+/// Match a name against a pattern with the wildcards `*`, `?` and `[...]`
+fn matches_name(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skipped| matches_name(rest, &name[skipped..])),
+        Some((b'?', rest)) => !name.is_empty() && matches_name(rest, &name[1..]),
+        Some((b'[', rest)) if let Some(end) = rest.iter().skip(1).position(|&byte| byte == b']').map(|end| end + 1) => {
+            let Some((&byte, name_rest)) = name.split_first() else {
+                return false;
+            };
+            let (negated, class) = match rest[..end].split_first() {
+                Some((b'!' | b'^', class)) => (true, class),
+                _ => (false, &rest[..end]),
+            };
+            in_class(class, byte) != negated && matches_name(&rest[end + 1..], name_rest)
+        }
+        Some((b'\\', rest)) if let Some((&escaped, rest)) = rest.split_first() => {
+            name.first() == Some(&escaped) && matches_name(rest, &name[1..])
+        }
+        Some((&literal, rest)) => name.first() == Some(&literal) && matches_name(rest, &name[1..]),
+    }
+}
+
+/// Check whether a byte is in a character class such as `a-z0-9_`
+fn in_class(class: &[u8], byte: u8) -> bool {
+    let mut index = 0;
+    while index < class.len() {
+        if index + 2 < class.len() && class[index + 1] == b'-' {
+            if (class[index]..=class[index + 2]).contains(&byte) {
+                return true;
+            }
+            index += 3;
+        } else {
+            if class[index] == byte {
+                return true;
+            }
+            index += 1;
+        }
+    }
+    false
+}
@@ -0,0 +1,392 @@
+use std::io::{self, Read};
+
+/// The size of the history that back-references can reach into
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/// The most bytes decoded at once before they are handed to the reader
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The base lengths of the length symbols 257 to 285, and their number of extra bits
+const LENGTH_BASES: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA_BITS: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// The base distances of the distance symbols 0 to 29, and their number of extra bits
+const DISTANCE_BASES: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// The order in which the lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// An error for a DEFLATE stream that can't be decoded
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid DEFLATE data: {}", message))
+}
+
+/// A reader of the bits of a stream, least significant bit first
+struct BitReader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    pos: usize,
+    len: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    /// Fill the bit buffer from the input, as far as it goes
+    fn refill(&mut self) -> io::Result<()> {
+        while self.count <= 56 {
+            if self.pos == self.len {
+                self.len = self.inner.read(&mut self.buffer)?;
+                self.pos = 0;
+                if self.len == 0 {
+                    break;
+                }
+            }
+            self.bits |= (self.buffer[self.pos] as u64) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        Ok(())
+    }
+
+    /// Look at the next bits without consuming them, padded with zeros at the end of the input
+    fn peek(&mut self, n: u32) -> io::Result<u32> {
+        if self.count < n {
+            self.refill()?;
+        }
+        Ok((self.bits & ((1 << n) - 1)) as u32)
+    }
+
+    fn consume(&mut self, n: u32) -> io::Result<()> {
+        if n > self.count {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated DEFLATE data"));
+        }
+        self.bits >>= n;
+        self.count -= n;
+        Ok(())
+    }
+
+    fn read_bits(&mut self, n: u32) -> io::Result<u32> {
+        let value = self.peek(n)?;
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary, as stored blocks start on one
+    fn align(&mut self) {
+        let skipped = self.count % 8;
+        self.bits >>= skipped;
+        self.count -= skipped;
+    }
+
+    /// Append the next `n` bytes of a stored block to `output`, which must be on a byte boundary
+    fn read_bytes(&mut self, output: &mut Vec<u8>, mut n: usize) -> io::Result<()> {
+        while n > 0 && self.count >= 8 {
+            output.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+            n -= 1;
+        }
+        while n > 0 {
+            if self.pos == self.len {
+                self.len = self.inner.read(&mut self.buffer)?;
+                self.pos = 0;
+                if self.len == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated DEFLATE data"));
+                }
+            }
+            let available = n.min(self.len - self.pos);
+            output.extend_from_slice(&self.buffer[self.pos..self.pos + available]);
+            self.pos += available;
+            n -= available;
+        }
+        Ok(())
+    }
+}
+
+/// A canonical Huffman code, decoded with a table indexed by the next `bits` bits of the input
+/// Each entry is the symbol shifted left by 4 bits, with the length of its code in the low bits.
+struct Huffman {
+    table: Vec<u16>,
+    bits: u32,
+}
+
+impl Huffman {
+    /// Build the code from the length of the code of each symbol, where 0 means the symbol is unused
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let bits = lengths.iter().copied().max().unwrap_or(0).max(1) as u32;
+        let mut counts = [0u32; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut next_code = [0u32; 16];
+        for len in 1..16 {
+            next_code[len] = (next_code[len - 1] + counts[len - 1]) << 1;
+        }
+
+        let mut table = vec![0; 1 << bits];
+        for (symbol, &len) in lengths.iter().enumerate().filter(|(_, len)| **len > 0) {
+            let len = len as u32;
+            let code = next_code[len as usize];
+            next_code[len as usize] += 1;
+            if code >= 1 << len {
+                return Err(invalid("over-subscribed Huffman code"));
+            }
+            // Codes are stored starting from their most significant bit
+            let reversed = code.reverse_bits() >> (32 - len);
+            let entry = (symbol as u16) << 4 | len as u16;
+            for index in (reversed as usize..table.len()).step_by(1 << len) {
+                table[index] = entry;
+            }
+        }
+        Ok(Huffman { table, bits })
+    }
+
+    fn decode<R: Read>(&self, input: &mut BitReader<R>) -> io::Result<u16> {
+        let entry = self.table[input.peek(self.bits)? as usize];
+        let len = (entry & 0xF) as u32;
+        if len == 0 {
+            return Err(invalid("unknown Huffman code"));
+        }
+        input.consume(len)?;
+        Ok(entry >> 4)
+    }
+}
+
+/// What the decoder is in the middle of
+enum Block {
+    /// The header of the next block comes next
+    Header,
+    /// A block stored without compression, with the number of bytes left in it
+    Stored(usize),
+    /// A compressed block, with its literal and length code and its distance code
+    Huffman(Huffman, Huffman),
+    Done,
+}
+
+/// A reader of the data compressed in a raw DEFLATE stream, as found in zip and gzip files
+pub struct Inflate<R> {
+    input: BitReader<R>,
+    /// The decoded bytes, of which the ones already read are kept as the history of back-references
+    output: Vec<u8>,
+    read_pos: usize,
+    block: Block,
+    last_block: bool,
+}
+
+impl<R: Read> Inflate<R> {
+    pub fn new(input: R) -> Self {
+        Inflate {
+            input: BitReader { inner: input, buffer: vec![0; 32 * 1024], pos: 0, len: 0, bits: 0, count: 0 },
+            output: Vec::new(),
+            read_pos: 0,
+            block: Block::Header,
+            last_block: false,
+        }
+    }
+
+    /// Decode the next part of the stream into the output
+    fn decode_some(&mut self) -> io::Result<()> {
+        // Only the history that back-references can reach is kept
+        if self.read_pos > 2 * WINDOW_SIZE {
+            let dropped = self.read_pos - WINDOW_SIZE;
+            self.output.drain(..dropped);
+            self.read_pos -= dropped;
+        }
+
+        match std::mem::replace(&mut self.block, Block::Done) {
+            Block::Header if self.last_block => {}
+            Block::Header => self.block = self.read_header()?,
+            Block::Stored(remaining) => {
+                let n = remaining.min(CHUNK_SIZE);
+                self.input.read_bytes(&mut self.output, n)?;
+                self.block = if remaining > n { Block::Stored(remaining - n) } else { Block::Header };
+            }
+            Block::Huffman(literals, distances) => {
+                let end_of_block = self.decode_symbols(&literals, &distances)?;
+                self.block = if end_of_block { Block::Header } else { Block::Huffman(literals, distances) };
+            }
+            Block::Done => {}
+        }
+        Ok(())
+    }
+
+    fn read_header(&mut self) -> io::Result<Block> {
+        self.last_block = self.input.read_bits(1)? == 1;
+        match self.input.read_bits(2)? {
+            0 => {
+                self.input.align();
+                let len = self.input.read_bits(16)?;
+                let complement = self.input.read_bits(16)?;
+                if len != !complement & 0xFFFF {
+                    return Err(invalid("corrupt stored block length"));
+                }
+                Ok(Block::Stored(len as usize))
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                Ok(Block::Huffman(Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+            }
+            2 => self.read_dynamic_codes(),
+            _ => Err(invalid("reserved block type")),
+        }
+    }
+
+    /// Read the codes of a block compressed with dynamic Huffman codes
+    fn read_dynamic_codes(&mut self) -> io::Result<Block> {
+        let literal_count = self.input.read_bits(5)? as usize + 257;
+        let distance_count = self.input.read_bits(5)? as usize + 1;
+        let code_length_count = self.input.read_bits(4)? as usize + 4;
+
+        let mut code_length_lengths = [0; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+            code_length_lengths[symbol] = self.input.read_bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_length_lengths)?;
+
+        let mut lengths = Vec::with_capacity(literal_count + distance_count);
+        while lengths.len() < literal_count + distance_count {
+            let (length, repeat) = match code_lengths.decode(&mut self.input)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 => {
+                    let previous = *lengths.last().ok_or_else(|| invalid("repeated length without a previous one"))?;
+                    (previous, 3 + self.input.read_bits(2)?)
+                }
+                17 => (0, 3 + self.input.read_bits(3)?),
+                _ => (0, 11 + self.input.read_bits(7)?),
+            };
+            lengths.extend(std::iter::repeat_n(length, repeat as usize));
+        }
+        if lengths.len() > literal_count + distance_count {
+            return Err(invalid("too many code lengths"));
+        }
+        if lengths[256] == 0 {
+            return Err(invalid("no end of block code"));
+        }
+        let literals = Huffman::new(&lengths[..literal_count])?;
+        let distances = Huffman::new(&lengths[literal_count..])?;
+        Ok(Block::Huffman(literals, distances))
+    }
+
+    /// Decode the symbols of a compressed block until a chunk is decoded or the block ends
+    /// Returns whether the end of the block was reached
+    fn decode_symbols(&mut self, literals: &Huffman, distances: &Huffman) -> io::Result<bool> {
+        let limit = self.output.len() + CHUNK_SIZE;
+        while self.output.len() < limit {
+            let symbol = literals.decode(&mut self.input)? as usize;
+            if symbol < 256 {
+                self.output.push(symbol as u8);
+                continue;
+            }
+            if symbol == 256 {
+                return Ok(true);
+            }
+            let index = symbol - 257;
+            if index >= LENGTH_BASES.len() {
+                return Err(invalid("unknown length symbol"));
+            }
+            let length = LENGTH_BASES[index] as usize + self.input.read_bits(LENGTH_EXTRA_BITS[index] as u32)? as usize;
+            let index = distances.decode(&mut self.input)? as usize;
+            if index >= DISTANCE_BASES.len() {
+                return Err(invalid("unknown distance symbol"));
+            }
+            let distance = DISTANCE_BASES[index] as usize + self.input.read_bits(DISTANCE_EXTRA_BITS[index] as u32)? as usize;
+            if distance > self.output.len() {
+                return Err(invalid("distance past the start of the data"));
+            }
+            // The copy may overlap the bytes it produces
+            let start = self.output.len() - distance;
+            for i in start..start + length {
+                self.output.push(self.output[i]);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<R: Read> Read for Inflate<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read_pos == self.output.len() && !matches!(self.block, Block::Done) {
+            self.decode_some()?;
+        }
+        let n = buf.len().min(self.output.len() - self.read_pos);
+        buf[..n].copy_from_slice(&self.output[self.read_pos..self.read_pos + n]);
+        self.read_pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Raw DEFLATE streams made by zlib, in each kind of block
+    const STORED: &[u8] = &[
+        0x01, 0x14, 0x00, 0xeb, 0xff, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64, 0x20,
+        0x62, 0x6c, 0x6f, 0x63, 0x6b, 0x21,
+    ];
+    const FIXED: &[u8] = &[0x4b, 0x4c, 0x4a, 0x4e, 0x44, 0x42, 0x0a, 0x19, 0xa9, 0x39, 0x39, 0xf9, 0x10, 0x12, 0x00];
+    const DYNAMIC: &[u8] = &[
+        0x85, 0xd3, 0x31, 0x0a, 0xc2, 0x40, 0x14, 0x84, 0xe1, 0xde, 0x53, 0xe4, 0x02, 0x91, 0x9d, 0x79,
+        0xd9, 0x64, 0xf7, 0x04, 0x92, 0xc2, 0x33, 0x88, 0x85, 0x08, 0x0a, 0xd1, 0xc2, 0xfb, 0xa3, 0xa0,
+        0xd3, 0x39, 0xbc, 0xa9, 0xff, 0xea, 0x83, 0x59, 0x8f, 0x87, 0x53, 0xf9, 0x6c, 0x7f, 0x7b, 0x5e,
+        0x87, 0xd7, 0xf9, 0x7e, 0xd9, 0x86, 0xc7, 0x36, 0xb0, 0xa0, 0x8f, 0xa5, 0x8e, 0x05, 0xbb, 0xf5,
+        0x1b, 0xc0, 0x04, 0x54, 0x40, 0x13, 0x84, 0x82, 0x30, 0xc1, 0xa4, 0x60, 0x32, 0x41, 0x55, 0x50,
+        0x4d, 0x30, 0x2b, 0x98, 0x4d, 0xb0, 0x28, 0x58, 0x4c, 0xd0, 0x14, 0x34, 0x13, 0x74, 0x05, 0xfd,
+        0x7f, 0x80, 0xf2, 0x0b, 0x60, 0x24, 0x21, 0x49, 0x18, 0x49, 0x48, 0x12, 0x46, 0x12, 0x92, 0x84,
+        0x91, 0x84, 0x24, 0x61, 0x24, 0x21, 0x49, 0x18, 0x49, 0x48, 0x12, 0x46, 0x12, 0x92, 0x84, 0x91,
+        0x84, 0x24, 0x61, 0x24, 0x21, 0x49, 0x18, 0x49, 0x4a, 0x92, 0x46, 0x92, 0x92, 0xa4, 0x91, 0xa4,
+        0x24, 0x69, 0x24, 0x29, 0x49, 0x1a, 0x49, 0x4a, 0x92, 0x46, 0x92, 0x92, 0xa4, 0x91, 0xa4, 0x24,
+        0x69, 0x24, 0x29, 0x49, 0x1a, 0x49, 0x4a, 0x92, 0x2d, 0xf9, 0x26, 0x7b, 0xf2, 0xcd, 0x28, 0xc9,
+        0x37, 0x03, 0xc9, 0x37, 0x83, 0xc9, 0x37, 0x23, 0x92, 0x6f, 0xc6, 0x94, 0x7c, 0x33, 0x6a, 0xf2,
+        0xcd, 0x98, 0x93, 0x6f, 0xc6, 0x92, 0x7c, 0x33, 0x5a, 0xf2, 0xcd, 0xe8, 0xf6, 0x9b, 0x6f,
+    ];
+
+    fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        Inflate::new(data).read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn inflates_stored_blocks() {
+        assert_eq!(inflate(STORED).unwrap(), b"Hello, stored block!");
+    }
+
+    #[test]
+    fn inflates_fixed_huffman_blocks() {
+        assert_eq!(inflate(FIXED).unwrap(), b"abcabcabcabcabc hello hello");
+    }
+
+    #[test]
+    fn inflates_dynamic_huffman_blocks() {
+        let expected: String = (0..40).map(|i| format!("IMG_{:04}.jpg taken on 2019-05-{:02}\n", i, i % 28 + 1)).collect();
+        assert_eq!(inflate(DYNAMIC).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn inflates_several_blocks() {
+        // The stored block without its last block flag, followed by the fixed one
+        let mut data = STORED.to_vec();
+        data[0] = 0x00;
+        data.extend_from_slice(FIXED);
+        assert_eq!(inflate(&data).unwrap(), b"Hello, stored block!abcabcabcabcabc hello hello");
+    }
+
+    #[test]
+    fn fails_on_truncated_data() {
+        assert!(inflate(&STORED[..10]).is_err());
+        assert!(inflate(&DYNAMIC[..DYNAMIC.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn fails_on_reserved_block_types() {
+        assert_eq!(inflate(&[0x07]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use std::path::Path;
+
+/// The first bytes of every JPEG file
+const JPEG_START: &[u8] = &[0xFF, 0xD8];
+
+/// The last bytes of a complete JPEG file
+const JPEG_END: &[u8] = &[0xFF, 0xD9];
+
+/// The first bytes of every PNG file
+const PNG_SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// The type of the last chunk of a complete PNG file
+const PNG_END_CHUNK: &[u8] = b"IEND";
+
+/// Check that a JPEG or PNG file is complete, returning what is wrong with it if it is not
+/// JPEG files must start with `FFD8` and end with `FFD9`, and PNG files must start with the PNG
+/// signature and end with an `IEND` chunk. Files of other types are not checked.
+pub fn problem(path: &Path) -> std::io::Result<Option<&'static str>> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => Ok(jpeg_problem(&fs::read(path)?)),
+        "png" => Ok(png_problem(&fs::read(path)?)),
+        _ => Ok(None),
+    }
+}
+
+fn jpeg_problem(data: &[u8]) -> Option<&'static str> {
+    if !data.starts_with(JPEG_START) {
+        return Some("it does not start with a JPEG marker");
+    }
+    // Some encoders pad the file with zeros after the end marker
+    let end = data.iter().rposition(|&byte| byte != 0).map_or(0, |last| last + 1);
+    // Motion photos have their video appended after the end marker of the still
+    if !data[..end].ends_with(JPEG_END) && !has_appended_video(data) {
+        return Some("it does not end with a JPEG end marker, so it is probably truncated");
+    }
+    None
+}
+
+fn png_problem(data: &[u8]) -> Option<&'static str> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return Some("it does not start with the PNG signature");
+    }
+    // The IEND chunk is its type followed by a 4-byte CRC
+    let end_chunk = data.len().checked_sub(8).map(|start| &data[start..start + 4]);
+    if end_chunk != Some(PNG_END_CHUNK) {
+        return Some("it does not end with an IEND chunk, so it is probably truncated");
+    }
+    None
+}
+
+/// Check whether a JPEG file has an MP4 video after the end marker of its still, as motion photos do
+fn has_appended_video(data: &[u8]) -> bool {
+    data.windows(4)
+        .position(|window| window == b"ftyp")
+        .is_some_and(|video| data[..video].windows(JPEG_END.len()).any(|window| window == JPEG_END))
+}
@@ -0,0 +1,27 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether Ctrl-C was pressed
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Only sets the flag, including on a second Ctrl-C, since stopping in the middle of a copy would
+/// leave a half-written file in the output
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Catch Ctrl-C so that the files being processed are finished instead of left half-written
+/// No new file is started once it was pressed. This is only supported on Unix: on Windows nothing
+/// is installed, Ctrl-C stops the process right away and `requested` is always false.
+pub fn install_handler() {
+    #[cfg(unix)]
+    // SAFETY: the handler only does async-signal-safe operations
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+/// Check whether Ctrl-C was pressed, in which case no new file should be started
+pub fn requested() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,3050 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use exif::{Field, In, Rational, Tag, Value as ExifValue};
+use filetime::{FileTime, set_file_times};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, DateTime, FixedOffset, LocalResult, TimeZone, Timelike, Utc};
+use log::*;
+use rayon::prelude::*;
+use std::sync::{Mutex, MutexGuard, Arc, OnceLock, PoisonError};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+mod archive;
+pub mod cache;
+pub mod check;
+mod crc32;
+pub mod date_map;
+pub mod error;
+pub mod events;
+mod exif_writer;
+mod heif;
+pub mod ignore;
+mod inflate;
+mod integrity;
+pub mod interrupt;
+pub mod manifest;
+mod name_date;
+mod nfc;
+mod orient;
+pub mod open_files;
+pub mod pattern;
+mod png;
+pub mod progress;
+pub mod prune;
+mod raw;
+mod reflink;
+pub mod report;
+mod rewrites;
+mod video;
+pub mod warnings;
+mod webp;
+mod xmp;
+pub mod years;
+pub mod zone;
+
+pub use archive::is_archive;
+pub use error::ProcessError;
+use cache::{CacheEntry, Fingerprint, MetadataCache};
+use ignore::{IgnoreRules, IGNORE_FILE_NAME};
+use pattern::{Hemisphere, Locale, MonthFormat, Pattern};
+use report::Report;
+use warnings::Category;
+use zone::Zone;
+
+/// The suffix recent Takeout exports add between the photo name and `.json`
+/// Long names are truncated by Google, so only a prefix of it may be present
+const SUPPLEMENTAL_METADATA_SUFFIX: &str = ".supplemental-metadata";
+
+/// The names of the JSON files of Takeout that describe the account rather than a photo
+const NON_PHOTO_JSON_NAMES: &[&str] = &["print-subscriptions.json", "shared_album_comments.json", "user-generated-memory-titles.json"];
+
+/// The suffixes Google adds to the edited version of a photo, in the languages seen in exports
+const EDITED_SUFFIXES: &[&str] = &["-edited", "-bearbeitet", "-modifié", "-editado", "-modificato", "-bewerkt"];
+
+/// The date prefixed to the file names with `--flatten`: `2021-03-14_153045_IMG_1234.jpg`
+pub const DEFAULT_FLATTEN_FORMAT: &str = "%Y-%m-%d_%H%M%S";
+
+/// The default name of the files with `--rename-to-timestamp`: `20210314_153045.jpg`
+pub const DEFAULT_RENAME_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+/// The default suffix of a file name taken by another file, as in `IMG_0001_1.jpg`
+pub const DEFAULT_COLLISION_SUFFIX: &str = "_{n}";
+
+/// The output directory of the files without a usable date, with `--no-creation-fallback`
+pub const UNSORTED_DIRECTORY: &str = "unsorted";
+
+/// The characters that Windows doesn't allow in file and folder names
+const RESERVED_CHARACTERS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// The extensions that are spelled in several ways, and the spelling used for their folder
+const EXTENSION_SYNONYMS: &[(&str, &str)] = &[("jpeg", "jpg"), ("tiff", "tif"), ("heif", "heic")];
+
+/// The output directory of the folders of each person, with `--by-person`
+pub const PEOPLE_DIRECTORY: &str = "People";
+
+/// The end of the first day after the Unix epoch, before which a date comes from a zero or missing
+/// timestamp rather than from a clock
+const EPOCH_DATES_END: i64 = 24 * 60 * 60;
+
+/// The output directory of the images that fail the integrity check of `--validate-images`
+pub const CORRUPT_DIRECTORY: &str = "corrupt";
+
+/// The directory of the output where the files without content go, with `--on-empty empty-dir`
+pub const EMPTY_DIRECTORY: &str = "empty";
+
+/// The extensions of the stills of motion photos, in the cases cameras write them in
+const MOTION_PHOTO_STILL_EXTENSIONS: &[&str] = &["jpg", "JPG", "jpeg", "JPEG", "heic", "HEIC"];
+
+/// The names of the files and folders that NAS and OS tools leave around, which are never photos
+const JUNK_NAMES: &[&str] = &["@eaDir", "Thumbs.db"];
+
+/// The directory of the output where the files of archives are extracted one at a time
+const STAGING_DIRECTORY: &str = ".takeout-exif-fix-staging";
+
+/// The largest metadata file read from an archive, as a guard against files that are not metadata
+const MAX_METADATA_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The extensions of the files that are not organized unless `--only-ext` asks for them
+const SKIPPED_EXTENSIONS: &[&str] = &["zip", "html"];
+
+/// The output paths reserved during parallel processing
+#[derive(Debug, Default)]
+pub struct ReservedPaths {
+    /// The reserved paths, along with the file each one is for
+    paths: HashMap<String, PathBuf>,
+    /// The next counter to try for each name, so that collisions don't rescan the taken names
+    next_counters: HashMap<String, usize>,
+    /// The files given each name so far, which `--dedup` compares the photos of that name with
+    dedup_candidates: HashMap<String, Vec<DedupCandidate>>,
+}
+
+impl ReservedPaths {
+    /// Add a file to the ones given a name, unless another thread hashed it first
+    fn record_candidate(&mut self, counter_key: String, candidate: DedupCandidate) {
+        let candidates = self.dedup_candidates.entry(counter_key).or_default();
+        if !candidates.iter().any(|recorded| recorded.output == candidate.output) {
+            candidates.push(candidate);
+        }
+    }
+}
+
+/// A file given a name, with the size and CRC-32 of the photo it comes from, so that photos are
+/// compared with what was given the name rather than with an output metadata was written into
+#[derive(Debug)]
+struct DedupCandidate {
+    /// The photo the file comes from, or the file itself when it was in the output before the run
+    source: PathBuf,
+    output: PathBuf,
+    size: u64,
+    crc: u32,
+}
+
+// A mutex to manage reserved file paths during parallel processing
+pub static MUTEX: OnceLock<Arc<Mutex<ReservedPaths>>> = OnceLock::new();
+
+/// Lock a mutex, even if a thread panicked while holding it
+/// The data behind the mutexes of this crate stays usable after a panic, since it is only changed
+/// by single insertions and counter updates.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The metadata read from a Google Takeout JSON file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhotoMetadata {
+    pub photo_taken_time: DateTime<Utc>,
+    pub photo_taken_time_field: TimestampField,
+    pub photo_last_modified_time: Option<DateTime<Utc>>,
+    pub geo_data: Option<GeoData>,
+    /// The names of the people tagged in the photo
+    #[serde(default)]
+    pub people: Vec<String>,
+    /// The caption written in Google Photos, if any
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// The field of a metadata file the date a photo was taken was read from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TimestampField {
+    PhotoTakenTime,
+    /// Used when `photoTakenTime` is missing
+    CreationTime,
+}
+
+impl TimestampField {
+    /// The name of the field in the metadata file
+    pub fn name(self) -> &'static str {
+        match self {
+            TimestampField::PhotoTakenTime => "photoTakenTime",
+            TimestampField::CreationTime => "creationTime",
+        }
+    }
+}
+
+/// Where the date used to organize a file came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateSource {
+    /// The date was given for the file by name in the map file
+    Manual,
+    Metadata,
+    Exif,
+    Video,
+    /// The date was found in the file name
+    FileName,
+    CreationTime,
+    /// The file had no usable date and was left in the unsorted directory
+    Unsorted,
+    /// The image failed the integrity check and was put in the corrupt directory
+    Corrupt,
+    /// The file has no content and was put in the empty directory
+    Empty,
+    /// The date is outside the window of `--since` and `--until`, so the file was skipped
+    OutsideWindow,
+    /// A previous run already put the file at its output path, so it was skipped
+    AlreadyOrganized,
+    /// With `--dedup`, a file with the same content was already given its name, so it was skipped
+    Duplicate,
+}
+
+impl DateSource {
+    /// The name of the source, as written in the manifest
+    pub fn name(self) -> &'static str {
+        match self {
+            DateSource::Manual => "manual",
+            DateSource::Metadata => "json",
+            DateSource::Exif => "exif",
+            DateSource::Video => "video",
+            DateSource::FileName => "filename",
+            DateSource::CreationTime => "creation",
+            DateSource::Unsorted => "unsorted",
+            DateSource::Corrupt => "corrupt",
+            DateSource::Empty => "empty",
+            DateSource::OutsideWindow => "outside_window",
+            DateSource::AlreadyOrganized => "already_organized",
+            DateSource::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// The location stored in the `geoData` field of a Google Takeout JSON file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GeoData {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+}
+
+/// Which date to use when the metadata file and the EXIF data of a photo disagree
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConflictPolicy {
+    /// Use the date of the metadata file
+    #[default]
+    Json,
+    /// Use the EXIF date
+    Exif,
+    /// Leave the photo out
+    Skip,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ConflictPolicy::Json),
+            "exif" => Ok(ConflictPolicy::Exif),
+            "skip" => Ok(ConflictPolicy::Skip),
+            _ => Err(format!("unknown conflict policy {} (expected json, exif or skip)", s)),
+        }
+    }
+}
+
+/// The unit the times of the output files are truncated to, with `--round-timestamps`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TimestampRounding {
+    #[default]
+    Second,
+    Minute,
+}
+
+impl TimestampRounding {
+    /// Truncate a file time to the unit, towards the past
+    fn round(self, time: FileTime) -> FileTime {
+        let seconds = match self {
+            TimestampRounding::Second => time.unix_seconds(),
+            TimestampRounding::Minute => time.unix_seconds() - time.unix_seconds().rem_euclid(60),
+        };
+        FileTime::from_unix_time(seconds, 0)
+    }
+}
+
+impl FromStr for TimestampRounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "second" => Ok(TimestampRounding::Second),
+            "minute" => Ok(TimestampRounding::Minute),
+            _ => Err(format!("unknown unit {} to round the timestamps to (expected second or minute)", s)),
+        }
+    }
+}
+
+/// What to do with the files that have no content, such as the placeholders of failed exports
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EmptyPolicy {
+    /// Leave the file out
+    #[default]
+    Skip,
+    /// Put the file in the empty directory of the output
+    EmptyDir,
+    /// Organize the file like any other
+    Process,
+}
+
+impl FromStr for EmptyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(EmptyPolicy::Skip),
+            "empty-dir" => Ok(EmptyPolicy::EmptyDir),
+            "process" => Ok(EmptyPolicy::Process),
+            _ => Err(format!("unknown policy {} for empty files (expected skip, empty-dir or process)", s)),
+        }
+    }
+}
+
+/// What to do with a photo where several people are tagged, with `--by-person`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MultiplePeople {
+    /// Put the photo in the folder of each person
+    #[default]
+    Each,
+    /// Only put the photo in the date-based layout
+    Skip,
+}
+
+impl FromStr for MultiplePeople {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "each" => Ok(MultiplePeople::Each),
+            "skip" => Ok(MultiplePeople::Skip),
+            _ => Err(format!("unknown choice {} for photos with several people (expected each or skip)", s)),
+        }
+    }
+}
+
+/// Whether copies are made as reflinks, which share the data of their source on copy-on-write
+/// filesystems such as Btrfs
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Reflink {
+    /// Try a reflink, and copy the data when the filesystem doesn't support it
+    #[default]
+    Auto,
+    /// Fail when a reflink can't be made
+    Always,
+    /// Always copy the data
+    Never,
+}
+
+impl FromStr for Reflink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Reflink::Auto),
+            "always" => Ok(Reflink::Always),
+            "never" => Ok(Reflink::Never),
+            _ => Err(format!("unknown reflink mode {} (expected auto, always or never)", s)),
+        }
+    }
+}
+
+/// Which EXIF date a photo is organized by
+/// When the chosen date is missing, the others are tried in the order below.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ExifDateField {
+    /// `DateTimeOriginal`, when the photo was taken
+    #[default]
+    Original,
+    /// `DateTimeDigitized`, when the photo was stored digitally, such as when it was scanned
+    Digitized,
+    /// `DateTime`, when the file was last changed
+    Created,
+}
+
+impl ExifDateField {
+    const ALL: [ExifDateField; 3] = [ExifDateField::Original, ExifDateField::Digitized, ExifDateField::Created];
+
+    /// The tags of the date, its UTC offset and its fraction of a second
+    fn tags(self) -> (Tag, Tag, Tag) {
+        match self {
+            ExifDateField::Original => (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal, Tag::SubSecTimeOriginal),
+            ExifDateField::Digitized => (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized, Tag::SubSecTimeDigitized),
+            ExifDateField::Created => (Tag::DateTime, Tag::OffsetTime, Tag::SubSecTime),
+        }
+    }
+}
+
+impl FromStr for ExifDateField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(ExifDateField::Original),
+            "digitized" => Ok(ExifDateField::Digitized),
+            "created" => Ok(ExifDateField::Created),
+            _ => Err(format!("unknown EXIF date field {} (expected original, digitized or created)", s)),
+        }
+    }
+}
+
+/// A correction of the clock of a camera, applied to the EXIF dates of the photos whose `Model`
+/// contains the model, ignoring case
+/// Parsed from `MODEL:OFFSET`, such as `Canon EOS 5D:+3h` or `DMC-FZ200:-1h30m`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraOffset {
+    pub model: String,
+    pub offset: chrono::Duration,
+}
+
+impl FromStr for CameraOffset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (model, offset) = s.rsplit_once(':')
+            .ok_or_else(|| format!("missing offset in camera offset {} (expected MODEL:OFFSET)", s))?;
+        let model = model.trim();
+        if model.is_empty() {
+            return Err(format!("missing model in camera offset {} (expected MODEL:OFFSET)", s));
+        }
+        let offset = parse_offset(offset.trim())
+            .ok_or_else(|| format!("invalid offset {} (expected a duration such as +3h, -90m or +1h30m)", offset))?;
+        Ok(CameraOffset { model: model.to_string(), offset })
+    }
+}
+
+/// Parse a signed duration made of days, hours, minutes and seconds, such as `+1h30m` or `-45s`
+fn parse_offset(offset: &str) -> Option<chrono::Duration> {
+    let (sign, mut rest) = match offset.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, offset.strip_prefix('+').unwrap_or(offset)),
+    };
+    if rest.is_empty() {
+        return None;
+    }
+    let mut seconds = 0i64;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).filter(|&digits| digits > 0)?;
+        let value: i64 = rest[..digits].parse().ok()?;
+        let unit = match rest[digits..].chars().next()? {
+            'd' => 86_400,
+            'h' => 3_600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        seconds = seconds.checked_add(value.checked_mul(unit)?)?;
+        rest = &rest[digits + 1..];
+    }
+    chrono::Duration::try_seconds(sign * seconds)
+}
+
+/// How the files are organized
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The input directories containing photos and metadata files, such as the folders of an export
+    /// that Google split in several parts
+    pub inputs: Vec<PathBuf>,
+    /// The output directory where organized photos will be stored
+    pub output: PathBuf,
+    /// Only log the planned destination of each file, without copying anything
+    pub dry_run: bool,
+    /// Move the files into the output directory instead of copying them
+    pub move_files: bool,
+    /// Give each copied file the permissions, owner and group of its input file, on Unix
+    pub preserve_ownership: bool,
+    /// Remove the directories of the input left empty once the files are organized
+    pub prune_empty_dirs: bool,
+    /// Create hard links to the files instead of copying them
+    pub hardlink: bool,
+    /// Create symbolic links to the files instead of copying them
+    pub symlink: bool,
+    /// Whether copies are made as reflinks on copy-on-write filesystems
+    pub reflink: Reflink,
+    /// How many times a copy is made again when its checksum differs from its input, if checked
+    pub checksum_retries: Option<usize>,
+    /// How many times a transfer or setting the times of a file is tried again after a transient error
+    pub retries: usize,
+    /// How long to wait before the first retry, doubling before each next one
+    pub retry_delay: std::time::Duration,
+    /// The directory structure of the output
+    pub pattern: Pattern,
+    /// How the `{month_name}` placeholder of the pattern shows a month
+    pub month_format: MonthFormat,
+    /// The language of the month names
+    pub locale: Locale,
+    /// Whose seasons the `{season}` placeholder of the pattern follows
+    pub hemisphere: Hemisphere,
+    /// Leave the `{ext}` placeholder of the pattern empty, so that there is no extension folder
+    pub no_ext_folders: bool,
+    /// The folder of the files without an extension
+    pub no_ext_dir: String,
+    /// Keep the case of the extension in the `{ext}` placeholder instead of lowercasing it
+    pub keep_ext_case: bool,
+    /// Put the files with synonymous extensions, such as `.jpeg` and `.jpg`, in the same folder
+    pub normalize_ext: bool,
+    /// Use the album directory of each photo as its output directory
+    pub keep_albums: bool,
+    /// Use a folder per location grid cell for the photos whose metadata has a location
+    pub by_location: bool,
+    /// Put every file directly in the output directory, prefixing its name with its date
+    pub flatten: bool,
+    /// The chrono format of the date prefixed to the file names with `flatten`
+    pub flatten_format: String,
+    /// Rename each file to its date in this chrono format, keeping its extension, whatever the layout
+    pub rename_to_timestamp: Option<String>,
+    /// Also put each photo with people tagged in its metadata in a folder per person
+    pub by_person: bool,
+    /// What to do with the photos where several people are tagged, with `by_person`
+    pub multiple_people: MultiplePeople,
+    /// Only process the edited version of a photo when both exist
+    pub prefer_edited: bool,
+    /// Only process the original of a photo when both exist
+    pub prefer_original: bool,
+    /// Organize the video of a motion photo with the date and in the folder of its still
+    pub group_motion_photos: bool,
+    /// Skip files that are identical to a file already at their destination
+    pub dedup: bool,
+    /// On a name collision, add the name of the source folder to the file name before trying counters
+    pub disambiguate_by_source: bool,
+    /// The suffix added to a file name taken by another file, where `{n}` is replaced by a counter
+    pub collision_suffix: String,
+    /// Organize every file again, even the ones a previous run already organized
+    pub force: bool,
+    /// Write an XMP sidecar next to each output file, named like `IMG_1234.xmp`, or `IMG_1234.CR2.xmp`
+    /// when another file of the same name took that
+    pub write_xmp: bool,
+    /// Check that the modification time of each output file is the one that was set
+    pub verify: bool,
+    /// Keep the times of the input files instead of setting them to the date the photos were taken
+    pub keep_mtime: bool,
+    /// Truncate the times of the output files to whole seconds or minutes, if set
+    pub round_timestamps: Option<TimestampRounding>,
+    /// Write the date from the metadata file into the EXIF data of JPEG files that have no
+    /// DateTimeOriginal tag
+    pub embed_exif: bool,
+    /// Write the caption from the metadata file into the EXIF data of JPEG files that have none,
+    /// and into the XMP sidecars
+    pub embed_description: bool,
+    /// Losslessly rotate JPEG files to their EXIF orientation, then set the orientation to upright
+    /// The JPEG files that can't be rotated without trimming an edge are left as they are
+    pub auto_orient: bool,
+    /// Check that JPEG and PNG files are complete, putting the ones that are not in the corrupt
+    /// directory instead of the date-based layout
+    pub validate_images: bool,
+    /// Copy the files that fail to be organized to this directory, along with a description of the error
+    pub quarantine: Option<PathBuf>,
+    /// The time zone the EXIF dates without an offset and the dates of file names are converted
+    /// from, instead of using them as UTC
+    pub time_zone: Option<Zone>,
+    /// Only organize the files with these extensions, if not empty
+    pub only_ext: Vec<String>,
+    /// Don't organize the files with these extensions
+    pub skip_ext: Vec<String>,
+    /// The ignore file used instead of the `.organizeignore` file of each input directory
+    pub ignore_file: Option<PathBuf>,
+    /// Follow the symbolic links of the input directory, except the ones leading to the output
+    pub follow_symlinks: bool,
+    /// How many levels of folders below the input directories are walked, all of them if None
+    pub max_depth: Option<usize>,
+    /// How many files of the input directories are organized, all of them if None
+    pub limit: Option<usize>,
+    /// Leave out hidden files and folders, such as `.DS_Store`, and the junk of NAS and OS tools,
+    /// such as `@eaDir` folders and `Thumbs.db`
+    pub skip_hidden: bool,
+    /// Leave the files without a creation time in the unsorted directory instead of using their
+    /// modification time
+    pub no_creation_fallback: bool,
+    /// The output directory of the files without a reliable date, instead of `unsorted`
+    /// Files without a creation time and files dated on the first day of the Unix epoch go there.
+    pub unknown_dir: Option<String>,
+    /// Skip the files dated before this day, in UTC
+    pub since: Option<NaiveDate>,
+    /// Skip the files dated after this day, in UTC
+    pub until: Option<NaiveDate>,
+    /// Show a progress bar when stderr is a terminal
+    pub progress: bool,
+    /// Compare the metadata and EXIF dates of a photo, which conflict if they are further apart
+    /// than this number of days
+    pub disagreement_days: Option<i64>,
+    /// Which date to use when the metadata and EXIF dates conflict
+    pub on_conflict: ConflictPolicy,
+    /// What to do with the files that have no content
+    pub on_empty: EmptyPolicy,
+    /// Date photos with their EXIF date when they have one, using the metadata file only for the others
+    pub exif_first: bool,
+    /// Corrections of the clocks of cameras, applied to the EXIF dates of their photos
+    pub camera_offsets: Vec<CameraOffset>,
+    /// The dates of files by name from `--map-file`, used before any other date
+    pub date_map: HashMap<String, DateTime<Utc>>,
+    /// The EXIF date the photos are organized by
+    pub exif_date_field: ExifDateField,
+    /// The number of files of the inputs from `count_candidate_files`, if already counted, so that
+    /// the progress bar doesn't walk the inputs again
+    pub candidate_count: Option<usize>,
+}
+
+impl Options {
+    /// Options that copy the files from the input to the output directory with the default layout
+    pub fn new(input: impl Into<PathBuf>, output: impl Into<PathBuf>) -> Self {
+        Options {
+            inputs: vec![input.into()],
+            output: output.into(),
+            dry_run: false,
+            move_files: false,
+            preserve_ownership: false,
+            prune_empty_dirs: false,
+            hardlink: false,
+            symlink: false,
+            reflink: Reflink::default(),
+            checksum_retries: None,
+            retries: 0,
+            retry_delay: std::time::Duration::from_millis(500),
+            pattern: Pattern::default(),
+            month_format: MonthFormat::default(),
+            locale: Locale::default(),
+            hemisphere: Hemisphere::default(),
+            no_ext_folders: false,
+            no_ext_dir: "no_ext".to_string(),
+            keep_ext_case: false,
+            normalize_ext: true,
+            keep_albums: false,
+            by_location: false,
+            flatten: false,
+            flatten_format: DEFAULT_FLATTEN_FORMAT.to_string(),
+            rename_to_timestamp: None,
+            by_person: false,
+            multiple_people: MultiplePeople::default(),
+            prefer_edited: false,
+            prefer_original: false,
+            group_motion_photos: false,
+            dedup: false,
+            disambiguate_by_source: false,
+            collision_suffix: DEFAULT_COLLISION_SUFFIX.to_string(),
+            force: false,
+            write_xmp: false,
+            verify: false,
+            keep_mtime: false,
+            round_timestamps: None,
+            embed_exif: false,
+            embed_description: false,
+            auto_orient: false,
+            validate_images: false,
+            quarantine: None,
+            time_zone: None,
+            only_ext: Vec::new(),
+            skip_ext: Vec::new(),
+            ignore_file: None,
+            follow_symlinks: false,
+            max_depth: None,
+            limit: None,
+            skip_hidden: true,
+            no_creation_fallback: false,
+            unknown_dir: None,
+            since: None,
+            until: None,
+            progress: false,
+            disagreement_days: None,
+            on_conflict: ConflictPolicy::default(),
+            on_empty: EmptyPolicy::default(),
+            exif_first: false,
+            camera_offsets: Vec::new(),
+            date_map: HashMap::new(),
+            exif_date_field: ExifDateField::default(),
+            candidate_count: None,
+        }
+    }
+}
+
+/// Walk the inputs for the metadata files
+/// The metadata file of a photo may be in another part of a split export than the photo.
+fn metadata_files(options: &Options) -> impl Iterator<Item = walkdir::DirEntry> {
+    let avoided = avoided_directories(options);
+    options.inputs.iter()
+        .flat_map(move |input| {
+            let avoided = avoided.clone();
+            let ignored = ignore_rules(input, options);
+            WalkDir::new(input)
+                .follow_links(options.follow_symlinks)
+                .max_depth(options.max_depth.unwrap_or(usize::MAX))
+                .into_iter()
+                .filter_entry(move |entry| !leads_into(entry, &avoided) && !is_ignored(entry, input, &ignored))
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| is_json_file(entry.path()))
+}
+
+/// Parse all metadata files and store relevant information in a HashMap
+/// The metadata of each file goes straight into the map, since without a cache nothing else
+/// needs to be kept about the file.
+pub fn parse_metadata_files(options: &Options) -> HashMap<String, Arc<PhotoMetadata>> {
+    let metadata_map = std::sync::Mutex::new(HashMap::new());
+    let total = std::sync::atomic::AtomicUsize::new(0);
+    let failed = std::sync::atomic::AtomicUsize::new(0);
+    metadata_files(options)
+        .par_bridge() // Parallelize the iterator
+        .for_each(|entry| {
+            // No file is organized after Ctrl-C, so the remaining metadata files are not needed
+            if interrupt::requested() {
+                return;
+            }
+            let path = entry.path();
+            let _permit = open_files::acquire(1);
+            total.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            match parse_metadata_file(path) {
+                Ok(Some((name, metadata))) => {
+                    lock(&metadata_map).insert(name, Arc::new(metadata));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Failed to read metadata file {:?}: {}", path, e);
+                    warnings::record(Category::UnreadableMetadata, path);
+                    failed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+            }
+        });
+    warn_unreadable_metadata(failed.into_inner(), total.into_inner());
+    let mut metadata_map = std::sync::Mutex::into_inner(metadata_map).unwrap_or_else(PoisonError::into_inner);
+    add_lowercase_names(&mut metadata_map);
+    metadata_map
+}
+
+/// Parse all metadata files like `parse_metadata_files`, reusing the cached entries of the files
+/// that have not changed
+/// The cache is updated with the files parsed, and loses the entries of the files that are gone.
+pub fn parse_metadata_files_cached(options: &Options, cache: &mut MetadataCache) -> HashMap<String, Arc<PhotoMetadata>> {
+    let entries = std::sync::Mutex::new(HashMap::new());
+    let reused = std::sync::atomic::AtomicUsize::new(0);
+    let failed = std::sync::atomic::AtomicUsize::new(0);
+
+    metadata_files(options)
+        .par_bridge() // Parallelize the iterator
+        .for_each(|entry| {
+            if interrupt::requested() {
+                return;
+            }
+            let path = entry.path();
+            let _permit = open_files::acquire(1);
+            let Some(fingerprint) = Fingerprint::of(path) else {
+                return;
+            };
+            let cache_entry = match cache.get(path, fingerprint) {
+                Some(cache_entry) => {
+                    reused.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    cache_entry.clone()
+                }
+                None => match parse_metadata_file(path) {
+                    Ok(photo) => CacheEntry { fingerprint, photo },
+                    // Not cached, so that the error is reported again until the file is fixed
+                    Err(e) => {
+                        warn!("Failed to read metadata file {:?}: {}", path, e);
+                        warnings::record(Category::UnreadableMetadata, path);
+                        failed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        return;
+                    }
+                },
+            };
+            lock(&entries).insert(path.to_path_buf(), cache_entry);
+        });
+
+    let entries = std::sync::Mutex::into_inner(entries).unwrap_or_else(PoisonError::into_inner);
+    debug!("Reused {} of {} metadata files from the cache", reused.into_inner(), entries.len());
+    let failed = failed.into_inner();
+    warn_unreadable_metadata(failed, failed + entries.len());
+    let mut metadata_map = entries.values()
+        .filter_map(|cache_entry| cache_entry.photo.clone())
+        .map(|(name, metadata)| (name, Arc::new(metadata)))
+        .collect();
+    cache.replace(entries);
+    add_lowercase_names(&mut metadata_map);
+    metadata_map
+}
+
+/// Also key the metadata by the lowercase name of each photo, for the photos whose name on disk
+/// differs in case from the title in their metadata file
+/// A lowercase name shared by several photos is left out, since either could be meant. Both names
+/// share the metadata, as most photos have an uppercase name.
+fn add_lowercase_names(metadata_map: &mut HashMap<String, Arc<PhotoMetadata>>) {
+    let mut lowercase: HashMap<String, Option<Arc<PhotoMetadata>>> = HashMap::new();
+    for (name, metadata) in metadata_map.iter() {
+        let key = name.to_lowercase();
+        if key == *name || metadata_map.contains_key(&key) {
+            continue;
+        }
+        lowercase.entry(key)
+            .and_modify(|existing| *existing = None)
+            .or_insert_with(|| Some(Arc::clone(metadata)));
+    }
+    for (key, metadata) in lowercase {
+        match metadata {
+            Some(metadata) => {
+                metadata_map.insert(key, metadata);
+            }
+            None => debug!("Not matching the photos named {} regardless of case, several metadata files have this name", key),
+        }
+    }
+}
+
+/// Summarize the metadata files that could not be read, which were each logged already
+fn warn_unreadable_metadata(failed: usize, total: usize) {
+    if failed > 0 {
+        warn!("{} of {} metadata files could not be read, their photos are dated from other sources", failed, total);
+    }
+}
+
+/// Parse a metadata file into the name of the photo it describes and its metadata
+/// Returns None when the file has no usable date, and an error when it can't be read or isn't JSON
+fn parse_metadata_file(path: &Path) -> Result<Option<(String, PhotoMetadata)>, ProcessError> {
+    let contents = fs::read_to_string(path)?;
+    parse_metadata(path, &contents, &|photo_path| photo_path.exists())
+}
+
+/// Parse the contents of a metadata file, telling with `photo_exists` which photos are next to it
+fn parse_metadata(path: &Path, contents: &str, photo_exists: &dyn Fn(&Path) -> bool) -> Result<Option<(String, PhotoMetadata)>, ProcessError> {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    if NON_PHOTO_JSON_NAMES.iter().any(|non_photo| name.eq_ignore_ascii_case(non_photo)) {
+        debug!("Skipping {:?}, which does not describe a photo", path);
+        return Ok(None);
+    }
+    let metadata = serde_json::from_str::<Value>(contents)?;
+    // The title of an album is its name, which must not be taken for a photo of the same name
+    if is_album_metadata(&metadata) {
+        debug!("Skipping album metadata file {:?}", path);
+        return Ok(None);
+    }
+    if is_supplemental_metadata_file(path) {
+        debug!("Parsing supplemental metadata file {:?}", path);
+    } else {
+        debug!("Parsing photo metadata file {:?}", path);
+    }
+    let Some(photo_filename) = resolve_photo_name(path, title(&metadata["title"]), photo_exists) else {
+        return Ok(None);
+    };
+    // The photo is looked up by its normalized name, however its file name is encoded on disk
+    let photo_filename = nfc::nfc(&photo_filename).into_owned();
+    let Some((field, timestamp)) = [TimestampField::PhotoTakenTime, TimestampField::CreationTime]
+        .into_iter()
+        .find_map(|field| Some((field, taken_time_seconds(&metadata[field.name()], path)?)))
+    else {
+        return Ok(None);
+    };
+    if field == TimestampField::CreationTime {
+        debug!("No photoTakenTime in {:?}, using its creationTime", path);
+    }
+    let Some(parsed_time) = DateTime::from_timestamp(timestamp, 0) else {
+        error!("Failed to parse timestamp for file: {}", photo_filename);
+        return Ok(None);
+    };
+    let photo_metadata = PhotoMetadata {
+        photo_taken_time: parsed_time,
+        photo_taken_time_field: field,
+        photo_last_modified_time: parse_timestamp(&metadata["photoLastModifiedTime"]),
+        geo_data: parse_geo_data(&metadata["geoData"]),
+        people: parse_people(&metadata["people"]),
+        description: metadata["description"].as_str()
+            .filter(|description| !description.trim().is_empty())
+            .map(str::to_string),
+    };
+    Ok(Some((photo_filename, photo_metadata)))
+}
+
+/// Read the `title` field of a metadata file, which a few hold as a list of names, the first being the photo
+fn title(title: &Value) -> Option<&str> {
+    match title {
+        Value::Array(titles) => titles.iter().find_map(Value::as_str),
+        title => title.as_str(),
+    }
+}
+
+/// Parse the `people` field of a metadata file, a list of objects with the `name` of each person
+fn parse_people(people: &Value) -> Vec<String> {
+    people.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|person| person["name"].as_str())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Check whether a metadata file describes an album, such as the `metadata.json` of each album folder
+/// These have a `date` and may have `enrichments`, but never the `photoTakenTime` of a photo
+fn is_album_metadata(metadata: &Value) -> bool {
+    metadata.get("photoTakenTime").is_none() && (metadata["date"].is_object() || metadata.get("enrichments").is_some())
+}
+
+/// Check whether a file is a JSON file, ignoring the case of the extension
+fn is_json_file(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// Get the name of the photo described by a metadata file, based on the metadata file name
+/// This handles both `IMG_1234.jpg.json` and `IMG_1234.jpg.supplemental-metadata.json`, including
+/// the truncated variants of the latter such as `IMG_1234.jpg.supplemental-m.json`
+fn sidecar_photo_name(json_path: &Path) -> Option<String> {
+    let stem = json_path.file_stem()?.to_str()?;
+    match stem.rfind('.') {
+        Some(dot) if stem.len() - dot >= 2 && SUPPLEMENTAL_METADATA_SUFFIX.starts_with(&stem[dot..]) => {
+            Some(stem[..dot].to_string())
+        }
+        _ => Some(stem.to_string()),
+    }
+}
+
+/// Get the name of the numbered duplicate of a photo described by a metadata file, if it is one
+/// Google numbers a duplicate after the extension in the name of its metadata file but before the
+/// extension in the name of the photo, as `IMG_1234.jpg(1).json` describes `IMG_1234(1).jpg`.
+fn numbered_sidecar_photo_name(json_path: &Path) -> Option<String> {
+    let stem = json_path.file_stem()?.to_str()?;
+    let (base, number) = stem.strip_suffix(')')?.rsplit_once('(')?;
+    if number.is_empty() || !number.bytes().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+    let photo_name = sidecar_photo_name(&json_path.with_file_name(format!("{}.json", base)))?;
+    match photo_name.rsplit_once('.') {
+        Some((photo_stem, extension)) if !photo_stem.is_empty() => Some(format!("{}({}).{}", photo_stem, number, extension)),
+        _ => None,
+    }
+}
+
+/// Check whether a metadata file uses the `.supplemental-metadata.json` naming scheme
+fn is_supplemental_metadata_file(json_path: &Path) -> bool {
+    let stem = json_path.file_stem().and_then(|stem| stem.to_str());
+    sidecar_photo_name(json_path).as_deref() != stem
+}
+
+/// Decide which photo a metadata file describes
+/// The `title` field is preferred, but Google may have left it out or truncated it, in which case
+/// the name derived from the metadata file name is used if such a photo exists next to it
+fn resolve_photo_name(json_path: &Path, title: Option<&str>, photo_exists: &dyn Fn(&Path) -> bool) -> Option<String> {
+    // The title of a numbered duplicate is the name of the first photo, so it can't be trusted
+    if let Some(photo_name) = numbered_sidecar_photo_name(json_path) {
+        debug!("Matched metadata file {:?} to the numbered duplicate {}", json_path, photo_name);
+        return Some(photo_name);
+    }
+
+    if let Some(title) = title
+        && photo_exists(&json_path.with_file_name(title))
+    {
+        debug!("Matched metadata file {:?} to {} by its title", json_path, title);
+        return Some(title.to_string());
+    }
+
+    if let Some(photo_name) = sidecar_photo_name(json_path)
+        && photo_exists(&json_path.with_file_name(&photo_name))
+    {
+        debug!("Matched metadata file {:?} to {} by its file name", json_path, photo_name);
+        return Some(photo_name);
+    }
+
+    // Nothing matched on disk, so trust the title as before
+    let title = title?;
+    debug!("No photo found next to metadata file {:?}, using its title {}", json_path, title);
+    Some(title.to_string())
+}
+
+/// Read the seconds of a `timestamp` field of a metadata file
+/// Recent exports store them as strings, older ones as numbers
+fn timestamp_seconds(timestamp: &Value) -> Option<i64> {
+    if let Some(seconds) = timestamp.as_str().and_then(|timestamp| timestamp.parse().ok()) {
+        debug!("Found timestamp {} stored as a string", seconds);
+        return Some(seconds);
+    }
+    let seconds = timestamp.as_i64()?;
+    debug!("Found timestamp {} stored as a number", seconds);
+    Some(seconds)
+}
+
+/// Read the seconds of a timestamp object of a metadata file, such as `photoTakenTime`
+/// Some exports store 0 or a negative `timestamp` while the `formatted` date is right, in which case
+/// the `formatted` date is used instead
+fn taken_time_seconds(timestamp: &Value, path: &Path) -> Option<i64> {
+    let seconds = timestamp_seconds(&timestamp["timestamp"]);
+    if seconds.is_some_and(|seconds| seconds > 0) {
+        return seconds;
+    }
+    match timestamp["formatted"].as_str().and_then(parse_formatted_timestamp) {
+        Some(formatted) => {
+            info!("Using the formatted date {} of {:?} instead of its timestamp", formatted, path);
+            Some(formatted.timestamp())
+        }
+        None => {
+            if let Some(seconds) = seconds {
+                warn!("Timestamp {} of {:?} looks bogus and there is no formatted date to use instead", seconds, path);
+                warnings::record(Category::BogusTimestamp, path);
+            }
+            seconds
+        }
+    }
+}
+
+/// Parse the `formatted` date of a timestamp object, such as `Mar 14, 2019, 6:30:45 PM UTC`
+/// Recent exports put a narrow no-break space before the AM or PM
+fn parse_formatted_timestamp(formatted: &str) -> Option<DateTime<Utc>> {
+    let formatted = formatted.replace('\u{202f}', " ");
+    let formatted = formatted.trim().strip_suffix("UTC")?.trim_end();
+    NaiveDateTime::parse_from_str(formatted, "%b %d, %Y, %I:%M:%S %p")
+        .ok()
+        .map(|date| date.and_utc())
+}
+
+/// Parse a timestamp object of a metadata file, such as `photoLastModifiedTime`
+fn parse_timestamp(timestamp: &Value) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(timestamp_seconds(&timestamp["timestamp"])?, 0)
+}
+
+/// Parse the `geoData` object of a metadata file
+/// Google stores 0.0 for both latitude and longitude when the location is unknown
+fn parse_geo_data(geo_data: &Value) -> Option<GeoData> {
+    let latitude = geo_data["latitude"].as_f64()?;
+    let longitude = geo_data["longitude"].as_f64()?;
+    if latitude == 0.0 && longitude == 0.0 {
+        return None;
+    }
+    Some(GeoData {
+        latitude,
+        longitude,
+        altitude: geo_data["altitude"].as_f64().unwrap_or(0.0),
+    })
+}
+
+/// Process the input directories and organize photos based on metadata or EXIF data
+/// Returns the summary of the run, where the files that failed are listed with their error
+pub fn process_directory_parallel(metadata_map: &HashMap<String, Arc<PhotoMetadata>>, options: &Options) -> Report {
+    let input_files = || options.inputs.iter().flat_map(|input| candidate_files(input, options));
+    let limit = options.limit.unwrap_or(usize::MAX);
+    // The edited and original versions of a photo can only be paired once all the files are known
+    let known_files: HashSet<String> = if options.prefer_edited || options.prefer_original {
+        input_files().map(|entry| lowercase_path(entry.path())).collect()
+    } else {
+        HashSet::new()
+    };
+
+    if options.progress && std::io::stderr().is_terminal() {
+        let count = options.candidate_count.unwrap_or_else(|| input_files().take(limit).count());
+        progress::start(count.min(limit));
+    }
+
+    let report = Mutex::new(Report::default());
+    let action = manifest_action(options);
+    // The files are taken one at a time by the threads, so the limit is exact
+    input_files()
+        .take(limit)
+        .par_bridge() // Parallelize the iterator
+        .for_each(|entry| {
+            // Ctrl-C lets the files in progress finish, but no new one is started
+            if interrupt::requested() {
+                return;
+            }
+            process_file(entry.path(), metadata_map, &known_files, options, &action, &report);
+        });
+
+    progress::finish();
+    let mut report = report.into_inner().unwrap_or_else(PoisonError::into_inner);
+    report.interrupted = interrupt::requested();
+    report
+}
+
+/// Organize the files of Takeout archives, such as the parts of a multi-part zip export, without
+/// extracting them first
+/// Each file is extracted to a staging directory of the output, organized like the files of an
+/// input directory and moved into place, so only one file at a time takes extra space. The metadata
+/// files of all the archives are read first, since Google may put one in another part than its
+/// photo, so each archive is read twice, a gzipped tar file being decompressed both times. Motion
+/// photos are not grouped, since the still of a video is not extracted next to it.
+pub fn process_archives(archives: &[PathBuf], options: &Options) -> Report {
+    // A dry run leaves the output alone, but the files still need extracting for their dates
+    let staging = if options.dry_run {
+        std::env::temp_dir().join(format!("takeout-exif-fix-{}", std::process::id()))
+    } else {
+        options.output.join(STAGING_DIRECTORY)
+    };
+    // The files are extracted to their path in the archive, so that their album is known
+    let staged_options = Options {
+        inputs: vec![staging.clone()],
+        move_files: !options.dry_run,
+        hardlink: false,
+        symlink: false,
+        group_motion_photos: false,
+        ..options.clone()
+    };
+    let extensions = ExtensionFilter::new(options);
+
+    let mut metadata_files = Vec::new();
+    let mut unreadable_metadata = 0;
+    let mut media_files = HashSet::new();
+    for archive_path in archives {
+        let listed = archive::for_each_entry(archive_path, |entry, contents| {
+            if interrupt::requested() {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            let Some(path) = staged_path(&staging, &entry.name, options) else {
+                return Ok(());
+            };
+            if is_json_file(&path) {
+                let mut json = String::new();
+                match contents.take(MAX_METADATA_FILE_SIZE).read_to_string(&mut json) {
+                    Ok(_) => metadata_files.push((path, json)),
+                    Err(e) => {
+                        warn!("Failed to read metadata file {:?} in {:?}: {}", entry.name, archive_path, e);
+                        warnings::record(Category::UnreadableMetadata, path.strip_prefix(&staging).unwrap_or(&path));
+                        unreadable_metadata += 1;
+                    }
+                }
+            } else if extensions.accepts(&path) {
+                media_files.insert(path);
+            }
+            Ok(())
+        });
+        match listed {
+            Err(_) if interrupt::requested() => break,
+            Err(e) => error!("Failed to read the archive {:?}: {}", archive_path, e),
+            Ok(()) => {}
+        }
+    }
+    let total_metadata = metadata_files.len() + unreadable_metadata;
+    let parsed: Vec<_> = metadata_files.par_iter()
+        .map(|(path, contents)| (path, parse_metadata(path, contents, &|photo_path| media_files.contains(photo_path))))
+        .collect();
+    let mut metadata_map = HashMap::new();
+    for (path, result) in parsed {
+        match result {
+            Ok(photo) => metadata_map.extend(photo.map(|(name, metadata)| (name, Arc::new(metadata)))),
+            Err(e) => {
+                warn!("Failed to read metadata file {:?}: {}", path.strip_prefix(&staging).unwrap_or(path), e);
+                warnings::record(Category::UnreadableMetadata, path.strip_prefix(&staging).unwrap_or(path));
+                unreadable_metadata += 1;
+            }
+        }
+    }
+    warn_unreadable_metadata(unreadable_metadata, total_metadata);
+    add_lowercase_names(&mut metadata_map);
+    info!("Found {} files and {} metadata files in {} archives", media_files.len(), metadata_files.len(), archives.len());
+
+    let known_files: HashSet<String> = if options.prefer_edited || options.prefer_original {
+        media_files.iter().map(|path| lowercase_path(path)).collect()
+    } else {
+        HashSet::new()
+    };
+    if options.progress && std::io::stderr().is_terminal() {
+        progress::start(media_files.len());
+    }
+
+    let report = Mutex::new(Report::default());
+    // The files are moved out of the staging directory, but the manifest tells what the user asked for
+    let action = manifest_action(options);
+    for archive_path in archives {
+        let processed = stage_each_file(archive_path, &staging, options, &media_files, |path, entry, staged| {
+            match staged {
+                Ok(()) => process_file(path, &metadata_map, &known_files, &staged_options, &action, &report),
+                Err(e) => {
+                    error!("Failed to extract {} from {:?}: {}", entry.name, archive_path, e);
+                    let e = e.into();
+                    lock(&report).record_error(path, &e);
+                    record_outcome(path, None, "error", Some(&e));
+                    progress::inc();
+                }
+            }
+        });
+        match processed {
+            Err(_) if interrupt::requested() => break,
+            Err(e) => error!("Failed to read the archive {:?}: {}", archive_path, e),
+            Ok(()) => {}
+        }
+    }
+
+    progress::finish();
+    if staging.exists()
+        && let Err(e) = fs::remove_dir_all(&staging)
+    {
+        warn!("Failed to remove the staging directory {:?}: {}", staging, e);
+    }
+    let mut report = report.into_inner().unwrap_or_else(PoisonError::into_inner);
+    report.interrupted = interrupt::requested();
+    report
+}
+
+/// Extract the media files of an archive to the staging directory one at a time, calling `organize`
+/// with each one and whether it was extracted, then removing what is left of it
+/// A file that was not organized, such as a duplicate, is left in the staging directory until then.
+/// Ctrl-C lets the file in progress finish, but no new one is extracted.
+fn stage_each_file(
+    archive_path: &Path,
+    staging: &Path,
+    options: &Options,
+    media_files: &HashSet<PathBuf>,
+    mut organize: impl FnMut(&Path, &archive::ArchiveEntry, std::io::Result<()>),
+) -> std::io::Result<()> {
+    archive::for_each_entry(archive_path, |entry, contents| {
+        if interrupt::requested() {
+            return Err(std::io::ErrorKind::Interrupted.into());
+        }
+        let Some(path) = staged_path(staging, &entry.name, options).filter(|path| media_files.contains(path)) else {
+            return Ok(());
+        };
+        organize(&path, entry, stage_file(&path, entry, contents));
+        if path.exists()
+            && let Err(e) = fs::remove_file(&path)
+        {
+            warn!("Failed to remove the extracted file {:?}: {}", path, e);
+        }
+        Ok(())
+    })
+}
+
+/// The path a file of an archive is extracted to, or None if it should be left out
+/// Names that would escape the staging directory are left out, and so are hidden and junk files
+/// unless `skip_hidden` is off.
+fn staged_path(staging: &Path, name: &str, options: &Options) -> Option<PathBuf> {
+    let name = Path::new(name);
+    let mut components = name.components();
+    if !components.all(|component| match component {
+        std::path::Component::Normal(component) => !options.skip_hidden || !is_hidden_or_junk_name(&component.to_string_lossy()),
+        _ => false,
+    }) {
+        debug!("Skipping {:?} in the archive", name);
+        return None;
+    }
+    Some(staging.join(name))
+}
+
+/// Extract a file of an archive to the staging directory, with its modification time
+fn stage_file(path: &Path, entry: &archive::ArchiveEntry, contents: &mut dyn Read) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+    // A truncated archive ends the file early
+    let extracted = std::io::copy(contents, &mut file).and_then(|written| {
+        if written == entry.size {
+            Ok(())
+        } else {
+            Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("got {} of its {} bytes", written, entry.size)))
+        }
+    });
+    if let Err(e) = extracted {
+        drop(file);
+        let _ = fs::remove_file(path);
+        return Err(e);
+    }
+    if let Some(modified) = entry.modified {
+        let modified = FileTime::from_unix_time(modified.timestamp(), 0);
+        set_file_times(path, modified, modified)?;
+    }
+    Ok(())
+}
+
+/// Organize one file, recording what happened to it in the report and the manifest with `action`
+/// A panic while processing the file is recorded as its error, so that the other files are still
+/// processed.
+fn process_file(path: &Path, metadata_map: &HashMap<String, Arc<PhotoMetadata>>, known_files: &HashSet<String>, options: &Options, action: &str, report: &Mutex<Report>) {
+    let _log_prefix = progress::prefix_logs_with(path);
+    // The file and its output are open at the same time
+    let _permit = open_files::acquire(2);
+    let processed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        process_file_unguarded(path, metadata_map, known_files, options, action, report)
+    }));
+    if let Err(panic) = processed {
+        let message = panic.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "panicked".to_string());
+        let e = ProcessError::Panic(message);
+        error!("Error processing photo file {:?}: {}", path, e);
+        lock(report).record_error(path, &e);
+        record_outcome(path, None, "error", Some(&e));
+        progress::inc();
+    }
+}
+
+/// Organize one file like `process_file`, letting a panic through
+fn process_file_unguarded(path: &Path, metadata_map: &HashMap<String, Arc<PhotoMetadata>>, known_files: &HashSet<String>, options: &Options, action: &str, report: &Mutex<Report>) {
+    let Some(filename) = path.file_name().and_then(|name| name.to_str()) else {
+        progress::inc();
+        return;
+    };
+    if let Some(preferred) = preferred_version(path, known_files, options) {
+        info!("Skipping {:?} in favor of {:?}", path, preferred);
+        lock(report).record_skipped();
+        record_outcome(path, None, "skipped", None);
+        progress::inc();
+        return;
+    }
+    let empty = options.on_empty != EmptyPolicy::Process && fs::metadata(path).is_ok_and(|metadata| metadata.len() == 0);
+    if empty {
+        warnings::record(Category::Empty, path);
+    }
+    if empty && options.on_empty == EmptyPolicy::Skip {
+        warn!("Skipping {:?}, it is empty", path);
+        lock(report).record_empty();
+        record_outcome(path, None, "skipped", None);
+        progress::inc();
+        return;
+    }
+    let metadata = find_metadata(path, filename, metadata_map);
+    let manual_time = options.date_map.get(nfc::nfc(filename).as_ref()).copied();
+    // A date given by hand settles any disagreement
+    let conflict = metadata
+        .filter(|_| manual_time.is_none())
+        .and_then(|metadata| conflicting_exif_date(path, metadata, options));
+    if conflict.is_some() && options.on_conflict == ConflictPolicy::Skip {
+        lock(report).record_skipped();
+        record_outcome(path, None, "skipped", None);
+        progress::inc();
+        return;
+    }
+    let motion_photo = if options.group_motion_photos {
+        motion_photo_date(path, metadata_map, options)
+    } else {
+        None
+    };
+    let result = if empty {
+        warn!("Putting {:?} in the {} directory, it is empty", path, EMPTY_DIRECTORY);
+        organize_aside(path, EMPTY_DIRECTORY, "as an empty file", DateSource::Empty, options)
+    } else if options.validate_images
+        && let Some(problem) = corrupt_image_problem(path)
+    {
+        warn!("Putting {:?} in the {} directory, {}", path, CORRUPT_DIRECTORY, problem);
+        warnings::record(Category::Corrupt, path);
+        organize_corrupt_file(path, options)
+    } else if let Some(manual_time) = manual_time {
+        info!("Processing file {:?} using the date from the map file: {}", path, manual_time);
+        organize_by_date(path, manual_time, metadata, DateSource::Manual, options)
+    } else if let Some((still_path, still_metadata, taken_time, source)) = motion_photo {
+        info!("Processing video file {:?} using the date of its motion photo still {:?}: {}", path, still_path, taken_time);
+        organize_by_date(path, taken_time, metadata.or(still_metadata), source, options)
+    } else if let Some(metadata) = metadata
+        && let Some(exif_time) = conflict
+        && options.on_conflict == ConflictPolicy::Exif
+    {
+        info!("Processing photo file {:?} using EXIF date: {}", path, exif_time);
+        organize_by_date(path, exif_time, Some(metadata), DateSource::Exif, options)
+    } else if let Some(metadata) = metadata
+        && options.exif_first
+        && let Ok(exif_time) = exif_date(path, options)
+    {
+        info!("Processing photo file {:?} using EXIF date, preferred over its metadata: {}", path, exif_time);
+        organize_by_date(path, exif_time, Some(metadata), DateSource::Exif, options)
+    } else if let Some(metadata) = metadata {
+        info!("Processing photo file {:?} using metadata timestamp from {}: {}",
+            path, metadata.photo_taken_time_field.name(), metadata.photo_taken_time);
+        // Process the photo using metadata
+        organize_by_date(path, metadata.photo_taken_time, Some(metadata), DateSource::Metadata, options)
+    } else if video::is_video_file(path) {
+        info!("Processing video file {:?} using its creation date", path);
+        process_video_file(path, options)
+    } else {
+        // Process the photo using EXIF data
+        info!("Processing photo file {:?} using EXIF data", path);
+        process_photo_file(path, options)
+    };
+    match &result {
+        Ok(source) => {
+            lock(report).record_processed(path, *source);
+            // A file without a destination was already organized or had a duplicate
+            if manifest::has_destination(path) {
+                record_outcome(path, Some(*source), action, None);
+            } else {
+                record_outcome(path, Some(*source), "skipped", None);
+            }
+        }
+        Err(e) => {
+            error!("Error processing photo file {:?}: {}", path, e);
+            lock(report).record_error(path, e);
+            record_outcome(path, None, "error", Some(e));
+            if let Some(quarantine) = &options.quarantine
+                && let Err(e) = quarantine_file(path, e, quarantine, options)
+            {
+                error!("Failed to quarantine {:?}: {}", path, e);
+            }
+        }
+    }
+    progress::inc();
+}
+
+/// Write the row of a file in the manifest and its event, logging any failure to do so
+fn record_outcome(path: &Path, source: Option<DateSource>, action: &str, error: Option<&ProcessError>) {
+    let (destination, taken_time) = manifest::take_destination(path);
+    if let Err(e) = manifest::record(path, destination.as_deref(), taken_time, source, action) {
+        error!("Failed to write the manifest row of {:?}: {}", path, e);
+    }
+    if let Err(e) = events::emit(path, destination.as_deref(), taken_time, source, action, error) {
+        error!("Failed to write the event of {:?}: {}", path, e);
+    }
+}
+
+/// What is done to the files that are put in the output directory, as written in the manifest
+fn manifest_action(options: &Options) -> String {
+    let (verb, past) = transfer_verbs(options);
+    if options.dry_run {
+        format!("would {}", verb)
+    } else {
+        past.to_string()
+    }
+}
+
+/// Normalize an extension given on the command line, so that `.JPG` matches `jpg`
+fn normalize_extension(extension: &str) -> String {
+    extension.trim().trim_start_matches('.').to_lowercase()
+}
+
+/// Check whether the input directories have neither metadata files nor files to organize, which
+/// usually means a wrong path
+/// The walks stop at the first file found, so this is quick for any real input.
+pub fn inputs_are_empty(options: &Options) -> bool {
+    metadata_files(options).next().is_none()
+        && options.inputs.iter().all(|input| candidate_files(input, options).next().is_none())
+}
+
+/// Count the files of the input directories that would be organized
+pub fn count_candidate_files(options: &Options) -> usize {
+    options.inputs.iter().map(|input| candidate_files(input, options).count()).sum()
+}
+
+/// Walk the directory and list the files that should be organized
+/// JSON metadata files are always left out, and so are hidden and junk files unless `skip_hidden` is
+/// off. When `--only-ext` is given, only files with those extensions are kept, otherwise the default
+/// skipped extensions and `--skip-ext` are left out. So are the paths matched by the ignore file.
+fn candidate_files(directory: &Path, options: &Options) -> impl Iterator<Item = walkdir::DirEntry> {
+    let extensions = ExtensionFilter::new(options);
+    let skip_hidden = options.skip_hidden;
+    let avoided = avoided_directories(options);
+    let ignored = ignore_rules(directory, options);
+    let root = directory.to_path_buf();
+    WalkDir::new(directory)
+        .follow_links(options.follow_symlinks)
+        .max_depth(options.max_depth.unwrap_or(usize::MAX))
+        .into_iter()
+        // The input directory itself is kept even if its name looks hidden
+        .filter_entry(move |entry| {
+            (!skip_hidden || entry.depth() == 0 || !is_hidden_or_junk(entry))
+                && !leads_into(entry, &avoided)
+                && !is_ignored(entry, &root, &ignored)
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .filter(|entry| !is_json_file(entry.path()))
+        .filter(move |entry| extensions.accepts(entry.path()))
+}
+
+/// The ignore rules of an input directory: the ones of `--ignore-file`, or of the `.organizeignore`
+/// file at its root if there is one
+fn ignore_rules(directory: &Path, options: &Options) -> IgnoreRules {
+    let path = options.ignore_file.clone().unwrap_or_else(|| directory.join(IGNORE_FILE_NAME));
+    if options.ignore_file.is_none() && !path.is_file() {
+        return IgnoreRules::default();
+    }
+    IgnoreRules::load(&path).unwrap_or_else(|e| {
+        warn!("Failed to read the ignore file {:?}, nothing is ignored: {}", path, e);
+        IgnoreRules::default()
+    })
+}
+
+/// Check whether a file or folder of an input directory is matched by its ignore rules, or is the
+/// ignore file itself
+fn is_ignored(entry: &walkdir::DirEntry, root: &Path, rules: &IgnoreRules) -> bool {
+    let Ok(relative_path) = entry.path().strip_prefix(root) else {
+        return false;
+    };
+    if relative_path.as_os_str() == IGNORE_FILE_NAME || rules.is_ignored(relative_path, entry.file_type().is_dir()) {
+        debug!("Ignoring {:?}", entry.path());
+        return true;
+    }
+    false
+}
+
+/// The directories that a walk of the input must not enter through a symbolic link, since the
+/// files organized by the run would be walked again: the output and quarantine directories
+/// Links that loop back to one of their parent folders are reported as errors by WalkDir and skipped.
+fn avoided_directories(options: &Options) -> Vec<PathBuf> {
+    if !options.follow_symlinks {
+        return Vec::new();
+    }
+    [Some(&options.output), options.quarantine.as_ref()]
+        .into_iter()
+        .flatten()
+        .filter_map(|directory| fs::canonicalize(directory).ok())
+        .collect()
+}
+
+/// Check whether an entry of a walk is, or is reached through a link as, one of the directories
+fn leads_into(entry: &walkdir::DirEntry, directories: &[PathBuf]) -> bool {
+    if directories.is_empty() || !(entry.path_is_symlink() || entry.file_type().is_dir()) {
+        return false;
+    }
+    let Ok(target) = fs::canonicalize(entry.path()) else {
+        return false;
+    };
+    if directories.iter().any(|directory| target.starts_with(directory)) {
+        info!("Not following {:?}, which leads to {:?} where the organized files go", entry.path(), target);
+        return true;
+    }
+    false
+}
+
+/// The extensions of the files to organize, from `--only-ext`, or the ones to leave out otherwise
+struct ExtensionFilter {
+    only: HashSet<String>,
+    skip: HashSet<String>,
+}
+
+impl ExtensionFilter {
+    fn new(options: &Options) -> Self {
+        ExtensionFilter {
+            only: options.only_ext.iter().map(|ext| normalize_extension(ext)).collect(),
+            skip: SKIPPED_EXTENSIONS.iter()
+                .map(|ext| ext.to_string())
+                .chain(options.skip_ext.iter().map(|ext| normalize_extension(ext)))
+                .collect(),
+        }
+    }
+
+    fn accepts(&self, path: &Path) -> bool {
+        let extension = path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if self.only.is_empty() {
+            !self.skip.contains(&extension)
+        } else {
+            self.only.contains(&extension)
+        }
+    }
+}
+
+/// Check whether a file or folder is hidden or left around by NAS and OS tools
+fn is_hidden_or_junk(entry: &walkdir::DirEntry) -> bool {
+    if is_hidden_or_junk_name(&entry.file_name().to_string_lossy()) {
+        debug!("Skipping hidden or junk {:?}", entry.path());
+        return true;
+    }
+    false
+}
+
+fn is_hidden_or_junk_name(name: &str) -> bool {
+    name.starts_with('.') || JUNK_NAMES.iter().any(|junk| name.eq_ignore_ascii_case(junk))
+}
+
+/// Get a path in lowercase, used to compare file names case-insensitively
+fn lowercase_path(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// Split the stem of an edited photo into the stem of the original and the edited suffix
+/// For example, `IMG_1234-Edited` gives `IMG_1234` and `-Edited`
+fn split_edited_suffix(stem: &str) -> Option<(&str, &str)> {
+    EDITED_SUFFIXES.iter().find_map(|suffix| {
+        let (original, edited) = stem.split_at_checked(stem.len().checked_sub(suffix.len())?)?;
+        (!original.is_empty() && edited.to_lowercase() == *suffix).then_some((original, edited))
+    })
+}
+
+/// Find the metadata of a photo
+/// Google doesn't write a metadata file for edited photos, so they use the one of the original,
+/// nor always for the video of a motion photo, which then uses the one of its still.
+pub fn find_metadata<'a>(photo_path: &Path, filename: &str, metadata_map: &'a HashMap<String, Arc<PhotoMetadata>>) -> Option<&'a PhotoMetadata> {
+    // The exact name wins over another photo whose name only differs in case
+    let get = |name: &str| {
+        let name = nfc::nfc(name);
+        metadata_map.get(name.as_ref()).or_else(|| metadata_map.get(&name.to_lowercase())).map(Arc::as_ref)
+    };
+    get(filename)
+        .or_else(|| {
+            let stem = photo_path.file_stem()?.to_str()?;
+            let (original_stem, _) = split_edited_suffix(stem)?;
+            let original_name = match photo_path.extension().and_then(|ext| ext.to_str()) {
+                Some(extension) => format!("{}.{}", original_stem, extension),
+                None => original_stem.to_string(),
+            };
+            get(&original_name)
+        })
+        .or_else(|| motion_photo_still_names(photo_path).iter().find_map(|still_name| get(still_name)))
+}
+
+/// The names the still of a motion photo can have, for its video
+/// Both `PXL_1234.MP4` and `PXL_1234.MP.mp4` go with `PXL_1234.MP.jpg`, and `IMG_1234.MOV` with
+/// `IMG_1234.HEIC`.
+fn motion_photo_still_names(video_path: &Path) -> Vec<String> {
+    if !video::is_video_file(video_path) {
+        return Vec::new();
+    }
+    let Some(stem) = video_path.file_stem().and_then(|stem| stem.to_str()) else {
+        return Vec::new();
+    };
+    let base = stem.len().checked_sub(".MP".len())
+        .and_then(|end| stem.split_at_checked(end))
+        .filter(|(base, infix)| !base.is_empty() && infix.eq_ignore_ascii_case(".MP"))
+        .map_or(stem, |(base, _)| base);
+    MOTION_PHOTO_STILL_EXTENSIONS.iter()
+        .flat_map(|extension| [format!("{}.MP.{}", base, extension), format!("{}.{}", base, extension)])
+        .collect()
+}
+
+/// Find the version of a photo that should be processed instead of this one, if any
+/// Depending on the preference, the edited version wins over the original or the other way
+/// around. `known_files` holds the lowercase paths of all the candidate files.
+fn preferred_version(photo_path: &Path, known_files: &HashSet<String>, options: &Options) -> Option<std::path::PathBuf> {
+    if !options.prefer_edited && !options.prefer_original {
+        return None;
+    }
+    let stem = photo_path.file_stem()?.to_str()?;
+    let extension = photo_path.extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let with_stem = |stem: &str| photo_path.with_file_name(format!("{}{}", stem, extension));
+
+    match split_edited_suffix(stem) {
+        // This is the edited version, skip it if the original exists
+        Some((original_stem, _)) if options.prefer_original => {
+            let original = with_stem(original_stem);
+            known_files.contains(&lowercase_path(&original)).then_some(original)
+        }
+        Some(_) => None,
+        // This is the original, skip it if any edited version exists
+        None if options.prefer_edited => EDITED_SUFFIXES.iter()
+            .map(|suffix| with_stem(&format!("{}{}", stem, suffix)))
+            .find(|edited| known_files.contains(&lowercase_path(edited))),
+        None => None,
+    }
+}
+
+/// Read the date a photo was taken from its EXIF DateTimeOriginal, converted to UTC
+/// Returns the reason when the photo has no usable date
+pub fn exif_date(photo_path: &Path, options: &Options) -> Result<DateTime<Utc>, &'static str> {
+    let exif = read_exif(photo_path).map_err(|_| "No EXIF metadata found")?;
+    let (date_field, field) = std::iter::once(options.exif_date_field)
+        .chain(ExifDateField::ALL.into_iter().filter(|date_field| *date_field != options.exif_date_field))
+        .find_map(|date_field| Some((date_field, exif.get_field(date_field.tags().0, In::PRIMARY)?)))
+        .ok_or("No EXIF date field found")?;
+    info!("Found EXIF {} field in {:?}", field.tag, photo_path);
+    let date_time = field.display_value().to_string();
+    debug!("EXIF {}: {}", field.tag, date_time);
+    let mut parsed_time = NaiveDateTime::parse_from_str(&date_time, "%Y-%m-%d %H:%M:%S")
+        .map_err(|_| "Failed to parse the EXIF date")?;
+    if let Some(nanos) = exif_subsec_nanos(&exif, date_field) {
+        debug!("EXIF fraction of a second of {:?}: {} ns", photo_path, nanos);
+        parsed_time = parsed_time.with_nanosecond(nanos).unwrap_or(parsed_time);
+    }
+    let parsed_time_utc = if let Some(offset) = exif_offset(&exif, date_field) {
+        debug!("EXIF offset of {:?}: {}", photo_path, offset);
+        local_time_to_utc(&offset, parsed_time, photo_path)
+    } else if let Some(zone) = &options.time_zone {
+        local_time_to_utc(zone, parsed_time, photo_path)
+    } else {
+        debug!("No EXIF offset found in {:?}, assuming its date is in UTC", photo_path);
+        local_time_to_utc(&Utc, parsed_time, photo_path)
+    };
+    let parsed_time_utc = parsed_time_utc.ok_or("Failed to convert the EXIF date to UTC")?;
+    Ok(match camera_offset(&exif, options) {
+        Some(camera_offset) => {
+            let corrected_time = parsed_time_utc + camera_offset.offset;
+            info!("Correcting the EXIF date of {:?} for camera {}: {} becomes {}",
+                photo_path, camera_offset.model, parsed_time_utc, corrected_time);
+            corrected_time
+        }
+        None => parsed_time_utc,
+    })
+}
+
+/// The name of the camera a photo was taken with, for the `{camera}` placeholder, such as
+/// `Canon EOS 80D` from its EXIF make and model, or `Unknown`
+/// The make is left out when the model already starts with it, as in `NIKON D750` by `NIKON CORPORATION`.
+fn camera_name(photo_path: &Path) -> String {
+    let exif = read_exif(photo_path).ok();
+    let ascii = |tag| {
+        let field = exif.as_ref()?.get_field(tag, In::PRIMARY)?;
+        let ExifValue::Ascii(values) = &field.value else {
+            return None;
+        };
+        let value = String::from_utf8_lossy(values.first()?).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string();
+        Some(value).filter(|value| !value.is_empty())
+    };
+    let name = match (ascii(Tag::Make), ascii(Tag::Model)) {
+        (Some(make), Some(model)) => {
+            let brand = make.split_whitespace().next().unwrap_or_default().to_lowercase();
+            if model.to_lowercase().starts_with(&brand) { model } else { format!("{} {}", make, model) }
+        }
+        (Some(name), None) | (None, Some(name)) => name,
+        (None, None) => return "Unknown".to_string(),
+    };
+    sanitize_component(&name)
+}
+
+/// Find the first camera offset whose model is part of the EXIF `Model` of a photo, ignoring case
+fn camera_offset<'a>(exif: &exif::Exif, options: &'a Options) -> Option<&'a CameraOffset> {
+    if options.camera_offsets.is_empty() {
+        return None;
+    }
+    let field = exif.get_field(Tag::Model, In::PRIMARY)?;
+    let ExifValue::Ascii(values) = &field.value else {
+        return None;
+    };
+    let model = String::from_utf8_lossy(values.first()?).to_lowercase();
+    options.camera_offsets.iter().find(|camera_offset| model.contains(&camera_offset.model.to_lowercase()))
+}
+
+/// Process a photo file using EXIF metadata
+/// Returns where the date used to organize the photo came from
+pub fn process_photo_file(photo_path: &Path, options: &Options) -> Result<DateSource, ProcessError> {
+    match exif_date(photo_path, options) {
+        Ok(parsed_time_utc) => return organize_by_date(photo_path, parsed_time_utc, None, DateSource::Exif, options),
+        Err(reason) => {
+            warn!("{} in {:?}", reason, photo_path);
+            warnings::record(Category::NoExifDate, photo_path);
+        }
+    }
+
+    process_file_with_name_date(photo_path, options)
+}
+
+/// Find the EXIF date of a photo matched to a metadata file, if they disagree by more than
+/// `--disagreement-days`. Nothing is compared when that option isn't set.
+fn conflicting_exif_date(photo_path: &Path, metadata: &PhotoMetadata, options: &Options) -> Option<DateTime<Utc>> {
+    let days = options.disagreement_days?;
+    let exif_time = match exif_date(photo_path, options) {
+        Ok(exif_time) => exif_time,
+        Err(reason) => {
+            debug!("{} in {:?}, not comparing it with the metadata", reason, photo_path);
+            return None;
+        }
+    };
+    let difference = (exif_time - metadata.photo_taken_time).abs();
+    if difference <= chrono::Duration::days(days) {
+        return None;
+    }
+    let action = match options.on_conflict {
+        ConflictPolicy::Json => "using the metadata date",
+        ConflictPolicy::Exif => "using the EXIF date",
+        ConflictPolicy::Skip => "skipping it",
+    };
+    warn!("The dates of {:?} disagree by {} days: {} in the metadata file and {} in EXIF, {}",
+        photo_path, difference.num_days(), metadata.photo_taken_time, exif_time, action);
+    warnings::record(Category::DatesDisagree, photo_path);
+    Some(exif_time)
+}
+
+/// Find the still of a motion photo whose video this is, such as `PXL_1234.MP.jpg` for
+/// `PXL_1234.MP.mp4`, or `IMG_1234.HEIC` for the `IMG_1234.MOV` of a Live Photo
+fn motion_photo_still(video_path: &Path) -> Option<PathBuf> {
+    if !video::is_video_file(video_path) {
+        return None;
+    }
+    MOTION_PHOTO_STILL_EXTENSIONS.iter()
+        .map(|extension| video_path.with_extension(extension))
+        .find(|still_path| still_path.is_file())
+}
+
+/// Get the date the still of a motion photo is organized with, for its video to use as well
+/// Returns the still, its metadata, the date and where it came from, or None if this is not the
+/// video of a motion photo or its still has no date or is skipped
+fn motion_photo_date<'a>(video_path: &Path, metadata_map: &'a HashMap<String, Arc<PhotoMetadata>>, options: &Options) -> Option<(PathBuf, Option<&'a PhotoMetadata>, DateTime<Utc>, DateSource)> {
+    let still_path = motion_photo_still(video_path)?;
+    let still_metadata = find_metadata(&still_path, still_path.file_name()?.to_str()?, metadata_map);
+    let exif_first = || options.exif_first.then(|| exif_date(&still_path, options).ok()).flatten();
+    let (taken_time, source) = match still_metadata {
+        Some(_) if let Some(exif_time) = exif_first() => (exif_time, DateSource::Exif),
+        Some(metadata) => match conflicting_exif_date(&still_path, metadata, options) {
+            Some(_) if options.on_conflict == ConflictPolicy::Skip => return None,
+            Some(exif_time) if options.on_conflict == ConflictPolicy::Exif => (exif_time, DateSource::Exif),
+            _ => (metadata.photo_taken_time, DateSource::Metadata),
+        },
+        None => (exif_date(&still_path, options).ok()?, DateSource::Exif),
+    };
+    Some((still_path, still_metadata, taken_time, source))
+}
+
+/// Process a QuickTime or MP4 video using the creation date stored in the file
+/// Returns where the date used to organize the video came from
+pub fn process_video_file(video_path: &Path, options: &Options) -> Result<DateSource, ProcessError> {
+    match video::read_creation_time(video_path) {
+        Ok(Some(creation_time)) => {
+            debug!("Video creation date of {:?}: {}", video_path, creation_time);
+            return organize_by_date(video_path, creation_time, None, DateSource::Video, options);
+        }
+        Ok(None) => {
+            warn!("No creation date found in video {:?}", video_path);
+            warnings::record(Category::NoVideoDate, video_path);
+        }
+        Err(e) => {
+            warn!("Failed to read the creation date of video {:?}: {}", video_path, e);
+            warnings::record(Category::NoVideoDate, video_path);
+        }
+    }
+
+    process_file_with_name_date(video_path, options)
+}
+
+/// Read the EXIF data of a photo
+/// HEIF and AVIF images that the exif crate doesn't recognize, such as the ones without the `mif1`
+/// brand, are parsed by the heif module instead, and WebP and PNG images whose EXIF chunk has an
+/// `Exif` prefix by the webp and png modules, which also read the EXIF raw profiles of older PNGs
+pub fn read_exif(photo_path: &Path) -> Result<exif::Exif, ProcessError> {
+    let file = File::open(photo_path)?;
+    let mut bufreader = std::io::BufReader::new(file);
+    match exif::Reader::new().read_from_container(&mut bufreader) {
+        Ok(exif) => Ok(exif),
+        Err(e) if heif::is_heif_file(photo_path) => {
+            debug!("Extracting the EXIF data of {:?} from its HEIF boxes: {}", photo_path, e);
+            Ok(exif::Reader::new().read_raw(heif::read_exif(photo_path)?)?)
+        }
+        Err(e) if webp::is_webp_file(photo_path) => {
+            debug!("Extracting the EXIF data of {:?} from its WebP chunks: {}", photo_path, e);
+            Ok(exif::Reader::new().read_raw(webp::read_exif(photo_path)?)?)
+        }
+        Err(e) if png::is_png_file(photo_path) => {
+            debug!("Extracting the EXIF data of {:?} from its PNG chunks: {}", photo_path, e);
+            Ok(exif::Reader::new().read_raw(png::read_exif(photo_path)?)?)
+        }
+        Err(e) if raw::is_raw_file(photo_path) => {
+            debug!("Extracting the EXIF data of RAW file {:?}: {}", photo_path, e);
+            Ok(exif::Reader::new().read_raw(raw::read_exif(photo_path)?)?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read the UTC offset of an EXIF date, such as `-03:00`
+/// The offset of the date itself is preferred, such as OffsetTimeOriginal for DateTimeOriginal,
+/// with OffsetTime as a fallback
+pub fn exif_offset(exif: &exif::Exif, date_field: ExifDateField) -> Option<FixedOffset> {
+    [date_field.tags().1, Tag::OffsetTime].into_iter().find_map(|tag| {
+        let field = exif.get_field(tag, In::PRIMARY)?;
+        let ExifValue::Ascii(values) = &field.value else {
+            return None;
+        };
+        let offset = std::str::from_utf8(values.first()?).ok()?;
+        offset.trim_end_matches('\0').trim().parse().ok()
+    })
+}
+
+/// Read the fraction of a second of an EXIF date, stored in its sub-second tag, such as
+/// SubSecTimeOriginal for DateTimeOriginal, as decimal digits such as `045` for 45 milliseconds
+fn exif_subsec_nanos(exif: &exif::Exif, date_field: ExifDateField) -> Option<u32> {
+    let field = exif.get_field(date_field.tags().2, In::PRIMARY)?;
+    let ExifValue::Ascii(values) = &field.value else {
+        return None;
+    };
+    let digits = std::str::from_utf8(values.first()?).ok()?.trim_end_matches('\0').trim();
+    if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+        return None;
+    }
+    // Only nanoseconds fit, so further digits are dropped
+    let digits = &digits[..digits.len().min(9)];
+    Some(digits.parse::<u32>().ok()? * 10u32.pow(9 - digits.len() as u32))
+}
+
+/// Convert a local time in the given time zone to UTC
+/// During a DST transition, an ambiguous time uses the earliest of its two instants and a time that
+/// falls in the gap is moved forward by an hour. Returns None if it still can't be resolved.
+pub fn local_time_to_utc<Tz: TimeZone>(timezone: &Tz, local_time: NaiveDateTime, photo_path: &Path) -> Option<DateTime<Utc>> {
+    match timezone.from_local_datetime(&local_time) {
+        LocalResult::Single(time) => Some(time.with_timezone(&Utc)),
+        LocalResult::Ambiguous(earliest, _) => {
+            warn!("EXIF date {} of {:?} is ambiguous in the time zone, using the earliest", local_time, photo_path);
+            warnings::record(Category::InvalidLocalTime, photo_path);
+            Some(earliest.with_timezone(&Utc))
+        }
+        LocalResult::None => {
+            warn!("EXIF date {} of {:?} does not exist in the time zone, moving it forward by an hour", local_time, photo_path);
+            warnings::record(Category::InvalidLocalTime, photo_path);
+            let shifted = timezone.from_local_datetime(&(local_time + chrono::Duration::hours(1))).earliest();
+            if shifted.is_none() {
+                warn!("Failed to convert EXIF date {} of {:?} to UTC", local_time, photo_path);
+                warnings::record(Category::InvalidLocalTime, photo_path);
+            }
+            shifted.map(|time| time.with_timezone(&Utc))
+        }
+    }
+}
+
+/// Process a file using the date in its name, such as `IMG_20210314_153045.jpg`
+/// Falls back to its creation timestamp when the name has no date
+fn process_file_with_name_date(photo_path: &Path, options: &Options) -> Result<DateSource, ProcessError> {
+    let Some(name_time) = name_date::date_in_name(photo_path, options) else {
+        return process_photo_file_with_creation_time(photo_path, options);
+    };
+    info!("Using the date {} in the name of {:?}", name_time, photo_path);
+    organize_by_date(photo_path, name_time, None, DateSource::FileName, options)
+}
+
+/// Fallback: Process a photo file using its creation timestamp if no metadata or EXIF data is available
+/// With `--no-creation-fallback` or `--unknown-dir`, a file whose creation time is unavailable is
+/// left unsorted rather than organized by its modification time
+pub fn process_photo_file_with_creation_time(photo_path: &Path, options: &Options) -> Result<DateSource, ProcessError> {
+    use std::fs::metadata;
+    let meta = metadata(photo_path)?;
+    let created = match meta.created() {
+        Ok(created) => created,
+        Err(e) if options.no_creation_fallback || options.unknown_dir.is_some() => {
+            info!("No creation time for {:?} ({}), leaving it unsorted", photo_path, e);
+            return organize_unsorted_file(photo_path, options);
+        }
+        Err(_) => meta.modified()?,
+    };
+    let datetime: chrono::DateTime<Utc> = created.into();
+    info!("Using file creation/modification time for {:?}", photo_path);
+    warnings::record(Category::CreationTime, photo_path);
+    organize_by_date(photo_path, datetime, None, DateSource::CreationTime, options)
+}
+
+/// Organize a file by its date, unless `--unknown-dir` is given and the date is too close to the
+/// Unix epoch to be real, in which case the file is left unsorted
+fn organize_by_date(photo_path: &Path, parsed_time: DateTime<Utc>, metadata: Option<&PhotoMetadata>, source: DateSource, options: &Options) -> Result<DateSource, ProcessError> {
+    if !in_date_window(parsed_time, options) {
+        debug!("Skipping {:?}, its date {} is outside the date window", photo_path, parsed_time);
+        return Ok(DateSource::OutsideWindow);
+    }
+    if options.unknown_dir.is_some() && parsed_time.timestamp() < EPOCH_DATES_END {
+        info!("The date {} of {:?} is not a real date, leaving it unsorted", parsed_time, photo_path);
+        return organize_unsorted_file(photo_path, options);
+    }
+    organize_and_update_file(photo_path, parsed_time, metadata, source, options)
+}
+
+/// Check whether a date is within the days of `--since` and `--until`, both included
+fn in_date_window(time: DateTime<Utc>, options: &Options) -> bool {
+    let day = time.date_naive();
+    options.since.is_none_or(|since| day >= since) && options.until.is_none_or(|until| day <= until)
+}
+
+/// A helper function to build a candidate filename
+/// This function appends a counter to the desired filename, or keeps it as is when the counter is 0.
+/// The counter is rendered with the `{n}` placeholder of the suffix format, such as `_{n}`.
+fn numbered_filename(desired_path: &Path, counter: usize, suffix_format: &str) -> std::path::PathBuf {
+    if counter == 0 {
+        return desired_path.to_path_buf();
+    }
+    with_name_suffix(desired_path, &suffix_format.replace("{n}", &counter.to_string()))
+}
+
+/// Add a suffix to a file name, before its extension
+fn with_name_suffix(path: &Path, suffix: &str) -> std::path::PathBuf {
+    let file_stem = path.file_stem()
+        .unwrap_or_else(|| std::ffi::OsStr::new("unnamed_file"));
+    let extension = path.extension()
+        .unwrap_or_else(|| std::ffi::OsStr::new(""));
+
+    let new_file_name = if extension.is_empty() {
+        format!("{}{}", file_stem.to_string_lossy(), suffix)
+    } else {
+        format!("{}{}.{}", file_stem.to_string_lossy(), suffix, extension.to_string_lossy())
+    };
+    path.with_file_name(new_file_name)
+}
+
+/// Get the name of the folder containing a file, keeping only the characters that are safe in a
+/// file name, such as `Vacation2019` for `Vacation 2019!`
+fn source_slug(photo_path: &Path) -> Option<String> {
+    let folder = photo_path.parent()?.file_name()?.to_string_lossy();
+    let slug: String = folder.chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .collect();
+    (!slug.is_empty()).then_some(slug)
+}
+
+/// Check whether two files have the same content, comparing their sizes first
+fn same_content(a: &Path, b: &Path) -> std::io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let mut reader_a = std::io::BufReader::new(File::open(a)?);
+    let mut reader_b = std::io::BufReader::new(File::open(b)?);
+    let mut buffer_a = [0; 8192];
+    let mut buffer_b = [0; 8192];
+    loop {
+        let read = reader_a.read(&mut buffer_a)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        reader_b.read_exact(&mut buffer_b[..read])?;
+        if buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Read the size and CRC-32 of a photo, which `--dedup` compares with those of the files given its name
+fn content_fingerprint(path: &Path) -> Option<(u64, u32)> {
+    let fingerprint = (|| Ok::<_, std::io::Error>((fs::metadata(path)?.len(), crc32::of_file(path)?)))();
+    fingerprint.inspect_err(|e| {
+        warn!("Failed to read {:?} to compare it with the files of the same name: {}", path, e);
+        warnings::record(Category::Transfer, path);
+    }).ok()
+}
+
+/// What `reserve_output_path` found, with the lock on the reserved paths released
+enum Reservation {
+    /// The path was reserved for the photo
+    Reserved(PathBuf),
+    /// A file of the same size and CRC-32 was given the name, from a photo that is gone
+    Duplicate(PathBuf),
+    /// A file of the same size and CRC-32 was given the name, and its source is to be compared with the photo
+    Compare { source: PathBuf, output: PathBuf },
+    /// The path is taken by a file that was not given the name in this run, which is to be hashed first
+    Hash(PathBuf),
+}
+
+/// A function to get a unique filename to output the photo
+/// This function ensures that no two threads write to the same file simultaneously
+/// by using a mutex to lock the reserved paths during the check and insert operation.
+/// First, it locks the reserved paths map, checks if the desired output path is already reserved or exists,
+/// and if not, it reserves the path by inserting it into the map along with the photo it belongs to.
+/// If the path is already reserved or exists, it tries again with a counter until a unique path is found.
+/// The counter resumes after the last one used for the same name, so that many collisions don't rescan
+/// every taken path. With `--dedup`, the photo is first compared with the files given the same name
+/// so far, then with the taken paths the counter goes past, and an error is returned when one of them
+/// already holds the same content. Files are only read with the lock released, and the lock is taken
+/// again afterwards. An error is also returned when a previous run already organized the photo at the
+/// desired output path, unless `--force` is given. The error is the source to record the skipped
+/// photo under, `DateSource::Duplicate` or `DateSource::AlreadyOrganized`.
+fn get_output_path(photo_path: &Path, desired_path: &Path, parsed_time: Option<DateTime<Utc>>, options: &Options) -> Result<std::path::PathBuf, DateSource> {
+    let reserved = || lock(reserved_paths()).paths.contains_key(desired_path.to_string_lossy().as_ref());
+    if !options.force && !reserved() && already_organized(photo_path, desired_path, parsed_time, options) {
+        info!("Skipping {:?}, already organized as {:?}", photo_path, desired_path);
+        return Err(DateSource::AlreadyOrganized);
+    }
+
+    let fingerprint = if options.dedup { content_fingerprint(photo_path) } else { None };
+    // The files found to differ from the photo despite the same size and CRC-32, or that couldn't be read
+    let mut different = HashSet::new();
+    loop {
+        match reserve_output_path(photo_path, desired_path, fingerprint, &different, options) {
+            Reservation::Reserved(output_path) => return Ok(output_path),
+            Reservation::Duplicate(output) => {
+                info!("Duplicate skipped: {:?} has the size and CRC-32 of the photo put at {:?}", photo_path, output);
+                return Err(DateSource::Duplicate);
+            }
+            Reservation::Compare { source, output } => match same_content(photo_path, &source) {
+                Ok(true) => {
+                    info!("Duplicate skipped: {:?} is identical to {:?}", photo_path, output);
+                    return Err(DateSource::Duplicate);
+                }
+                Ok(false) => {
+                    different.insert(output);
+                }
+                Err(e) => {
+                    warn!("Failed to compare {:?} with {:?}: {}", photo_path, source, e);
+                    warnings::record(Category::Transfer, photo_path);
+                    different.insert(output);
+                }
+            },
+            Reservation::Hash(existing) => match content_fingerprint(&existing) {
+                Some((size, crc)) => {
+                    let candidate = DedupCandidate { source: existing.clone(), output: existing, size, crc };
+                    lock(reserved_paths()).record_candidate(desired_counter_key(photo_path, desired_path, options), candidate);
+                }
+                None => {
+                    different.insert(existing);
+                }
+            },
+        }
+    }
+}
+
+/// The reserved paths of the run, shared by every thread
+fn reserved_paths() -> &'static Mutex<ReservedPaths> {
+    MUTEX.get_or_init(|| Arc::new(Mutex::new(ReservedPaths::default())))
+}
+
+/// The path with `--disambiguate-by-source`'s first alternative name, the one with the source folder
+fn disambiguated_path(photo_path: &Path, desired_path: &Path, options: &Options) -> Option<PathBuf> {
+    options.disambiguate_by_source
+        .then(|| source_slug(photo_path))
+        .flatten()
+        .map(|slug| with_name_suffix(desired_path, &format!("__{}", slug)))
+}
+
+/// The key of the counter and the dedup candidates of a desired path
+fn desired_counter_key(photo_path: &Path, desired_path: &Path, options: &Options) -> String {
+    disambiguated_path(photo_path, desired_path, options).as_deref().unwrap_or(desired_path).to_string_lossy().to_string()
+}
+
+/// Reserve the first free output path for a photo while holding the lock on the reserved paths,
+/// unless a file given the name may have the same content, which is left to `get_output_path` to read
+fn reserve_output_path(photo_path: &Path, desired_path: &Path, fingerprint: Option<(u64, u32)>, different: &HashSet<PathBuf>, options: &Options) -> Reservation {
+    let disambiguated_path = disambiguated_path(photo_path, desired_path, options);
+    let counter_key = desired_counter_key(photo_path, desired_path, options);
+    let mut reserved_paths = lock(reserved_paths());
+    let reserved_paths = &mut *reserved_paths;
+
+    let candidates = reserved_paths.dedup_candidates.get(&counter_key).map(Vec::as_slice).unwrap_or_default();
+    if let Some((size, crc)) = fingerprint
+        && let Some(candidate) = candidates.iter()
+            .find(|candidate| candidate.size == size && candidate.crc == crc && !different.contains(&candidate.output))
+    {
+        // A moved photo is gone, and its output may have had metadata written into it, so the CRC-32
+        // recorded for the photo is trusted then
+        if !candidate.source.exists() {
+            return Reservation::Duplicate(candidate.output.clone());
+        }
+        return Reservation::Compare { source: candidate.source.clone(), output: candidate.output.clone() };
+    }
+
+    let mut counter = reserved_paths.next_counters.get(&counter_key).copied().unwrap_or(0);
+    loop {
+        let output_path = match &disambiguated_path {
+            Some(disambiguated_path) if counter > 0 => numbered_filename(disambiguated_path, counter - 1, &options.collision_suffix),
+            _ => numbered_filename(desired_path, counter, &options.collision_suffix),
+        };
+        let key = output_path.to_string_lossy().to_string();
+        let reserved = reserved_paths.paths.contains_key(&key);
+        let exists = output_path.exists();
+        if !reserved && !exists {
+            reserved_paths.paths.insert(key, photo_path.to_path_buf());
+            reserved_paths.next_counters.insert(counter_key.clone(), counter + 1);
+            if let Some((size, crc)) = fingerprint {
+                let candidate = DedupCandidate { source: photo_path.to_path_buf(), output: output_path.clone(), size, crc };
+                reserved_paths.record_candidate(counter_key, candidate);
+            }
+            return Reservation::Reserved(output_path);
+        }
+        // A path reserved in the run is among the candidates already
+        let hashed = reserved_paths.dedup_candidates.get(&counter_key)
+            .is_some_and(|candidates| candidates.iter().any(|candidate| candidate.output == output_path));
+        if !reserved && fingerprint.is_some() && !hashed && !different.contains(&output_path) {
+            return Reservation::Hash(output_path);
+        }
+        counter += 1;
+    }
+}
+
+/// Reserve an exact output path for a file
+/// Returns false if the path is already reserved or exists
+fn reserve_path(path: &Path, source: &Path) -> bool {
+    let mut reserved_paths = lock(reserved_paths());
+    let key = path.to_string_lossy().to_string();
+    if reserved_paths.paths.contains_key(&key) || path.exists() {
+        return false;
+    }
+    reserved_paths.paths.insert(key, source.to_path_buf());
+    true
+}
+
+/// Check whether a file left by a previous run is what organizing the photo would produce
+/// It must have the same size, or the size it was left with when metadata was written into it, and
+/// the modification time that would be set, or for a hard link or
+/// with `--keep-mtime` the modification time of the photo itself, rounded like the times that are set
+/// with `--round-timestamps`. A symbolic link must point to the photo. Files without a date keep whatever time they get, so
+/// their content is compared instead.
+fn already_organized(photo_path: &Path, output_path: &Path, parsed_time: Option<DateTime<Utc>>, options: &Options) -> bool {
+    if options.symlink {
+        return matches!(
+            (fs::read_link(output_path), fs::canonicalize(photo_path)),
+            (Ok(target), Ok(photo)) if target == photo
+        );
+    }
+    let (Ok(photo), Ok(output)) = (fs::metadata(photo_path), fs::metadata(output_path)) else {
+        return false;
+    };
+    // Writing metadata into the output changes its size, which is recorded along with the size of the photo
+    if photo.len() != output.len() && rewrites::source_size(&options.output, output_path, output.len()) != Some(photo.len()) {
+        return false;
+    }
+    let Some(parsed_time) = parsed_time else {
+        return same_content(photo_path, output_path).unwrap_or(false);
+    };
+    let rounded = |time: FileTime| options.round_timestamps.map_or(time, |rounding| rounding.round(time)).unix_seconds();
+    let modification_time = FileTime::from_last_modification_time(&output).unix_seconds();
+    let photo_modification_time = FileTime::from_last_modification_time(&photo);
+    // A hard link shares the times of the photo, which are never rounded
+    modification_time == rounded(FileTime::from_unix_time(parsed_time.timestamp(), 0))
+        || (options.keep_mtime && modification_time == rounded(photo_modification_time))
+        || (options.hardlink && modification_time == photo_modification_time.unix_seconds())
+}
+
+/// Get the name of the album directory containing a photo
+/// Returns None when the photo is directly in an input directory
+fn album_name<'a>(photo_path: &'a Path, options: &Options) -> Option<&'a std::ffi::OsStr> {
+    let parent = photo_path.parent()?;
+    if options.inputs.iter().any(|input| parent == input) {
+        return None;
+    }
+    parent.file_name()
+}
+
+/// Make a name usable as a single file or folder name on every platform
+/// The characters Windows reserves and control characters become `_`, and the trailing dots and
+/// spaces that Windows drops are removed.
+pub fn sanitize_component(name: &str) -> String {
+    let sanitized: String = name.chars()
+        .map(|c| if RESERVED_CHARACTERS.contains(&c) || c.is_control() { '_' } else { c })
+        .collect();
+    match sanitized.trim_end_matches(['.', ' ']) {
+        "" => "_".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+/// Use the extended-length form of a long path on Windows, such as `\\?\C:\Photos\...`, which is
+/// not limited to 260 characters
+#[cfg(windows)]
+fn extended_length_path(path: PathBuf) -> PathBuf {
+    const MAX_PATH: usize = 260;
+    if path.as_os_str().len() < MAX_PATH || path.to_string_lossy().starts_with(r"\\?\") {
+        return path;
+    }
+    // The extended-length form must be absolute and use backslashes only
+    let Ok(absolute) = std::path::absolute(&path) else {
+        return path;
+    };
+    let absolute = absolute.to_string_lossy().replace('/', "\\");
+    match absolute.strip_prefix(r"\\") {
+        Some(share) => PathBuf::from(format!(r"\\?\UNC\{}", share)),
+        None => PathBuf::from(format!(r"\\?\{}", absolute)),
+    }
+}
+
+#[cfg(not(windows))]
+fn extended_length_path(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// Get the name of the grid cell of a location, with the coordinates rounded to a tenth of a
+/// degree, such as `-23.5_-46.6`
+fn location_cell(geo_data: &GeoData) -> String {
+    // Adding 0.0 turns -0.0 into 0.0
+    let round = |coordinate: f64| (coordinate * 10.0).round() / 10.0 + 0.0;
+    format!("{:.1}_{:.1}", round(geo_data.latitude), round(geo_data.longitude))
+}
+
+/// Check whether a file is a JPEG image by looking at its first bytes
+fn is_jpeg_file(path: &Path) -> bool {
+    let mut magic = [0; 2];
+    File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && exif_writer::is_jpeg(&magic)
+}
+
+/// Get the verb describing how files are put in the output directory, in base and past forms
+pub fn transfer_verbs(options: &Options) -> (&'static str, &'static str) {
+    if options.move_files {
+        ("move", "moved")
+    } else if options.hardlink {
+        ("hard link", "hard linked")
+    } else if options.symlink {
+        ("symlink", "symlinked")
+    } else {
+        ("copy", "copied")
+    }
+}
+
+/// Create a hard link to a file, falling back to a copy when that is not possible
+/// Returns whether the hard link was created
+fn hard_link_or_copy(
+    source: &Path,
+    destination: &Path,
+    reflink: Reflink,
+    checksum_retries: Option<usize>,
+) -> std::io::Result<bool> {
+    match fs::hard_link(source, destination) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            warn!("Failed to hard link {:?}, copying instead: {}", source, e);
+            warnings::record(Category::Transfer, source);
+            copy_file(source, destination, reflink, checksum_retries)?;
+            Ok(false)
+        }
+    }
+}
+
+/// Create a symbolic link to a file, pointing to its absolute path so that it works from any directory
+fn symlink_file(source: &Path, destination: &Path) -> std::io::Result<()> {
+    let source = fs::canonicalize(source)?;
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(source, destination);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(source, destination);
+}
+
+/// Copy a file, checking that the copy has the CRC-32 of the file if `checksum_retries` is set
+/// A copy whose checksum differs is made again up to `checksum_retries` times, then removed.
+fn copy_file(source: &Path, destination: &Path, reflink: Reflink, checksum_retries: Option<usize>) -> std::io::Result<()> {
+    let Some(retries) = checksum_retries else {
+        return copy_file_unchecked(source, destination, reflink);
+    };
+    let expected = crc32::of_file(source)?;
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            fs::remove_file(destination)?;
+            warn!("Copying {:?} again (attempt {} of {})", source, attempt + 1, retries + 1);
+            warnings::record(Category::Transfer, source);
+        }
+        copy_file_unchecked(source, destination, reflink)?;
+        let actual = crc32::of_file(destination)?;
+        if actual == expected {
+            return Ok(());
+        }
+        warn!("The copy {:?} has the checksum {:08x} instead of {:08x}", destination, actual, expected);
+    }
+    fs::remove_file(destination)?;
+    let attempts = if retries == 0 { "1 copy".to_string() } else { format!("{} copies", retries + 1) };
+    let message = format!("{} of {:?} did not have its checksum", attempts, source);
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, ProcessError::Checksum(message)))
+}
+
+/// Copy a file, removing the copy if it could not be completed so that no truncated file is left
+/// Unless `reflink` is `Never`, a reflink is tried first.
+fn copy_file_unchecked(source: &Path, destination: &Path, reflink: Reflink) -> std::io::Result<()> {
+    if reflink != Reflink::Never {
+        match reflink::clone_file(source, destination) {
+            Ok(()) => return Ok(()),
+            Err(e) if reflink == Reflink::Always => {
+                return Err(std::io::Error::new(e.kind(), format!("failed to reflink {:?}: {}", source, e)));
+            }
+            Err(e) => debug!("Failed to reflink {:?}, copying instead: {}", source, e),
+        }
+    }
+    if let Err(e) = fs::copy(source, destination) {
+        if destination.exists() && fs::remove_file(destination).is_ok() {
+            debug!("Removed the incomplete copy {:?}", destination);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Move a file, falling back to copy and delete when the destination is on another filesystem
+/// The file is only removed once its copy is complete, and checked if `checksum_retries` is set.
+fn move_file(source: &Path, destination: &Path, reflink: Reflink, checksum_retries: Option<usize>) -> std::io::Result<()> {
+    match fs::rename(source, destination) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            debug!("Cannot rename {:?} across filesystems, copying instead", source);
+            copy_file(source, destination, reflink, checksum_retries)?;
+            fs::remove_file(source)
+        }
+        result => result,
+    }
+}
+
+/// Convert a coordinate in decimal degrees to the degrees, minutes and seconds used by EXIF
+fn to_exif_coordinate(decimal: f64) -> Vec<Rational> {
+    let decimal = decimal.abs();
+    let degrees = decimal.trunc();
+    let minutes = ((decimal - degrees) * 60.0).trunc();
+    let seconds = (decimal - degrees - minutes / 60.0) * 3600.0;
+    vec![
+        Rational { num: degrees as u32, denom: 1 },
+        Rational { num: minutes as u32, denom: 1 },
+        Rational { num: (seconds * 10000.0).round() as u32, denom: 10000 },
+    ]
+}
+
+/// Write the location from the metadata file into the GPS EXIF tags of a JPEG file
+/// The file is left untouched if it already has GPS coordinates, and otherwise keeps the GPS tags it has
+fn write_gps_tags(photo_path: &Path, geo_data: &GeoData) -> Result<(), ProcessError> {
+    let gps_field = |tag, value| Field { tag, ifd_num: In::PRIMARY, value };
+    let latitude_ref = if geo_data.latitude < 0.0 { "S" } else { "N" };
+    let longitude_ref = if geo_data.longitude < 0.0 { "W" } else { "E" };
+    let fields = [
+        gps_field(Tag::GPSVersionID, ExifValue::Byte(vec![2, 3, 0, 0])),
+        gps_field(Tag::GPSLatitudeRef, ExifValue::Ascii(vec![latitude_ref.into()])),
+        gps_field(Tag::GPSLatitude, ExifValue::Rational(to_exif_coordinate(geo_data.latitude))),
+        gps_field(Tag::GPSLongitudeRef, ExifValue::Ascii(vec![longitude_ref.into()])),
+        gps_field(Tag::GPSLongitude, ExifValue::Rational(to_exif_coordinate(geo_data.longitude))),
+        gps_field(Tag::GPSAltitudeRef, ExifValue::Byte(vec![u8::from(geo_data.altitude < 0.0)])),
+        gps_field(Tag::GPSAltitude, ExifValue::Rational(vec![
+            Rational { num: (geo_data.altitude.abs() * 100.0).round() as u32, denom: 100 },
+        ])),
+    ];
+    if exif_writer::insert_fields(photo_path, &[Tag::GPSLatitude, Tag::GPSLongitude], &fields)? {
+        debug!("Wrote GPS EXIF tags to {:?}", photo_path);
+    } else {
+        debug!("Keeping the existing GPS EXIF tags of {:?}", photo_path);
+    }
+    Ok(())
+}
+
+/// Write the date a photo was taken into the DateTimeOriginal and DateTimeDigitized EXIF tags of a
+/// JPEG file, with their offset, in the local time zone with `--timezone` and in UTC otherwise
+/// The file is left untouched if it already has a DateTimeOriginal tag, and otherwise keeps the
+/// other date tags it has, such as the DateTimeDigitized of a scan
+fn write_date_tags(photo_path: &Path, parsed_time: DateTime<Utc>, options: &Options) -> Result<(), ProcessError> {
+    let local_time = if let Some(zone) = &options.time_zone {
+        parsed_time.with_timezone(zone).fixed_offset()
+    } else {
+        parsed_time.fixed_offset()
+    };
+    let date = local_time.format("%Y:%m:%d %H:%M:%S").to_string();
+    let offset = local_time.format("%:z").to_string();
+    let ascii_field = |tag, value: &str| Field { tag, ifd_num: In::PRIMARY, value: ExifValue::Ascii(vec![value.into()]) };
+    let fields = [
+        ascii_field(Tag::DateTimeOriginal, &date),
+        ascii_field(Tag::DateTimeDigitized, &date),
+        ascii_field(Tag::OffsetTimeOriginal, &offset),
+        ascii_field(Tag::OffsetTimeDigitized, &offset),
+    ];
+    if exif_writer::insert_fields(photo_path, &[Tag::DateTimeOriginal], &fields)? {
+        debug!("Wrote EXIF date tags to {:?}", photo_path);
+    } else {
+        debug!("Keeping the existing EXIF date tags of {:?}", photo_path);
+    }
+    Ok(())
+}
+
+/// Write the caption of a photo into the ImageDescription EXIF tag of a JPEG file
+/// The file is left untouched if it already has a description, such as one set by the camera.
+fn write_description_tag(photo_path: &Path, description: &str) -> Result<(), ProcessError> {
+    let field = Field { tag: Tag::ImageDescription, ifd_num: In::PRIMARY, value: ExifValue::Ascii(vec![description.into()]) };
+    if exif_writer::insert_fields(photo_path, &[Tag::ImageDescription], &[field])? {
+        debug!("Wrote the EXIF description of {:?}", photo_path);
+    } else {
+        debug!("Keeping the existing EXIF description of {:?}", photo_path);
+    }
+    Ok(())
+}
+
+/// The first name in sorted order of each stem shared by several files of a folder, such as
+/// `IMG_1234.CR2` for `IMG_1234.CR2` and `IMG_1234.jpg`
+type SharedStems = HashMap<std::ffi::OsString, std::ffi::OsString>;
+
+/// The shared stems of each input folder, listed once, the first time one of its files gets an XMP sidecar
+static SHARED_STEMS: Mutex<Option<HashMap<PathBuf, Arc<SharedStems>>>> = Mutex::new(None);
+
+/// Get the first name of each stem shared by several of the files to organize in a folder
+fn shared_stems(folder: &Path, options: &Options) -> Arc<SharedStems> {
+    if let Some(stems) = lock(&SHARED_STEMS).as_ref().and_then(|folders| folders.get(folder)) {
+        return stems.clone();
+    }
+    let extensions = ExtensionFilter::new(options);
+    let mut names: Vec<PathBuf> = fs::read_dir(folder).into_iter().flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && !is_json_file(path) && extensions.accepts(path))
+        .collect();
+    names.sort();
+    let mut stems = HashMap::new();
+    for pair in names.windows(2) {
+        if let (Some(stem), Some(other_stem), Some(name)) = (pair[0].file_stem(), pair[1].file_stem(), pair[0].file_name())
+            && stem == other_stem
+        {
+            stems.entry(stem.to_os_string()).or_insert_with(|| name.to_os_string());
+        }
+    }
+    let stems = Arc::new(stems);
+    lock(&SHARED_STEMS).get_or_insert_with(HashMap::new).insert(folder.to_path_buf(), stems.clone());
+    stems
+}
+
+/// Write an XMP sidecar next to the output file, such as `IMG_1234.xmp` for `IMG_1234.CR2`, the
+/// name Lightroom looks for
+/// The sidecar is reserved like the output files. When files of the same folder share the stem, as
+/// `IMG_1234.CR2` and `IMG_1234.jpg` do, only the first of their names in sorted order gets that
+/// name, whichever is processed first, and the sidecars of the others keep the extension of their
+/// file, as in `IMG_1234.jpg.xmp`. So do the sidecars whose name a file of another folder took. A
+/// sidecar is not written under a numbered name, since it would no longer be associated with the file.
+fn write_xmp_sidecar(photo_path: &Path, output_path: &Path, parsed_time: DateTime<Utc>, metadata: Option<&PhotoMetadata>, options: &Options) -> Result<(), ProcessError> {
+    let first_of_stem = match (photo_path.parent(), photo_path.file_stem()) {
+        (Some(folder), Some(stem)) => shared_stems(folder, options).get(stem).cloned(),
+        _ => None,
+    };
+    let plain_name = first_of_stem.is_none_or(|first| Some(first.as_os_str()) == photo_path.file_name());
+    let mut sidecar_path = output_path.with_extension("xmp");
+    if !plain_name || !reserve_path(&sidecar_path, photo_path) {
+        let mut name = output_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".xmp");
+        sidecar_path = output_path.with_file_name(name);
+        if !reserve_path(&sidecar_path, photo_path) {
+            return Err(ProcessError::PathConflict(sidecar_path));
+        }
+    }
+    if options.dry_run {
+        info!("Would write XMP sidecar {:?}", sidecar_path);
+        return Ok(());
+    }
+    let geo_data = metadata.and_then(|metadata| metadata.geo_data.as_ref());
+    let description = metadata.and_then(|metadata| metadata.description.as_deref()).filter(|_| options.embed_description);
+    fs::write(&sidecar_path, xmp::render(parsed_time, geo_data, description))?;
+    debug!("Wrote XMP sidecar {:?}", sidecar_path);
+    Ok(())
+}
+
+/// Check whether an I/O error may go away by trying again, as when a network share hiccups
+fn is_transient(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e.kind(), Interrupted | WouldBlock | TimedOut | ResourceBusy | StaleNetworkFileHandle
+        | ConnectionReset | ConnectionAborted | NetworkDown | NetworkUnreachable)
+}
+
+/// Run an I/O operation on a file, trying it again up to `--retries` times when it fails with a
+/// transient error, and waiting twice as long before each new attempt
+fn retry_transient<T>(action: &str, path: &Path, options: &Options, mut operation: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    let mut delay = options.retry_delay;
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(e) if attempt < options.retries && is_transient(&e) => {
+                attempt += 1;
+                warn!("Failed to {} {:?}, trying again in {:?} (retry {} of {}): {}", action, path, delay, attempt, options.retries, e);
+                warnings::record(Category::Transfer, path);
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Put a file at its output path by moving, linking or copying it
+/// Returns whether the output is a hard or symbolic link to the file
+fn transfer_file(photo_path: &Path, output_path: &Path, options: &Options) -> Result<bool, ProcessError> {
+    // A move across filesystems removes the input once copied
+    let input = if options.preserve_ownership { Some(fs::metadata(photo_path)?) } else { None };
+    let linked = if options.move_files {
+        retry_transient("move", photo_path, options, || move_file(photo_path, output_path, options.reflink, options.checksum_retries))?;
+        if options.prune_empty_dirs {
+            prune::record_moved(photo_path);
+        }
+        false
+    } else if options.hardlink {
+        retry_transient("link", photo_path, options, || hard_link_or_copy(photo_path, output_path, options.reflink, options.checksum_retries))?
+    } else if options.symlink {
+        retry_transient("link", photo_path, options, || symlink_file(photo_path, output_path))?;
+        true
+    } else {
+        retry_transient("copy", photo_path, options, || copy_file(photo_path, output_path, options.reflink, options.checksum_retries))?;
+        false
+    };
+    if let Some(input) = input
+        && !linked
+    {
+        preserve_ownership(&input, output_path)?;
+    }
+    Ok(linked)
+}
+
+/// Give an output file the mode, owner and group of its input file
+/// Changing the owner usually needs root, so a refusal is warned about and the file is kept.
+#[cfg(unix)]
+fn preserve_ownership(input: &fs::Metadata, output_path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    // A refusal is only warned about once, since it is the same for every file
+    static CHOWN_REFUSED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    fs::set_permissions(output_path, input.permissions())?;
+    match std::os::unix::fs::chown(output_path, Some(input.uid()), Some(input.gid())) {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            if !CHOWN_REFUSED.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                warn!("Not allowed to change the owner of {:?} to {}:{}, the output files keep the owner of this process: {}",
+                    output_path, input.uid(), input.gid(), e);
+            }
+            debug!("Kept the owner of {:?}", output_path);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[cfg(not(unix))]
+fn preserve_ownership(input: &fs::Metadata, output_path: &Path) -> std::io::Result<()> {
+    fs::set_permissions(output_path, input.permissions())
+}
+
+/// Copy a file that failed to be organized to the quarantine directory, at the same path relative
+/// to its input directory, and describe the error in a `.error.txt` file next to it
+fn quarantine_file(photo_path: &Path, error: &ProcessError, quarantine: &Path, options: &Options) -> Result<(), ProcessError> {
+    let relative_path = options.inputs.iter()
+        .find_map(|input| photo_path.strip_prefix(input).ok())
+        .unwrap_or(photo_path);
+    let quarantine_path = quarantine.join(relative_path);
+    let mut error_path = quarantine_path.clone().into_os_string();
+    error_path.push(".error.txt");
+
+    if options.dry_run {
+        info!("Would quarantine {:?} to {:?}", photo_path, quarantine_path);
+        return Ok(());
+    }
+    if let Some(parent) = quarantine_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&error_path, format!("{}: {}\n", error.kind(), error))?;
+    fs::copy(photo_path, &quarantine_path)?;
+    info!("Quarantined {:?} to {:?}", photo_path, quarantine_path);
+    Ok(())
+}
+
+/// Put a file without a usable date in the `unsorted` output directory, keeping its name and times
+pub fn organize_unsorted_file(photo_path: &Path, options: &Options) -> Result<DateSource, ProcessError> {
+    let directory = options.unknown_dir.as_deref().unwrap_or(UNSORTED_DIRECTORY);
+    organize_aside(photo_path, directory, "without a date", DateSource::Unsorted, options)
+}
+
+/// Put an image that failed the integrity check in the `corrupt` output directory, keeping its name
+pub fn organize_corrupt_file(photo_path: &Path, options: &Options) -> Result<DateSource, ProcessError> {
+    organize_aside(photo_path, CORRUPT_DIRECTORY, "as a corrupt image", DateSource::Corrupt, options)
+}
+
+/// Put a file in a directory of the output outside of the date-based layout, keeping its name and times
+/// Returns the source to record the file under, which is the given one unless the file was skipped.
+fn organize_aside(photo_path: &Path, directory: &str, description: &str, source: DateSource, options: &Options) -> Result<DateSource, ProcessError> {
+    let file_name = photo_path.file_name().unwrap_or_else(|| std::ffi::OsStr::new("unnamed_file"));
+    let desired_path = extended_length_path(options.output.join(directory).join(sanitize_component(&file_name.to_string_lossy())));
+    if check::is_active() {
+        check::compare(photo_path, &desired_path);
+        return Ok(source);
+    }
+    if !options.dry_run {
+        fs::create_dir_all(options.output.join(directory))?;
+    }
+
+    let output_path = match get_output_path(photo_path, &desired_path, None, options) {
+        Ok(output_path) => output_path,
+        Err(skipped) => return Ok(skipped),
+    };
+    manifest::record_destination(photo_path, &output_path, None);
+
+    if options.dry_run {
+        let (action, _) = transfer_verbs(options);
+        info!("Would {} {:?} to {:?} {}", action, photo_path, output_path, description);
+        return Ok(source);
+    }
+
+    transfer_file(photo_path, &output_path, options)?;
+    Ok(source)
+}
+
+/// Check whether an image is truncated or corrupt, returning what is wrong with it
+/// An image that can't be read is left to fail when it is processed
+fn corrupt_image_problem(path: &Path) -> Option<&'static str> {
+    match integrity::problem(path) {
+        Ok(problem) => problem,
+        Err(e) => {
+            warn!("Failed to check the integrity of {:?}: {}", path, e);
+            warnings::record(Category::Unchecked, path);
+            None
+        }
+    }
+}
+
+/// The name of the extension folder of a file, lowercase unless `keep_ext_case` is on
+/// With `normalize_ext`, the synonyms of an extension share its folder, as `.jpeg` files go in `jpg`.
+/// The case of an uppercase synonym is kept, as `JPEG` goes in `JPG`.
+fn extension_folder(extension: &str, options: &Options) -> String {
+    let extension = if options.keep_ext_case { extension.to_string() } else { extension.to_lowercase() };
+    let canonical = EXTENSION_SYNONYMS.iter()
+        .find(|(synonym, _)| options.normalize_ext && extension.eq_ignore_ascii_case(synonym))
+        .map(|(_, canonical)| *canonical);
+    match canonical {
+        Some(canonical) if extension.chars().all(|c| c.is_ascii_uppercase()) => canonical.to_uppercase(),
+        Some(canonical) => canonical.to_string(),
+        None => extension,
+    }
+}
+
+/// The name of a file renamed to its date, such as `20210314_153045.jpg`, keeping its extension
+fn timestamp_file_name(photo_path: &Path, parsed_time: DateTime<Utc>, format: &str) -> String {
+    let name = parsed_time.format(format).to_string();
+    match photo_path.extension() {
+        Some(ext) => format!("{}.{}", name, ext.to_string_lossy()),
+        None => name,
+    }
+}
+
+/// Organize and update the file based on the parsed time
+/// In a dry run, the destination is computed and reserved but nothing is written to disk.
+/// Returns the source to record the file under, which is the given one unless the file was skipped.
+pub fn organize_and_update_file(photo_path: &Path, parsed_time: chrono::DateTime<Utc>, metadata: Option<&PhotoMetadata>, source: DateSource, options: &Options) -> Result<DateSource, ProcessError> {
+    // The video of a motion photo goes in the folder of its still
+    let still_path = if options.group_motion_photos { motion_photo_still(photo_path) } else { None };
+    let extension = if options.no_ext_folders {
+        String::new()
+    } else {
+        // `extension` is None for a dotfile such as `.profile`, and empty for a name ending with a dot
+        still_path.as_deref().unwrap_or(photo_path).extension()
+            .and_then(|ext| ext.to_str())
+            .filter(|ext| !ext.is_empty())
+            .map(|ext| sanitize_component(&extension_folder(ext, options)))
+            .unwrap_or_else(|| sanitize_component(&options.no_ext_dir))
+    };
+    let file_name = match &options.rename_to_timestamp {
+        Some(format) => timestamp_file_name(photo_path, parsed_time, format),
+        None => photo_path.file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new("unnamed_file"))
+            .to_string_lossy()
+            .to_string(),
+    };
+    let desired_path = if options.flatten {
+        // A file renamed to its date doesn't need the date as a prefix too
+        if options.rename_to_timestamp.is_some() {
+            options.output.join(sanitize_component(&file_name))
+        } else {
+            let prefix = parsed_time.format(&options.flatten_format);
+            options.output.join(sanitize_component(&format!("{}_{}", prefix, file_name)))
+        }
+    } else {
+        let geo_data = metadata.and_then(|metadata| metadata.geo_data.as_ref());
+        let target_dir = match (album_name(photo_path, options), geo_data) {
+            (Some(album), _) if options.keep_albums => options.output.join(sanitize_component(&album.to_string_lossy())),
+            (_, Some(geo_data)) if options.by_location => options.output.join(location_cell(geo_data)),
+            _ => {
+                // The video of a motion photo goes with the camera of its still
+                let camera = if options.pattern.uses_camera() { camera_name(still_path.as_deref().unwrap_or(photo_path)) } else { String::new() };
+                options.output.join(options.pattern.render(parsed_time, &extension, &camera, options.month_format, options.locale, options.hemisphere))
+            }
+        };
+        target_dir.join(sanitize_component(&file_name))
+    };
+    let desired_path = extended_length_path(desired_path);
+    if check::is_active() {
+        check::compare(photo_path, &desired_path);
+        return Ok(source);
+    }
+    if !options.dry_run
+        && let Some(target_dir) = desired_path.parent()
+    {
+        fs::create_dir_all(target_dir)?;
+    }
+
+    let output_path = match get_output_path(photo_path, &desired_path, Some(parsed_time), options) {
+        Ok(output_path) => output_path,
+        Err(skipped) => return Ok(skipped),
+    };
+    manifest::record_destination(photo_path, &output_path, Some(parsed_time));
+    years::record(parsed_time.year());
+
+    if options.write_xmp
+        && let Err(e) = write_xmp_sidecar(photo_path, &output_path, parsed_time, metadata, options)
+    {
+        warn!("Failed to write the XMP sidecar of {:?}: {}", output_path, e);
+        warnings::record(Category::OutputNotUpdated, &output_path);
+    }
+
+    if options.dry_run {
+        let (action, _) = transfer_verbs(options);
+        info!("Would {} {:?} to {:?} with timestamp {}", action, photo_path, output_path, parsed_time);
+        place_by_person(photo_path, &output_path, parsed_time, metadata, options);
+        return Ok(source);
+    }
+
+    // A moved file is gone from the input once transferred
+    let input = fs::metadata(photo_path)?;
+    let input_times = options.keep_mtime
+        .then(|| (FileTime::from_last_access_time(&input), FileTime::from_last_modification_time(&input)));
+    let linked = transfer_file(photo_path, &output_path, options)?;
+
+    // Changing a link would change the input file as well
+    if !linked {
+        update_output_file(&output_path, parsed_time, metadata, input_times, options)?;
+        let output_size = fs::metadata(&output_path)?.len();
+        if output_size != input.len()
+            && let Err(e) = rewrites::record(&options.output, &output_path, input.len(), output_size)
+        {
+            warn!("Failed to record the new size of {:?}: {}", output_path, e);
+            warnings::record(Category::OutputNotUpdated, &output_path);
+        }
+    }
+    place_by_person(photo_path, &output_path, parsed_time, metadata, options);
+    Ok(source)
+}
+
+/// Write the metadata of a photo into its organized file and set the times of the file
+/// The times are the access and modification times of the input file when given, with `--keep-mtime`.
+fn update_output_file(output_path: &Path, parsed_time: DateTime<Utc>, metadata: Option<&PhotoMetadata>, input_times: Option<(FileTime, FileTime)>, options: &Options) -> Result<(), ProcessError> {
+    // Rotating rewrites the whole file, so it goes before the EXIF tags are written
+    if options.auto_orient
+        && is_jpeg_file(output_path)
+        && let Err(e) = orient::auto_orient(output_path)
+    {
+        warn!("Failed to rotate {:?} to its EXIF orientation: {}", output_path, e);
+        warnings::record(Category::OutputNotUpdated, output_path);
+    }
+
+    if let Some(geo_data) = metadata.and_then(|metadata| metadata.geo_data.as_ref())
+        && is_jpeg_file(output_path)
+        && let Err(e) = write_gps_tags(output_path, geo_data)
+    {
+        warn!("Failed to write GPS EXIF tags to {:?}: {}", output_path, e);
+        warnings::record(Category::OutputNotUpdated, output_path);
+    }
+
+    if options.embed_exif
+        && metadata.is_some()
+        && is_jpeg_file(output_path)
+        && let Err(e) = write_date_tags(output_path, parsed_time, options)
+    {
+        warn!("Failed to write EXIF date tags to {:?}: {}", output_path, e);
+        warnings::record(Category::OutputNotUpdated, output_path);
+    }
+
+    if options.embed_description
+        && let Some(description) = metadata.and_then(|metadata| metadata.description.as_deref())
+        && is_jpeg_file(output_path)
+        && let Err(e) = write_description_tag(output_path, description)
+    {
+        warn!("Failed to write the EXIF description of {:?}: {}", output_path, e);
+        warnings::record(Category::OutputNotUpdated, output_path);
+    }
+
+    // The modification time is when the photo was taken, and the access time when it was last
+    // modified in Google Photos if the metadata has it
+    let (access_time, modification_time) = match input_times {
+        Some(times) => times,
+        None => {
+            let modification_time = FileTime::from_unix_time(parsed_time.timestamp(), parsed_time.timestamp_subsec_nanos());
+            let access_time = match metadata.and_then(|metadata| metadata.photo_last_modified_time) {
+                Some(last_modified_time) => FileTime::from_unix_time(last_modified_time.timestamp(), 0),
+                None => modification_time,
+            };
+            (access_time, modification_time)
+        }
+    };
+    let (access_time, modification_time) = match options.round_timestamps {
+        Some(rounding) => (rounding.round(access_time), rounding.round(modification_time)),
+        None => (access_time, modification_time),
+    };
+    retry_transient("set the times of", output_path, options, || set_file_times(output_path, access_time, modification_time))?;
+
+    if options.verify {
+        let expected = DateTime::from_timestamp(modification_time.unix_seconds(), modification_time.nanoseconds())
+            .ok_or_else(|| ProcessError::TimestampParse(format!("modification time of {:?} is out of range", output_path)))?;
+        verify_modification_time(output_path, expected)?;
+    }
+
+    Ok(())
+}
+
+/// Put an organized photo in `People/<name>` for each person tagged in its metadata, with `--by-person`
+/// It is a hard link to the organized file, or a copy where hard links are not supported, and a
+/// symbolic link to the input file with `--symlink`. Failures are only logged, since the photo is
+/// already organized by its date.
+fn place_by_person(photo_path: &Path, output_path: &Path, parsed_time: DateTime<Utc>, metadata: Option<&PhotoMetadata>, options: &Options) {
+    let people = metadata.map(|metadata| metadata.people.as_slice()).unwrap_or_default();
+    if !options.by_person || people.is_empty() {
+        return;
+    }
+    if people.len() > 1 && options.multiple_people == MultiplePeople::Skip {
+        info!("Not putting {:?} in a folder per person, {} people are tagged in it", photo_path, people.len());
+        return;
+    }
+    let Some(file_name) = output_path.file_name() else {
+        return;
+    };
+    for person in people {
+        // A name is a single folder, whatever characters it has, and not a hidden one
+        let folder = sanitize_component(person.trim_start_matches('.'));
+        let desired_path = extended_length_path(options.output.join(PEOPLE_DIRECTORY).join(&folder).join(file_name));
+        if !options.dry_run
+            && let Some(parent) = desired_path.parent()
+            && let Err(e) = fs::create_dir_all(parent)
+        {
+            warn!("Failed to create the folder of {} for {:?}: {}", person, photo_path, e);
+            warnings::record(Category::PersonFolder, photo_path);
+            continue;
+        }
+        let Ok(person_path) = get_output_path(photo_path, &desired_path, Some(parsed_time), options) else {
+            continue;
+        };
+        if options.dry_run {
+            info!("Would also put {:?} in {:?}", photo_path, person_path);
+            continue;
+        }
+        let placed = if options.symlink {
+            symlink_file(photo_path, &person_path)
+        } else {
+            // A copy gets the times of the organized file, which a hard link shares
+            hard_link_or_copy(output_path, &person_path, options.reflink, options.checksum_retries).and_then(|linked| {
+                if linked {
+                    return Ok(());
+                }
+                let metadata = fs::metadata(output_path)?;
+                let access_time = FileTime::from_last_access_time(&metadata);
+                set_file_times(&person_path, access_time, FileTime::from_last_modification_time(&metadata))
+            })
+        };
+        // A copy of a rewritten file is recognized on a re-run like the file itself
+        let placed = placed.and_then(|()| {
+            let size = fs::metadata(&person_path)?.len();
+            match rewrites::source_size(&options.output, output_path, size) {
+                Some(source_size) => rewrites::record(&options.output, &person_path, source_size, size),
+                None => Ok(()),
+            }
+        });
+        match placed {
+            Ok(()) => debug!("Put {:?} in {:?}", photo_path, person_path),
+            Err(e) => {
+                warn!("Failed to put {:?} in {:?}: {}", photo_path, person_path, e);
+                warnings::record(Category::PersonFolder, photo_path);
+            }
+        }
+    }
+}
+
+/// Check that the modification time of an output file is within a second of the one that was set
+/// Some network filesystems silently ignore or round the times that are set
+fn verify_modification_time(output_path: &Path, parsed_time: DateTime<Utc>) -> Result<(), ProcessError> {
+    let modification_time = FileTime::from_last_modification_time(&fs::metadata(output_path)?);
+    let written = DateTime::from_timestamp(modification_time.unix_seconds(), modification_time.nanoseconds())
+        .ok_or_else(|| ProcessError::TimestampParse(format!("modification time of {:?} is out of range", output_path)))?;
+    if (written - parsed_time).abs() > chrono::Duration::seconds(1) {
+        return Err(ProcessError::Verification(format!(
+            "the modification time of {:?} is {} instead of {}", output_path, written, parsed_time,
+        )));
+    }
+    debug!("Verified the modification time of {:?}", output_path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for a test in the temporary directory, with an input and an output directory
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("takeout-exif-fix-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("input")).unwrap();
+        fs::create_dir_all(directory.join("output")).unwrap();
+        directory
+    }
+
+    fn write(path: &Path, contents: &str) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        path.to_path_buf()
+    }
+
+    fn output_name(photo: &Path, desired: &Path, options: &Options) -> Option<String> {
+        let output = get_output_path(photo, desired, None, options).ok()?;
+        Some(output.file_name().unwrap().to_string_lossy().to_string())
+    }
+
+    #[test]
+    fn extracts_one_archive_file_at_a_time() {
+        let directory = test_directory("extracts_one_archive_file_at_a_time");
+        let options = Options::new(directory.join("input"), directory.join("output"));
+        let photos = ["IMG_0001.jpg", "IMG_0002.jpg", "IMG_0003.jpg"];
+        let entries: Vec<(String, u8, &[u8])> = photos.iter()
+            .map(|name| (format!("Takeout/Google Photos/Photos from 2019/{}", name), b'0', b"a photo".as_slice()))
+            .collect();
+        let entries: Vec<_> = entries.iter().map(|(name, kind, contents)| (name.as_str(), *kind, *contents)).collect();
+        let archive_path = directory.join("input/takeout.tgz");
+        fs::write(&archive_path, archive::tests::gzip(&archive::tests::tar_archive(&entries))).unwrap();
+        let staging = directory.join("output").join(STAGING_DIRECTORY);
+        let media_files: HashSet<PathBuf> = entries.iter().map(|(name, _, _)| staging.join(name)).collect();
+
+        let mut organized = Vec::new();
+        stage_each_file(&archive_path, &staging, &options, &media_files, |path, _, staged| {
+            assert!(staged.is_ok());
+            let staged_files: Vec<_> = WalkDir::new(&staging).into_iter()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.into_path())
+                .collect();
+            assert_eq!(staged_files, [path]);
+            organized.push(path.file_name().unwrap().to_string_lossy().to_string());
+        }).unwrap();
+
+        assert_eq!(organized, photos);
+        assert!(WalkDir::new(&staging).into_iter().filter_map(Result::ok).all(|entry| !entry.file_type().is_file()));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn numbers_file_names_before_their_extension() {
+        assert_eq!(numbered_filename(Path::new("a/IMG_0001.jpg"), 0, "_{n}"), Path::new("a/IMG_0001.jpg"));
+        assert_eq!(numbered_filename(Path::new("a/IMG_0001.jpg"), 2, "_{n}"), Path::new("a/IMG_0001_2.jpg"));
+        assert_eq!(numbered_filename(Path::new("a/README"), 1, " ({n})"), Path::new("a/README (1)"));
+    }
+
+    #[test]
+    fn counts_up_from_taken_output_paths() {
+        let directory = test_directory("counts_up_from_taken_output_paths");
+        let options = Options::new(directory.join("input"), directory.join("output"));
+        let desired = write(&directory.join("output/IMG_0001.jpg"), "a photo organized before");
+        let first = write(&directory.join("input/a/IMG_0001.jpg"), "first");
+        let second = write(&directory.join("input/b/IMG_0001.jpg"), "second");
+
+        assert_eq!(output_name(&first, &desired, &options).as_deref(), Some("IMG_0001_1.jpg"));
+        assert_eq!(output_name(&second, &desired, &options).as_deref(), Some("IMG_0001_2.jpg"));
+        // A path written to since it was reserved is skipped too
+        write(&directory.join("output/IMG_0001_3.jpg"), "another photo");
+        assert_eq!(output_name(&second, &desired, &options).as_deref(), Some("IMG_0001_4.jpg"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn uses_the_collision_suffix() {
+        let directory = test_directory("uses_the_collision_suffix");
+        let options = Options { collision_suffix: " ({n})".to_string(), ..Options::new(directory.join("input"), directory.join("output")) };
+        let desired = write(&directory.join("output/IMG_0001.jpg"), "a photo organized before");
+        let photo = write(&directory.join("input/IMG_0001.jpg"), "a photo");
+
+        assert_eq!(output_name(&photo, &desired, &options).as_deref(), Some("IMG_0001 (1).jpg"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn tries_the_source_folder_before_counters() {
+        let directory = test_directory("tries_the_source_folder_before_counters");
+        let options = Options { disambiguate_by_source: true, ..Options::new(directory.join("input"), directory.join("output")) };
+        let desired = write(&directory.join("output/IMG_0001.jpg"), "a photo organized before");
+        let first = write(&directory.join("input/Vacation 2019!/IMG_0001.jpg"), "first");
+        let second = write(&directory.join("input/Vacation 2019!/IMG_0001 copy.jpg"), "second");
+
+        assert_eq!(output_name(&first, &desired, &options).as_deref(), Some("IMG_0001__Vacation2019.jpg"));
+        assert_eq!(output_name(&second, &desired, &options).as_deref(), Some("IMG_0001__Vacation2019_1.jpg"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn skips_duplicates_of_any_taken_path() {
+        let directory = test_directory("skips_duplicates_of_any_taken_path");
+        let options = Options { dedup: true, ..Options::new(directory.join("input"), directory.join("output")) };
+        let desired = write(&directory.join("output/IMG_0001.jpg"), "another photo");
+        write(&directory.join("output/IMG_0001_1.jpg"), "the same photo");
+        let duplicate = write(&directory.join("input/a/IMG_0001.jpg"), "the same photo");
+        let different = write(&directory.join("input/b/IMG_0001.jpg"), "a different photo");
+
+        assert_eq!(output_name(&duplicate, &desired, &options), None);
+        assert_eq!(output_name(&different, &desired, &options).as_deref(), Some("IMG_0001_2.jpg"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn skips_duplicates_of_paths_reserved_in_the_run() {
+        let directory = test_directory("skips_duplicates_of_paths_reserved_in_the_run");
+        let options = Options { dedup: true, ..Options::new(directory.join("input"), directory.join("output")) };
+        let desired = write(&directory.join("output/IMG_0001.jpg"), "a photo organized before");
+        let first = write(&directory.join("input/a/IMG_0001.jpg"), "photo one");
+        let copy = write(&directory.join("input/b/IMG_0001.jpg"), "photo one");
+        // The same size as the others, so that only the CRC-32 tells it apart
+        let other = write(&directory.join("input/c/IMG_0001.jpg"), "photo two");
+
+        assert_eq!(output_name(&first, &desired, &options).as_deref(), Some("IMG_0001_1.jpg"));
+        // The reserved path hasn't been written to, so the photo it is for is compared instead
+        assert_eq!(output_name(&copy, &desired, &options), None);
+        assert_eq!(output_name(&other, &desired, &options).as_deref(), Some("IMG_0001_2.jpg"));
+        assert_eq!(output_name(&other, &desired, &options), None);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn skips_duplicates_of_moved_photos_their_output_was_rewritten() {
+        let directory = test_directory("skips_duplicates_of_moved_photos");
+        let options = Options { dedup: true, ..Options::new(directory.join("input"), directory.join("output")) };
+        let desired = directory.join("output/IMG_0001.jpg");
+        let first = write(&directory.join("input/a/IMG_0001.jpg"), "photo one");
+        let copy = write(&directory.join("input/b/IMG_0001.jpg"), "photo one");
+
+        assert_eq!(output_name(&first, &desired, &options).as_deref(), Some("IMG_0001.jpg"));
+        // The photo was moved to its output, and then its location was written into it
+        fs::rename(&first, &desired).unwrap();
+        fs::write(&desired, "photo one, with GPS tags").unwrap();
+        assert_eq!(output_name(&copy, &desired, &options), None);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
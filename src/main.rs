@@ -1,251 +1,785 @@
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::Read;
-use std::path::Path;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use chrono::NaiveDate;
+use chrono::format::{Item, StrftimeItems};
 use clap::Parser;
-use walkdir::WalkDir;
-use serde_json::Value;
-use exif::{In, Tag};
-use filetime::{FileTime, set_file_times};
-use chrono::{NaiveDateTime, Datelike, DateTime, TimeZone, Utc};
-use pretty_env_logger;
 use log::*;
-use rayon::prelude::*;
-use std::sync::{Mutex, MutexGuard, Arc, OnceLock};
-use std::collections::HashSet;
-
-
-// A mutex to manage reserved file paths during parallel processing
-pub static MUTEX: OnceLock<Arc<Mutex<HashSet<String>>>> = OnceLock::new();
+use takeout_exif_fix::cache::MetadataCache;
+use takeout_exif_fix::ignore::IgnoreRules;
+use takeout_exif_fix::pattern::{Hemisphere, Locale, MonthFormat, Pattern, DEFAULT_PATTERN, EXT_TOP_PATTERN};
+use takeout_exif_fix::zone::Zone;
+use takeout_exif_fix::{check, count_candidate_files, date_map, events, inputs_are_empty, interrupt, is_archive, manifest, open_files, parse_metadata_files, parse_metadata_files_cached, process_archives, process_directory_parallel, progress, prune, transfer_verbs, warnings, years, CameraOffset, ConflictPolicy, EmptyPolicy, ExifDateField, MultiplePeople, Options, Reflink, TimestampRounding, DEFAULT_COLLISION_SUFFIX, DEFAULT_FLATTEN_FORMAT, DEFAULT_RENAME_FORMAT};
 
 /// A tool to organize photos based on their metadata
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// The input directory containing photos and metadata files
-    #[arg(short, long)]
-    input: String,
+    /// The input directories containing photos and metadata files, or Takeout archives (.zip, .tgz
+    /// or .tar.gz) to read without extracting them first. Can be repeated or given several values
+    /// for the parts of an export, such as -i "Takeout 1" "Takeout 2"
+    #[arg(short, long, required = true, num_args = 1..)]
+    input: Vec<String>,
 
     /// The output directory where organized photos will be stored
     #[arg(short, long)]
     output: String,
+
+    /// Only log the planned destination of each file, without copying anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Compare the output with where the files of the input would be put, such as after moving
+    /// some of them by hand, listing the files that are in another folder or missing without
+    /// changing anything. A file is found by the name it would get and its content
+    #[arg(long)]
+    check: bool,
+
+    /// Go ahead without asking, however many files the input has
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Ask for confirmation before organizing more files than this, in case the input is the wrong
+    /// directory. Without a terminal to ask on, such as in a cron job, --yes is required instead
+    #[arg(long, default_value_t = 50_000)]
+    confirm_threshold: usize,
+
+    /// Stop with an error instead of a warning when the input has no photos, videos or metadata
+    /// files, which usually means a mistyped path
+    #[arg(long)]
+    strict: bool,
+
+    /// Move the files into the output directory instead of copying them
+    #[arg(long = "move")]
+    move_files: bool,
+
+    /// Give each output file the mode, owner and group of its input file, on Unix. Changing the
+    /// owner needs root, so it is warned about and skipped without it
+    #[arg(long)]
+    preserve_ownership: bool,
+
+    /// Once the files are organized, remove the directories of the input left empty, along with
+    /// the metadata files of the photos moved out of them, such as after --move
+    #[arg(long)]
+    prune_empty_dirs: bool,
+
+    /// Create hard links to the files instead of copying them. The file times are left untouched,
+    /// since they are shared with the input files
+    #[arg(long, conflicts_with = "move_files")]
+    hardlink: bool,
+
+    /// Create symbolic links to the files instead of copying them, such as for a browsable view of a
+    /// read-only archive. The file times are left untouched, since they belong to the input files
+    #[arg(long, conflicts_with_all = ["move_files", "hardlink"])]
+    symlink: bool,
+
+    /// Whether copies are made as reflinks, which are instant on copy-on-write filesystems such as
+    /// Btrfs and XFS: auto tries a reflink and copies the data when it fails, always fails instead,
+    /// and never always copies the data. Reflinks are only supported on Linux
+    #[arg(long, default_value = "auto")]
+    reflink: Reflink,
+
+    /// Compare the CRC-32 of each copied file with the one of its input, counting the files where
+    /// they differ as errors, such as when copying to a flaky USB drive or network share
+    #[arg(long)]
+    checksum: bool,
+
+    /// How many times a copy whose checksum differs is made again before giving up, with --checksum
+    #[arg(long, default_value_t = 0, requires = "checksum")]
+    checksum_retries: usize,
+
+    /// How many times copying, moving or linking a file and setting its times is tried again when
+    /// it fails with a transient error, such as a timeout of a network share. Missing files and
+    /// denied permissions are never retried
+    #[arg(long, default_value_t = 0)]
+    retries: usize,
+
+    /// How many milliseconds to wait before the first retry, doubling before each next one
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    retry_delay: u64,
+
+    /// The directory structure of the output, using the placeholders {year}, {month}, {month_name},
+    /// {day}, {weekday}, {season}, {ext} and {camera}, the EXIF make and model of the camera or
+    /// Unknown. Numeric placeholders can be zero-padded, as in {month:02}
+    #[arg(long, default_value = DEFAULT_PATTERN)]
+    pattern: Pattern,
+
+    /// Put the extension folder above the year and month, as in jpg/2021/March, which is the
+    /// pattern {ext}/{year}/{month_name}
+    #[arg(long, conflicts_with_all = ["pattern", "no_ext_folders"])]
+    ext_top: bool,
+
+    /// Put the files in a folder per camera, as in Camera/Canon EOS 80D/2021/March/jpg, from the
+    /// EXIF make and model of each photo. Files without them go in Camera/Unknown. The layout
+    /// inside each camera folder is the one of --pattern or --ext-top
+    #[arg(long)]
+    by_camera: bool,
+
+    /// How the {month_name} placeholder shows a month: name (March), number (03), which sorts in
+    /// order, or name-number (03-March)
+    #[arg(long, default_value = "name")]
+    month_format: MonthFormat,
+
+    /// The language of the month, weekday and season names, such as de or de_DE. English, German,
+    /// French, Spanish, Italian, Dutch and Portuguese are supported
+    #[arg(long, default_value = "en")]
+    locale: Locale,
+
+    /// Whose seasons the {season} placeholder follows, north or south. Seasons are meteorological,
+    /// so winter is December to February in the north, and summer in the south
+    #[arg(long, default_value = "north")]
+    hemisphere: Hemisphere,
+
+    /// Don't put the files in a folder per extension, leaving the {ext} placeholder of the pattern
+    /// empty. With the default pattern, this gives a year/month tree
+    #[arg(long)]
+    no_ext_folders: bool,
+
+    /// The folder of the {ext} placeholder for files without an extension, such as .profile or
+    /// README. A name that only ends with a dot, as in photo., has no extension either
+    #[arg(long, default_value = "no_ext", conflicts_with = "no_ext_folders")]
+    no_ext_dir: String,
+
+    /// Keep the case of the extension in the {ext} placeholder, as in JPG, instead of lowercasing it
+    #[arg(long, conflicts_with = "no_ext_folders")]
+    keep_ext_case: bool,
+
+    /// Give each spelling of an extension its own folder in the {ext} placeholder, instead of
+    /// putting .jpeg files in jpg, .tiff files in tif and .heif files in heic. The names of the
+    /// files always keep their extension
+    #[arg(long, conflicts_with = "no_ext_folders")]
+    no_normalize_ext: bool,
+
+    /// Use the album directory of each photo as its output directory instead of the date-based
+    /// layout. Photos directly in the input directory still use the date-based layout
+    #[arg(long)]
+    keep_albums: bool,
+
+    /// Organize the photos whose metadata has a location by a grid cell of a tenth of a degree, as in
+    /// -23.5_-46.6, instead of the date-based layout. Other photos still use the date-based layout
+    #[arg(long, conflicts_with_all = ["keep_albums", "flatten"])]
+    by_location: bool,
+
+    /// Put every file directly in the output directory, with its date prefixed to its name as in
+    /// 2021-03-14_153045_IMG_1234.jpg, instead of the date-based layout
+    #[arg(long, conflicts_with = "keep_albums")]
+    flatten: bool,
+
+    /// The format of the date prefixed to the file names with --flatten, using the strftime
+    /// specifiers of chrono
+    #[arg(long, default_value = DEFAULT_FLATTEN_FORMAT, value_parser = parse_flatten_format)]
+    flatten_format: String,
+
+    /// Rename each file to its date, keeping its extension, as in 20210314_153045.jpg. The format
+    /// uses the strftime specifiers of chrono, and files with the same date get a counter
+    #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_RENAME_FORMAT, value_parser = parse_flatten_format)]
+    rename_to_timestamp: Option<String>,
+
+    /// Also put each photo with people tagged in its metadata in People/<name>, as a hard link to
+    /// the organized photo, or a symbolic link with --symlink
+    #[arg(long)]
+    by_person: bool,
+
+    /// What --by-person does with a photo where several people are tagged: put it in the folder of
+    /// each of them, or skip it
+    #[arg(long, default_value = "each", requires = "by_person")]
+    multiple_people: MultiplePeople,
+
+    /// When both a photo and its edited version (such as IMG_1234-edited.jpg) exist, only process
+    /// the edited version
+    #[arg(long, conflicts_with = "prefer_original")]
+    prefer_edited: bool,
+
+    /// When both a photo and its edited version (such as IMG_1234-edited.jpg) exist, only process
+    /// the original
+    #[arg(long)]
+    prefer_original: bool,
+
+    /// Organize the video of a motion photo, such as PXL_1234.MP.mp4 next to PXL_1234.MP.jpg or the
+    /// IMG_1234.MOV of a Live Photo, with the date and in the folder of its still. Also available
+    /// as --resolve-live-photos
+    #[arg(long, alias = "resolve-live-photos")]
+    group_motion_photos: bool,
+
+    /// Write a JSON summary of the run to this file
+    #[arg(long)]
+    report: Option<String>,
+
+    /// Append a CSV row to this file for each input file, with its destination, the source of its
+    /// date, the date and what was done with it
+    #[arg(long)]
+    manifest: Option<String>,
+
+    /// Write a line of JSON to stdout for each file as it is processed, with its source and
+    /// destination paths, its date, the source of the date, what was done with it and any error.
+    /// Given a file, the lines are appended to it instead. The summary then goes to stderr
+    #[arg(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "-")]
+    emit_events: Option<String>,
+
+    /// Keep the parsed metadata files in this file, so that the next runs only parse the metadata
+    /// files whose size or modification time changed
+    #[arg(long)]
+    cache: Option<String>,
+
+    /// The number of threads used to process the files. 0 uses rayon's default of one thread per
+    /// CPU, and 1 processes the files sequentially
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
+    /// The most files open at once, for systems with a low limit of open files (ulimit -n). Each
+    /// file being organized counts twice, for itself and its output
+    #[arg(long)]
+    max_open_files: Option<usize>,
+
+    /// Skip files that are identical to a file already at their destination, instead of saving
+    /// them under a new name such as IMG_1234_1.jpg
+    #[arg(long)]
+    dedup: bool,
+
+    /// When two files have the same name, add the name of the folder the second one comes from, as in
+    /// IMG_0001__Vacation2019.jpg, before falling back to a counter
+    #[arg(long)]
+    disambiguate_by_source: bool,
+
+    /// The suffix added to the name of a file when it is taken by another file, where {n} is a
+    /// counter starting at 1, such as " ({n})" for IMG_0001 (1).jpg
+    #[arg(long, default_value = DEFAULT_COLLISION_SUFFIX, value_parser = parse_collision_suffix)]
+    collision_suffix: String,
+
+    /// The time zone the camera clocks were set to: an IANA name such as America/Sao_Paulo, read from
+    /// the time zone database of TZDIR or /usr/share/zoneinfo, a POSIX TZ rule such as
+    /// CET-1CEST,M3.5.0,M10.5.0/3, or an offset such as +02:00. The last two also work where there is
+    /// no database, as on Windows. EXIF dates are local times, so they are converted to UTC from this
+    /// zone unless the photo has an EXIF offset. Without it, they are used as is
+    #[arg(long)]
+    timezone: Option<Zone>,
+
+    /// When the creation time of a file without metadata or EXIF date is unavailable, put it in an
+    /// unsorted directory with its original name and times instead of using its modification time
+    #[arg(long)]
+    no_creation_fallback: bool,
+
+    /// Put every file without a reliable date in this folder of the output, with its original name
+    /// and times: files without metadata, EXIF date or date in their name whose creation time is
+    /// unavailable, and files dated on the first day of 1970, which comes from a zero timestamp
+    #[arg(long, value_parser = parse_unknown_dir)]
+    unknown_dir: Option<String>,
+
+    /// Only organize the files dated on this day or later, as YYYY-MM-DD. The date is checked once
+    /// it was found, whatever its source, and the other files are skipped
+    #[arg(long, value_parser = parse_day)]
+    since: Option<NaiveDate>,
+
+    /// Only organize the files dated on this day or earlier, as YYYY-MM-DD
+    #[arg(long, value_parser = parse_day)]
+    until: Option<NaiveDate>,
+
+    /// Also organize hidden files and folders, such as .DS_Store, and the junk of NAS and OS tools,
+    /// such as @eaDir folders and Thumbs.db, which are skipped by default
+    #[arg(long)]
+    no_skip_hidden: bool,
+
+    /// Follow the symbolic links of the input directory, such as links to folders on other drives.
+    /// Links leading back into the output or quarantine directory are not followed, and links that
+    /// loop back to one of their parent folders are skipped
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Only walk this many levels of folders below each input directory, where 1 only organizes
+    /// the files directly in it, such as to stay out of volumes mounted deep inside the input.
+    /// Archives are always read entirely
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Only organize the first N files found, such as to try options on a sample before a long
+    /// run. Archives are always read entirely
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Don't show the progress bar. It is only shown when stderr is a terminal
+    #[arg(long)]
+    no_progress: bool,
+
+    /// Log more details: -v for the file being processed, -vv for debugging and -vvv for tracing.
+    /// RUST_LOG overrides it when set
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Only log errors, leaving out the warnings
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Organize every file again, even the ones a previous run already put in the output directory.
+    /// Without it, a file is skipped when its destination has the same size and modification time.
+    /// The size of the files metadata is written into is listed in .takeout-exif-fix-rewrites in
+    /// the output directory, so that they are recognized too
+    #[arg(long)]
+    force: bool,
+
+    /// Write an XMP sidecar next to each output file with its date and location, for the files
+    /// whose EXIF data can't be changed such as RAW files and videos. It is named IMG_1234.xmp, or
+    /// IMG_1234.jpg.xmp when a file of the same folder named IMG_1234 sorts first, as IMG_1234.CR2
+    /// does, or a file of another folder already has that sidecar
+    #[arg(long)]
+    write_xmp: bool,
+
+    /// Check that the modification time of each output file is the one that was set, which some
+    /// network filesystems silently ignore, counting the files where it isn't as errors
+    #[arg(long)]
+    verify: bool,
+
+    /// Give each output file the modification and access times of its input file instead of the
+    /// date it was taken, which is still used to choose its folder
+    #[arg(long)]
+    keep_mtime: bool,
+
+    /// Truncate the times given to the output files to whole seconds, or to minutes with
+    /// --round-timestamps minute, so that sync tools see the same times whatever their source
+    #[arg(long, alias = "normalize-timestamps", value_name = "UNIT", num_args = 0..=1, default_missing_value = "second")]
+    round_timestamps: Option<TimestampRounding>,
+
+    /// Write the date from the metadata file of each JPEG photo into its DateTimeOriginal and
+    /// DateTimeDigitized EXIF tags, unless it already has a DateTimeOriginal tag
+    #[arg(long)]
+    embed_exif: bool,
+
+    /// Write the caption from the metadata file of each JPEG photo into its ImageDescription EXIF
+    /// tag, unless it already has one, and into the XMP sidecar with --write-xmp
+    #[arg(long)]
+    embed_description: bool,
+
+    /// Losslessly rotate or mirror each baseline JPEG whose EXIF orientation isn't upright, like
+    /// jpegtran -perfect, and set its orientation to 1, for viewers that ignore the tag. A JPEG
+    /// whose mirrored edge isn't a whole number of 8 or 16 pixel blocks is left as it is, with a warning
+    #[arg(long)]
+    auto_orient: bool,
+
+    /// Check that each JPEG starts with FFD8 and ends with FFD9 and that each PNG has the PNG
+    /// signature and an IEND chunk, putting the truncated ones in a corrupt folder of the output
+    #[arg(long)]
+    validate_images: bool,
+
+    /// Copy the files that fail to be organized to this directory, keeping their path relative to
+    /// the input directory, with a .error.txt file next to each describing what went wrong
+    #[arg(long)]
+    quarantine: Option<String>,
+
+    /// Only organize the files with these extensions, such as jpg,heic. This takes precedence over
+    /// --skip-ext
+    #[arg(long, value_delimiter = ',')]
+    only_ext: Vec<String>,
+
+    /// Don't organize the files with these extensions, such as aae,txt,ini, in addition to zip and
+    /// html files
+    #[arg(long, value_delimiter = ',')]
+    skip_ext: Vec<String>,
+
+    /// Leave out the paths matching the gitignore-style patterns of this file, relative to each
+    /// input directory. By default the .organizeignore file at the root of each input is used
+    #[arg(long)]
+    ignore_file: Option<PathBuf>,
+
+    /// Compare the date of the metadata file of each photo with its EXIF date, and warn when they
+    /// are more than this number of days apart
+    #[arg(long)]
+    disagreement_days: Option<u32>,
+
+    /// Which date to use when the metadata and EXIF dates disagree: json, exif or skip
+    #[arg(long, default_value = "json", requires = "disagreement_days")]
+    on_conflict: ConflictPolicy,
+
+    /// What to do with the files that have no content, such as the placeholders of failed exports:
+    /// skip them, put them in the empty directory of the output with empty-dir, or process them
+    #[arg(long, default_value = "skip")]
+    on_empty: EmptyPolicy,
+
+    /// Date each photo with its EXIF date when it has one, such as when the camera clock is more
+    /// trusted than the photoTakenTime of Google, using the metadata file only for the others
+    #[arg(long, conflicts_with = "disagreement_days")]
+    exif_first: bool,
+
+    /// A CSV file of filename,date rows giving the dates of files by name, such as scans without any
+    /// metadata, used before any other date. The date is a day such as 2001-06-30, a date and time
+    /// such as 2001-06-30 14:05:00, or an RFC 3339 date with an offset
+    #[arg(long)]
+    map_file: Option<String>,
+
+    /// Correct the clock of a camera, as MODEL:OFFSET such as "Canon EOS 5D:+3h" or
+    /// "DMC-FZ200:-1h30m". The offset is added to the EXIF dates of the photos whose EXIF Model
+    /// contains MODEL, ignoring case. Can be repeated
+    #[arg(long)]
+    camera_offset: Vec<CameraOffset>,
+
+    /// The EXIF date photos are organized by: original (DateTimeOriginal), digitized
+    /// (DateTimeDigitized, such as when a photo was scanned) or created (DateTime). The others are
+    /// used when a photo doesn't have it
+    #[arg(long, default_value = "original")]
+    exif_date_field: ExifDateField,
 }
 
-fn main() {
-    pretty_env_logger::init();
+impl Cli {
+    /// The log level asked for with `--verbose` and `--quiet`
+    fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Warn,
+            (false, 1) => LevelFilter::Info,
+            (false, 2) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+
+    /// The options of the organizer, from the command line arguments
+    fn options(&self) -> Options {
+        Options {
+            dry_run: self.dry_run || self.check,
+            move_files: self.move_files,
+            preserve_ownership: self.preserve_ownership,
+            prune_empty_dirs: self.prune_empty_dirs,
+            hardlink: self.hardlink,
+            symlink: self.symlink,
+            reflink: self.reflink,
+            checksum_retries: self.checksum.then_some(self.checksum_retries),
+            retries: self.retries,
+            retry_delay: std::time::Duration::from_millis(self.retry_delay),
+            pattern: {
+                let pattern = if self.ext_top {
+                    EXT_TOP_PATTERN.parse().expect("The --ext-top pattern is valid")
+                } else {
+                    self.pattern.clone()
+                };
+                if self.by_camera { pattern.under_camera() } else { pattern }
+            },
+            month_format: self.month_format,
+            hemisphere: self.hemisphere,
+            locale: self.locale,
+            no_ext_folders: self.no_ext_folders,
+            no_ext_dir: self.no_ext_dir.clone(),
+            keep_ext_case: self.keep_ext_case,
+            normalize_ext: !self.no_normalize_ext,
+            keep_albums: self.keep_albums,
+            by_location: self.by_location,
+            flatten: self.flatten,
+            flatten_format: self.flatten_format.clone(),
+            rename_to_timestamp: self.rename_to_timestamp.clone(),
+            by_person: self.by_person,
+            multiple_people: self.multiple_people,
+            prefer_edited: self.prefer_edited,
+            prefer_original: self.prefer_original,
+            group_motion_photos: self.group_motion_photos,
+            dedup: self.dedup,
+            disambiguate_by_source: self.disambiguate_by_source,
+            collision_suffix: self.collision_suffix.clone(),
+            force: self.force,
+            write_xmp: self.write_xmp,
+            verify: self.verify,
+            keep_mtime: self.keep_mtime,
+            round_timestamps: self.round_timestamps,
+            embed_exif: self.embed_exif,
+            embed_description: self.embed_description,
+            auto_orient: self.auto_orient,
+            validate_images: self.validate_images,
+            quarantine: self.quarantine.as_ref().map(PathBuf::from),
+            time_zone: self.timezone.clone(),
+            only_ext: self.only_ext.clone(),
+            skip_ext: self.skip_ext.clone(),
+            ignore_file: self.ignore_file.clone(),
+            follow_symlinks: self.follow_symlinks,
+            max_depth: self.max_depth,
+            limit: self.limit,
+            skip_hidden: !self.no_skip_hidden,
+            no_creation_fallback: self.no_creation_fallback,
+            unknown_dir: self.unknown_dir.clone(),
+            since: self.since,
+            until: self.until,
+            progress: !self.no_progress,
+            disagreement_days: self.disagreement_days.map(i64::from),
+            on_conflict: self.on_conflict,
+            on_empty: self.on_empty,
+            exif_first: self.exif_first,
+            camera_offsets: self.camera_offset.clone(),
+            exif_date_field: self.exif_date_field,
+            inputs: self.input.iter().map(PathBuf::from).collect(),
+            ..Options::new(&self.input[0], &self.output)
+        }
+    }
+}
 
+fn main() {
     let args = Cli::parse();
 
-    let input_directory = &args.input;
+    progress::init_logger(args.log_level());
+
     let output_directory = &args.output;
+    let archives: Vec<PathBuf> = args.input.iter().map(PathBuf::from).filter(|input| is_archive(input)).collect();
+    let input_directories: &[String] = if archives.is_empty() { &args.input } else { &[] };
+
+    for input in &args.input {
+        if !Path::new(input).exists() {
+            error!("Input does not exist: {}", input);
+            std::process::exit(1);
+        }
+    }
 
-    if !Path::new(input_directory).exists() {
-        error!("Input directory does not exist: {}", input_directory);
+    if !archives.is_empty() && archives.len() != args.input.len() {
+        error!("The inputs must be either directories or archives, not both");
         std::process::exit(1);
     }
 
+    // The files of a directory inside another input would be organized twice
+    let canonical_inputs: Vec<(&String, PathBuf)> = input_directories.iter()
+        .filter_map(|input| Some((input, fs::canonicalize(input).ok()?)))
+        .collect();
+    for (index, (input, canonical)) in canonical_inputs.iter().enumerate() {
+        if let Some((other, _)) = canonical_inputs[..index].iter().find(|(_, other)| canonical.starts_with(other) || other.starts_with(canonical)) {
+            error!("Input directories {} and {} must not be the same or inside each other", other, input);
+            std::process::exit(1);
+        }
+    }
+
     if !Path::new(output_directory).exists() {
         error!("Output directory does not exist: {}", output_directory);
         std::process::exit(1);
     }
 
-    log::info!("Starting the photo organizer...");
-
-    let metadata_map = parse_metadata_files(input_directory);
-    process_directory_parallel(input_directory, output_directory, &metadata_map);
-}
+    // The output would be walked again as part of the input
+    for (input_directory, input) in &canonical_inputs {
+        if let Ok(output) = fs::canonicalize(output_directory)
+            && output.starts_with(input)
+        {
+            error!("Output directory {} must not be the input directory {} or inside it", output_directory, input_directory);
+            std::process::exit(1);
+        }
+    }
 
-/// Parse all metadata files and store relevant information in a HashMap
-fn parse_metadata_files(directory: &str) -> HashMap<String, chrono::DateTime<Utc>> {
-    let metadata_map = std::sync::Mutex::new(HashMap::new());
-
-    WalkDir::new(directory)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|entry| entry.path().extension().and_then(|ext| ext.to_str()) == Some("json"))
-        .par_bridge() // Parallelize the iterator
-        .for_each(|entry| {
-            let path = entry.path();
-            if let Ok(mut file) = File::open(path) {
-                let mut contents = String::new();
-                if file.read_to_string(&mut contents).is_ok() {
-                    if let Ok(metadata) = serde_json::from_str::<Value>(&contents) {
-                        if let Some(photo_filename) = metadata["title"].as_str() {
-                            if let Some(photo_taken_timestamp) = metadata["photoTakenTime"]["timestamp"].as_str() {
-                                if let Ok(timestamp) = photo_taken_timestamp.parse::<i64>() {
-                                    if let Some(parsed_time) = DateTime::from_timestamp(timestamp, 0) {
-                                        let mut metadata_map = metadata_map.lock().unwrap();
-                                        metadata_map.insert(photo_filename.to_string(), parsed_time);
-                                    } else {
-                                        error!("Failed to parse timestamp for file: {}", photo_filename);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    if let Some(quarantine_directory) = &args.quarantine {
+        if !args.dry_run
+            && let Err(e) = fs::create_dir_all(quarantine_directory)
+        {
+            error!("Failed to create the quarantine directory {}: {}", quarantine_directory, e);
+            std::process::exit(1);
+        }
+        // The quarantined files would be walked again as part of the input
+        for (input_directory, input) in &canonical_inputs {
+            if let Ok(quarantine) = fs::canonicalize(quarantine_directory)
+                && quarantine.starts_with(input)
+            {
+                error!("Quarantine directory {} must not be inside the input directory {}", quarantine_directory, input_directory);
+                std::process::exit(1);
             }
-        });
+        }
+    }
 
-    std::sync::Mutex::into_inner(metadata_map).unwrap()
-}
+    if let (Some(since), Some(until)) = (args.since, args.until)
+        && since > until
+    {
+        error!("--since {} must not be after --until {}", since, until);
+        std::process::exit(1);
+    }
 
-/// Process the directory and organize photos based on metadata or EXIF data
-fn process_directory_parallel(directory: &str, output_directory: &str, metadata_map: &HashMap<String, chrono::DateTime<Utc>>) {
-    WalkDir::new(directory)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|entry| entry.path().is_file())
-        .filter(|entry| entry.path().extension().and_then(|ext| ext.to_str()) != Some("json"))
-        .filter(|entry| entry.path().extension().and_then(|ext| ext.to_str()) != Some("zip"))
-        .filter(|entry| entry.path().extension().and_then(|ext| ext.to_str()) != Some("html"))
-        .par_bridge() // Parallelize the iterator
-        .for_each(|entry| {
-            let path = entry.path();
-            if let Some(filename) = path.file_name().and_then(|name| name.to_str()) {
-                if let Some(&parsed_time) = metadata_map.get(filename) {
-                    info!("Processing photo file {:?} using metadata timestamp: {}", path, parsed_time);
-                    // Process the photo using metadata
-                    if let Err(e) = organize_and_update_file(path, parsed_time, output_directory) {
-                        error!("Error processing photo file {:?}: {}", path, e);
-                    }
-                } else {
-                    // Process the photo using EXIF data
-                    info!("Processing photo file {:?} using EXIF data", path);
-                    if let Err(e) = process_photo_file(path, output_directory) {
-                        error!("Error processing photo file {:?}: {}", path, e);
-                    }
-                }
-            }
-        });
-}
+    if !archives.is_empty() && (args.hardlink || args.symlink) {
+        error!("The files inside archives can't be linked to, they can only be extracted");
+        std::process::exit(1);
+    }
+
+    if !archives.is_empty() && args.prune_empty_dirs {
+        warn!("Archives have no directories to prune, --prune-empty-dirs is ignored");
+    }
+
+    if !archives.is_empty() && args.cache.is_some() {
+        warn!("The metadata cache is not used with archives");
+    }
+
+    if args.hardlink {
+        warn!("Hard links share their times with the input files, so the file times will not be updated");
+    }
+
+    if args.symlink {
+        warn!("Symbolic links point to the input files, so the file times will not be updated");
+    }
+
+    if args.threads > 0
+        && let Err(e) = rayon::ThreadPoolBuilder::new().num_threads(args.threads).build_global()
+    {
+        error!("Failed to set up {} threads: {}", args.threads, e);
+        std::process::exit(1);
+    }
+
+    if let Some(max_open_files) = args.max_open_files {
+        open_files::set_limit(max_open_files);
+    }
 
-/// Process a photo file using EXIF metadata
-fn process_photo_file(photo_path: &Path, output_directory: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let file = File::open(photo_path)?;
-    let mut bufreader = std::io::BufReader::new(file);
-
-    if let Ok(exif) = exif::Reader::new().read_from_container(&mut bufreader) {
-        if let Some(field) = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY) {
-            info!("Found EXIF DateTimeOriginal field in {:?}", photo_path);
-            let date_time_original = field.display_value().to_string();
-            debug!("EXIF DateTimeOriginal: {}", date_time_original);
-            if let Ok(parsed_time) = NaiveDateTime::parse_from_str(&date_time_original, "%Y-%m-%d %H:%M:%S") {
-                // Convert to UTC
-                let parsed_time_utc = Utc.from_local_datetime(&parsed_time).unwrap();
-                organize_and_update_file(photo_path, parsed_time_utc, output_directory)?;
-            } else {
-                warn!("Failed to parse EXIF DateTimeOriginal for file: {:?}", photo_path);
-                process_photo_file_with_creation_time(photo_path, output_directory)?;
+    log::info!("Starting the photo organizer...");
+
+    let mut options = args.options();
+    if let Some(map_file) = &args.map_file {
+        match date_map::load(Path::new(map_file), options.time_zone.as_ref()) {
+            Ok(date_map) => {
+                info!("Loaded the dates of {} files from {}", date_map.len(), map_file);
+                options.date_map = date_map;
+            }
+            Err(e) => {
+                error!("Invalid map file: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(ignore_file) = &options.ignore_file
+        && let Err(e) = IgnoreRules::load(ignore_file)
+    {
+        error!("Failed to read the ignore file {}: {}", ignore_file.display(), e);
+        std::process::exit(1);
+    }
+    if archives.is_empty() && inputs_are_empty(&options) {
+        let inputs: Vec<String> = options.inputs.iter().map(|input| input.display().to_string()).collect();
+        let message = format!("No photos, videos or metadata files were found in {}, is the input path right?", inputs.join(", "));
+        if args.strict {
+            error!("{}", message);
+            std::process::exit(1);
+        }
+        warn!("{}", message);
+    }
+    if !options.dry_run && !args.yes && archives.is_empty() {
+        options.candidate_count = Some(confirm_large_input(&options, args.confirm_threshold));
+    }
+    // Installed once nothing is left to ask, so that Ctrl-C still answers the confirmation
+    interrupt::install_handler();
+    if let Some(manifest_path) = &args.manifest
+        && let Err(e) = manifest::start(Path::new(manifest_path))
+    {
+        error!("Failed to open the manifest {}: {}", manifest_path, e);
+        std::process::exit(1);
+    }
+    let events_on_stdout = args.emit_events.as_deref() == Some("-");
+    if let Some(events_path) = &args.emit_events
+        && let Err(e) = events::start(Some(Path::new(events_path)).filter(|_| !events_on_stdout))
+    {
+        error!("Failed to open the events file {}: {}", events_path, e);
+        std::process::exit(1);
+    }
+    if args.check {
+        check::start(Path::new(output_directory));
+    }
+    let report = if archives.is_empty() {
+        let metadata_map = match &args.cache {
+            Some(cache_path) => {
+                let mut cache = MetadataCache::load(Path::new(cache_path));
+                let metadata_map = parse_metadata_files_cached(&options, &mut cache);
+                // An interrupted parse would leave out the entries of the files it didn't get to
+                if !interrupt::requested()
+                    && let Err(e) = cache.write(Path::new(cache_path))
+                {
+                    warn!("Failed to write the metadata cache to {}: {}", cache_path, e);
+                }
+                metadata_map
             }
-        } else {
-            warn!("No EXIF DateTimeOriginal field found in {:?}", photo_path);
-            process_photo_file_with_creation_time(photo_path, output_directory)?;
+            None => parse_metadata_files(&options),
+        };
+        let report = process_directory_parallel(&metadata_map, &options);
+        if options.prune_empty_dirs {
+            let removed = prune::prune_empty_dirs(&options.inputs, options.dry_run);
+            info!("{} {} empty directories of the input", if options.dry_run { "Would remove" } else { "Removed" }, removed);
         }
+        report
     } else {
-        warn!("No EXIF metadata found in {:?}", photo_path);
-        process_photo_file_with_creation_time(photo_path, output_directory)?;
+        process_archives(&archives, &options)
+    };
+    if let Err(e) = manifest::finish() {
+        error!("Failed to write the manifest: {}", e);
+    }
+    if let Err(e) = events::finish() {
+        error!("Failed to write the events: {}", e);
     }
 
-    Ok(())
-}
-
-/// Fallback: Process a photo file using its creation timestamp if no metadata or EXIF data is available
-fn process_photo_file_with_creation_time(photo_path: &Path, output_directory: &str) -> Result<(), Box<dyn std::error::Error>> {
-    use std::fs::metadata;
-    let meta = metadata(photo_path)?;
-    let created = meta.created().or_else(|_| meta.modified())?;
-    let datetime: chrono::DateTime<Utc> = created.into();
-    info!("Using file creation/modification time for {:?}", photo_path);
-    organize_and_update_file(photo_path, datetime, output_directory)
-}
+    // The summary stays out of the events when they go to stdout
+    let print = |text: String| if events_on_stdout { eprintln!("{}", text) } else { println!("{}", text) };
+    if let Some(digest) = warnings::digest() {
+        print(digest);
+    }
+    print(report.summary());
+    if let Some(table) = years::table() {
+        print(table);
+    }
+    if let Some(summary) = check::finish() {
+        print(summary);
+    }
 
-/// A helper function to find a unique filename
-/// This function appends a counter to the original filename. The counter is incremented until a unique filename is found.
-fn find_unique_filename(base_dir: &Path, original_path: &Path, reserved_paths: &MutexGuard<HashSet<String>>) -> std::path::PathBuf {
-    let mut counter = 1;
-    loop {
-        let file_stem = original_path.file_stem()
-            .unwrap_or_else(|| std::ffi::OsStr::new("unnamed_file"));
-        let extension = original_path.extension()
-            .unwrap_or_else(|| std::ffi::OsStr::new(""));
-        
-        let new_file_name = if extension.is_empty() {
-            format!("{}_{}", file_stem.to_string_lossy(), counter)
-        } else {
-            format!("{}_{}.{}", file_stem.to_string_lossy(), counter, extension.to_string_lossy())
-        };
+    if options.dry_run && !args.check {
+        let (_, action) = transfer_verbs(&options);
+        info!("Dry run finished: {} files would have been {}", report.organized(), action);
+    }
 
-        // If the new file name is not in reserved paths and does not exist, return it
-        if !reserved_paths.contains(base_dir.join(&new_file_name).to_string_lossy().as_ref()) && !base_dir.join(&new_file_name).exists() {
-            return base_dir.join(new_file_name);
+    if let Some(report_path) = &args.report {
+        if let Err(e) = report.write(Path::new(report_path)) {
+            error!("Failed to write the report to {}: {}", report_path, e);
+            std::process::exit(1);
         }
-        counter += 1;
+        info!("Report written to {}", report_path);
     }
-}
 
-/// A function to get a unique filename to output the photo
-/// This function ensures that no two threads write to the same file simultaneously
-/// by using a mutex to lock the reserved paths during the check and insert operation.
-/// First, it locks the reserved paths set, checks if the desired output path is already reserved or exists,
-/// and if not, it reserves the path by inserting it into the set.
-/// If the path is already reserved or exists, it tries again until a unique path is found.
-/// Finally, it releases the lock before performing the file copy operation.
-fn get_output_path(photo_path: &Path, target_dir: &Path) -> std::path::PathBuf {
-     let mut reserved_paths = MUTEX
-            .get_or_init(|| Arc::new(Mutex::new(HashSet::new())))
-            .lock()
-            .unwrap();
-    let mut output_path = target_dir.join(photo_path.file_name().unwrap_or_else(|| std::ffi::OsStr::new("unnamed_file")));
-    loop {
-        if !reserved_paths.contains(output_path.to_string_lossy().as_ref()) && !output_path.exists() {
-            reserved_paths.insert(output_path.to_string_lossy().to_string());
-            break;
-        }
-        output_path = find_unique_filename(target_dir, photo_path, &reserved_paths);
+    if report.interrupted {
+        warn!("Interrupted, the remaining files were not processed");
+        // The usual exit code of a process stopped by SIGINT
+        std::process::exit(130);
     }
-    output_path
 }
 
-/// Organize and update the file based on the parsed time
-fn organize_and_update_file(photo_path: &Path, parsed_time: chrono::DateTime<Utc>, output_directory: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let year = parsed_time.year();
-    let month = parsed_time.month();
-
-    let month_name = match month {
-        1 => "January",
-        2 => "February",
-        3 => "March",
-        4 => "April",
-        5 => "May",
-        6 => "June",
-        7 => "July",
-        8 => "August",
-        9 => "September",
-        10 => "October",
-        11 => "November",
-        12 => "December",
-        _ => "Unknown",
-    };
-
-    let year_dir = Path::new(output_directory).join(year.to_string());
-    let month_dir = year_dir.join(month_name);
-    fs::create_dir_all(&month_dir)?;
+/// Ask whether to go on when the input has more files than the threshold, exiting if not
+/// Without a terminal on stdin the answer can't be asked for, so the run stops unless `--yes` was
+/// given, which skips this check. Returns the number of files of the input.
+fn confirm_large_input(options: &Options, threshold: usize) -> usize {
+    let count = count_candidate_files(options);
+    if count <= threshold {
+        return count;
+    }
+    let resolve = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let inputs: Vec<String> = options.inputs.iter().map(|input| resolve(input).display().to_string()).collect();
+    eprintln!("About to organize {} files from {} into {}", count, inputs.join(", "), resolve(&options.output).display());
+    if !std::io::stdin().is_terminal() {
+        error!("More than {} files to organize and no terminal to confirm on, pass --yes to go ahead", threshold);
+        std::process::exit(1);
+    }
+    eprint!("Continue? [y/N] ");
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() || !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        info!("Nothing was organized");
+        std::process::exit(1);
+    }
+    count
+}
 
-    let target_dir = if let Some(extension) = photo_path.extension().and_then(|ext| ext.to_str()) {
-        month_dir.join(extension.to_lowercase())
-    } else {
-        month_dir.join("no_ext")
-    };
-    fs::create_dir_all(&target_dir)?;
+/// Check that a date format is valid and produces a file name rather than a path
+fn parse_flatten_format(format: &str) -> Result<String, String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid date format {}", format));
+    }
+    if format.contains('/') {
+        return Err(format!("date format {} must not contain '/'", format));
+    }
+    Ok(format.to_string())
+}
 
-    let output_path = get_output_path(photo_path, &target_dir);
+/// Check that a collision suffix has the {n} placeholder of the counter and stays in the file name
+fn parse_collision_suffix(suffix: &str) -> Result<String, String> {
+    if !suffix.contains("{n}") {
+        return Err(format!("suffix {} must contain the {{n}} placeholder", suffix));
+    }
+    if suffix.contains(['/', '\\']) {
+        return Err(format!("suffix {} must not contain '/'", suffix));
+    }
+    Ok(suffix.to_string())
+}
 
-    fs::copy(photo_path, &output_path)?;
+/// Check that the name of the folder of the files without a date is a single folder name
+fn parse_unknown_dir(name: &str) -> Result<String, String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(format!("{} must be the name of a folder, without '/'", name));
+    }
+    Ok(name.to_string())
+}
 
-    let unix_timestamp = parsed_time.timestamp();
-    let file_time = FileTime::from_unix_time(unix_timestamp, 0);
-    set_file_times(&output_path, file_time, file_time)?;
+/// Parse a day given as YYYY-MM-DD
+fn parse_day(day: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(day, "%Y-%m-%d").map_err(|e| format!("invalid date {} ({}), expected YYYY-MM-DD", day, e))
+}
 
-    Ok(())
-}
\ No newline at end of file
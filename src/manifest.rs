@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{lock, DateSource};
+
+/// The first row of a new manifest
+const HEADER: &str = "source,destination,date_source,timestamp,action";
+
+/// The manifest being written, if any
+/// It is a CSV file listing where each input file went, for auditing a run.
+static ACTIVE: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+
+/// Where each file being processed was put, and with which date
+type Destinations = HashMap<PathBuf, (PathBuf, Option<DateTime<Utc>>)>;
+
+/// The destination and date of the files being processed, until their row or event is written,
+/// if the manifest or the events are written
+static DESTINATIONS: Mutex<Option<Destinations>> = Mutex::new(None);
+
+/// Quote a CSV field if it contains a separator, a quote or a line break
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Start appending rows to a manifest, writing its header first if the file is new or empty
+pub fn start(path: &Path) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = BufWriter::new(file);
+    if writer.get_ref().metadata()?.len() == 0 {
+        writeln!(writer, "{}", HEADER)?;
+    }
+    track_destinations();
+    *lock(&ACTIVE) = Some(writer);
+    Ok(())
+}
+
+/// Start remembering the destination of each file, for its row or event
+pub fn track_destinations() {
+    lock(&DESTINATIONS).get_or_insert_with(HashMap::new);
+}
+
+/// Remember where a file is put and with which date, for its row
+pub fn record_destination(photo_path: &Path, output_path: &Path, taken_time: Option<DateTime<Utc>>) {
+    if let Some(destinations) = lock(&DESTINATIONS).as_mut() {
+        destinations.insert(photo_path.to_path_buf(), (output_path.to_path_buf(), taken_time));
+    }
+}
+
+/// Forget where a file was put once its row and event are written, returning its destination and date
+pub fn take_destination(photo_path: &Path) -> (Option<PathBuf>, Option<DateTime<Utc>>) {
+    let destination = lock(&DESTINATIONS).as_mut().and_then(|destinations| destinations.remove(photo_path));
+    match destination {
+        Some((destination, taken_time)) => (Some(destination), taken_time),
+        None => (None, None),
+    }
+}
+
+/// Write the row of a file once it has been processed
+/// The action is what happened to it, such as `copied`, `skipped` or `error`
+pub fn record(
+    photo_path: &Path,
+    destination: Option<&Path>,
+    taken_time: Option<DateTime<Utc>>,
+    source: Option<DateSource>,
+    action: &str,
+) -> std::io::Result<()> {
+    let mut active = lock(&ACTIVE);
+    let Some(writer) = active.as_mut() else {
+        return Ok(());
+    };
+    let row = [
+        photo_path.to_string_lossy().to_string(),
+        destination.map(|destination| destination.to_string_lossy().to_string()).unwrap_or_default(),
+        source.map(|source| source.name().to_string()).unwrap_or_default(),
+        taken_time.map(|time| time.to_rfc3339_opts(SecondsFormat::AutoSi, true)).unwrap_or_default(),
+        action.to_string(),
+    ];
+    let row: Vec<String> = row.iter().map(|field| escape(field)).collect();
+    writeln!(writer, "{}", row.join(","))
+}
+
+/// Whether a destination was recorded for a file, meaning it was put somewhere
+pub fn has_destination(photo_path: &Path) -> bool {
+    lock(&DESTINATIONS).as_ref().is_some_and(|destinations| destinations.contains_key(photo_path))
+}
+
+/// Flush the manifest and stop writing to it
+pub fn finish() -> std::io::Result<()> {
+    match lock(&ACTIVE).take() {
+        Some(mut writer) => writer.flush(),
+        None => Ok(()),
+    }
+}
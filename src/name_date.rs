@@ -0,0 +1,145 @@
+use std::path::Path;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+
+use crate::{local_time_to_utc, Options};
+
+/// The years a date in a file name may have, to avoid taking any long number for a date
+const YEARS: std::ops::RangeInclusive<i32> = 1990..=2100;
+
+/// The separators allowed between the year, month and day of a date
+const DATE_SEPARATORS: &[u8] = b"-_.";
+
+/// The separators allowed between the date and the time
+const DATE_TIME_SEPARATORS: &[u8] = b"_- T.";
+
+/// The separators allowed between the hours, minutes and seconds of a time
+const TIME_SEPARATORS: &[u8] = b".:-";
+
+/// A reader over the bytes of a file name
+struct Cursor<'a> {
+    name: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    /// Read a number of exactly `len` digits
+    fn number(&mut self, len: usize) -> Option<u32> {
+        let digits = self.name.get(self.pos..self.pos + len)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        self.pos += len;
+        Some(digits.iter().fold(0, |value, digit| value * 10 + (digit - b'0') as u32))
+    }
+
+    /// Read one of the separators, if the next byte is one
+    fn separator(&mut self, separators: &[u8]) -> Option<u8> {
+        let separator = *self.name.get(self.pos).filter(|byte| separators.contains(byte))?;
+        self.pos += 1;
+        Some(separator)
+    }
+
+    /// Read the given separator, or nothing if there is none
+    fn same_separator(&mut self, separator: Option<u8>) -> Option<()> {
+        match separator {
+            Some(separator) if self.name.get(self.pos) == Some(&separator) => self.pos += 1,
+            Some(_) => return None,
+            None => {}
+        }
+        Some(())
+    }
+
+    fn at_digit(&self) -> bool {
+        self.name.get(self.pos).is_some_and(u8::is_ascii_digit)
+    }
+}
+
+/// Find the date in the name of a file, such as `IMG_20210314_153045.jpg`,
+/// `2021-03-14 15.30.45.png`, `Screenshot_20210314-153045.png`, `PXL_20210314_153045123.jpg` or
+/// `IMG-20210314-WA0001.jpg`, for which the time is midnight
+/// The date is a local time like the EXIF dates, except for names that are a Unix time in milliseconds.
+pub fn date_in_name(path: &Path, options: &Options) -> Option<DateTime<Utc>> {
+    let stem = path.file_stem()?.to_str()?;
+    if stem.len() == 13 && stem.bytes().all(|byte| byte.is_ascii_digit()) {
+        let time = DateTime::from_timestamp_millis(stem.parse().ok()?)?;
+        return YEARS.contains(&time.year()).then_some(time);
+    }
+
+    let name = stem.as_bytes();
+    let local_time = (0..name.len())
+        .filter(|&start| name[start].is_ascii_digit() && (start == 0 || !name[start - 1].is_ascii_digit()))
+        .find_map(|start| date_at(&mut Cursor { name, pos: start }))?;
+    match &options.time_zone {
+        Some(zone) => local_time_to_utc(zone, local_time, path),
+        None => local_time_to_utc(&Utc, local_time, path),
+    }
+}
+
+/// Read a date starting at the cursor, followed by a time if there is one
+fn date_at(cursor: &mut Cursor) -> Option<NaiveDateTime> {
+    let year = cursor.number(4)? as i32;
+    let separator = cursor.separator(DATE_SEPARATORS);
+    let month = cursor.number(2)?;
+    cursor.same_separator(separator)?;
+    let day = cursor.number(2)?;
+    // A longer number is not a date
+    if cursor.at_digit() || !YEARS.contains(&year) {
+        return None;
+    }
+    let date = NaiveDate::from_ymd_opt(year, month, day)?;
+    let time = time_at(cursor).unwrap_or(NaiveTime::MIN);
+    Some(date.and_time(time))
+}
+
+/// Read the time following a date, with its milliseconds if they follow the seconds directly
+fn time_at(cursor: &mut Cursor) -> Option<NaiveTime> {
+    cursor.separator(DATE_TIME_SEPARATORS)?;
+    let hour = cursor.number(2)?;
+    let separator = cursor.separator(TIME_SEPARATORS);
+    let minute = cursor.number(2)?;
+    cursor.same_separator(separator)?;
+    let second = cursor.number(2)?;
+    let milli = if separator.is_none() { cursor.number(3).unwrap_or(0) } else { 0 };
+    if cursor.at_digit() {
+        return None;
+    }
+    NaiveTime::from_hms_milli_opt(hour, minute, second, milli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn date(name: &str) -> Option<DateTime<Utc>> {
+        date_in_name(Path::new(name), &Options::new("input", "output"))
+    }
+
+    #[test]
+    fn finds_dates_and_times_in_names() {
+        let time = Utc.with_ymd_and_hms(2021, 3, 14, 15, 30, 45).unwrap();
+        assert_eq!(date("IMG_20210314_153045.jpg"), Some(time));
+        assert_eq!(date("2021-03-14 15.30.45.png"), Some(time));
+        assert_eq!(date("Screenshot_20210314-153045.png"), Some(time));
+        assert_eq!(date("PXL_20210314_153045123.jpg"), Some(time + chrono::Duration::milliseconds(123)));
+    }
+
+    #[test]
+    fn uses_midnight_for_names_without_a_time() {
+        assert_eq!(date("IMG-20210314-WA0001.jpg"), Some(Utc.with_ymd_and_hms(2021, 3, 14, 0, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn reads_unix_times_in_milliseconds() {
+        assert_eq!(date("1615735845123.jpg"), DateTime::from_timestamp_millis(1_615_735_845_123));
+    }
+
+    #[test]
+    fn ignores_numbers_that_are_not_dates() {
+        assert_eq!(date("IMG_0001.jpg"), None);
+        assert_eq!(date("IMG_202103145.jpg"), None);
+        assert_eq!(date("IMG_20211314_153045.jpg"), None);
+        assert_eq!(date("IMG_18500314.jpg"), None);
+        assert_eq!(date("2021-03_14.jpg"), None);
+    }
+}
@@ -0,0 +1,144 @@
+use std::borrow::Cow;
+
+mod tables;
+
+use tables::{COMBINING_CLASSES, COMPOSITIONS, DECOMPOSITIONS};
+
+/// The first Hangul syllable and jamo, which compose and decompose by arithmetic
+const SYLLABLE_BASE: u32 = 0xAC00;
+const LEADING_BASE: u32 = 0x1100;
+const VOWEL_BASE: u32 = 0x1161;
+const TRAILING_BASE: u32 = 0x11A7;
+const LEADING_COUNT: u32 = 19;
+const VOWEL_COUNT: u32 = 21;
+const TRAILING_COUNT: u32 = 28;
+const SYLLABLE_COUNT: u32 = LEADING_COUNT * VOWEL_COUNT * TRAILING_COUNT;
+
+/// Put a string in Unicode Normalization Form C, where accented letters are single characters
+/// macOS stores file names decomposed, with the accents as separate combining characters, while
+/// Google writes titles composed, so both are normalized before being compared.
+pub fn nfc(text: &str) -> Cow<'_, str> {
+    if text.is_ascii() {
+        return Cow::Borrowed(text);
+    }
+    let mut decomposed = Vec::with_capacity(text.len());
+    for character in text.chars() {
+        decompose(character as u32, &mut decomposed);
+    }
+    reorder(&mut decomposed);
+    let composed: String = compose(&decomposed).into_iter().filter_map(char::from_u32).collect();
+    if composed == text { Cow::Borrowed(text) } else { Cow::Owned(composed) }
+}
+
+/// Append the full canonical decomposition of a character
+fn decompose(character: u32, output: &mut Vec<u32>) {
+    if (SYLLABLE_BASE..SYLLABLE_BASE + SYLLABLE_COUNT).contains(&character) {
+        let index = character - SYLLABLE_BASE;
+        output.push(LEADING_BASE + index / (VOWEL_COUNT * TRAILING_COUNT));
+        output.push(VOWEL_BASE + index % (VOWEL_COUNT * TRAILING_COUNT) / TRAILING_COUNT);
+        if !index.is_multiple_of(TRAILING_COUNT) {
+            output.push(TRAILING_BASE + index % TRAILING_COUNT);
+        }
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&character, |&(composed, _, _)| composed) {
+        Ok(index) => {
+            let (_, first, second) = DECOMPOSITIONS[index];
+            decompose(first, output);
+            if second != 0 {
+                decompose(second, output);
+            }
+        }
+        Err(_) => output.push(character),
+    }
+}
+
+/// The canonical combining class of a character, 0 for the starters that other characters combine with
+fn combining_class(character: u32) -> u8 {
+    let index = COMBINING_CLASSES.partition_point(|&(_, last, _)| last < character);
+    match COMBINING_CLASSES.get(index) {
+        Some(&(first, _, class)) if first <= character => class,
+        _ => 0,
+    }
+}
+
+/// Sort each run of combining characters by their class, keeping the order of the ones of the same class
+fn reorder(characters: &mut [u32]) {
+    let mut start = 0;
+    while start < characters.len() {
+        let end = characters[start..].iter()
+            .position(|&character| combining_class(character) == 0)
+            .map_or(characters.len(), |offset| start + offset);
+        characters[start..end].sort_by_key(|&character| combining_class(character));
+        start = end + 1;
+    }
+}
+
+/// The character that two characters compose into, if any
+fn compose_pair(first: u32, second: u32) -> Option<u32> {
+    if (LEADING_BASE..LEADING_BASE + LEADING_COUNT).contains(&first) && (VOWEL_BASE..VOWEL_BASE + VOWEL_COUNT).contains(&second) {
+        let syllable = ((first - LEADING_BASE) * VOWEL_COUNT + second - VOWEL_BASE) * TRAILING_COUNT;
+        return Some(SYLLABLE_BASE + syllable);
+    }
+    if (SYLLABLE_BASE..SYLLABLE_BASE + SYLLABLE_COUNT).contains(&first)
+        && (first - SYLLABLE_BASE).is_multiple_of(TRAILING_COUNT)
+        && (TRAILING_BASE + 1..TRAILING_BASE + TRAILING_COUNT).contains(&second)
+    {
+        return Some(first + second - TRAILING_BASE);
+    }
+    COMPOSITIONS.binary_search_by_key(&(first, second), |&(first, second, _)| (first, second))
+        .ok()
+        .map(|index| COMPOSITIONS[index].2)
+}
+
+/// Compose each starter with the characters after it that aren't blocked from it by another
+/// character of the same or a higher class
+fn compose(characters: &[u32]) -> Vec<u32> {
+    let mut output: Vec<u32> = Vec::with_capacity(characters.len());
+    let mut starter = None;
+    let mut last_class = 0;
+    for &character in characters {
+        let class = combining_class(character);
+        if let Some(starter) = starter
+            && (output.len() == starter + 1 || last_class < class)
+            && let Some(composed) = compose_pair(output[starter], character)
+        {
+            output[starter] = composed;
+            continue;
+        }
+        if class == 0 {
+            starter = Some(output.len());
+        }
+        last_class = class;
+        output.push(character);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_ascii_and_composed_text() {
+        assert!(matches!(nfc("IMG_0001.jpg"), Cow::Borrowed(_)));
+        assert!(matches!(nfc("Café.jpg"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn composes_accents() {
+        assert_eq!(nfc("Cafe\u{301}.jpg"), "Caf\u{e9}.jpg");
+        assert_eq!(nfc("A\u{30a}ngstro\u{308}m"), "\u{c5}ngstr\u{f6}m");
+    }
+
+    #[test]
+    fn orders_combining_characters_by_class() {
+        // The dot below has a lower class than the dot above, so it composes first
+        assert_eq!(nfc("s\u{307}\u{323}"), "\u{1e69}");
+    }
+
+    #[test]
+    fn composes_hangul() {
+        assert_eq!(nfc("\u{1112}\u{1161}\u{11ab}"), "\u{d55c}");
+    }
+}
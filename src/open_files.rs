@@ -0,0 +1,53 @@
+use std::sync::{Condvar, Mutex, PoisonError};
+
+use crate::lock;
+
+/// The limit of open files and how many may still be opened, if there is a limit
+static LIMIT: Mutex<Option<Limit>> = Mutex::new(None);
+
+/// Signaled when files are closed
+static RELEASED: Condvar = Condvar::new();
+
+struct Limit {
+    total: usize,
+    available: usize,
+}
+
+/// The right to keep some files open, given back when dropped
+pub struct Permit {
+    count: usize,
+}
+
+/// Limit how many files are open at once across all threads, such as to stay under a low ulimit
+pub fn set_limit(total: usize) {
+    let total = total.max(1);
+    *lock(&LIMIT) = Some(Limit { total, available: total });
+}
+
+/// Wait until the given number of files may be opened, returning right away when there is no limit
+/// Asking for more files than the limit waits for all of them instead.
+pub fn acquire(count: usize) -> Permit {
+    let mut limit = lock(&LIMIT);
+    let Some(count) = limit.as_ref().map(|limit| count.min(limit.total)) else {
+        return Permit { count: 0 };
+    };
+    while limit.as_ref().is_some_and(|limit| limit.available < count) {
+        limit = RELEASED.wait(limit).unwrap_or_else(PoisonError::into_inner);
+    }
+    if let Some(limit) = limit.as_mut() {
+        limit.available -= count;
+    }
+    Permit { count }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+        if let Some(limit) = lock(&LIMIT).as_mut() {
+            limit.available += self.count;
+        }
+        RELEASED.notify_all();
+    }
+}
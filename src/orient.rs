@@ -0,0 +1,905 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::ProcessError;
+
+/// The natural position in a block of each coefficient, in the zigzag order they are stored in
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// The EXIF tags of the orientation, the pointer to the EXIF IFD and the dimensions of the image
+const ORIENTATION_TAG: u16 = 0x0112;
+const EXIF_IFD_POINTER_TAG: u16 = 0x8769;
+const IMAGE_WIDTH_TAG: u16 = 0x0100;
+const IMAGE_HEIGHT_TAG: u16 = 0x0101;
+const PIXEL_X_DIMENSION_TAG: u16 = 0xA002;
+const PIXEL_Y_DIMENSION_TAG: u16 = 0xA003;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// An error for a JPEG file that can't be rotated
+fn invalid(message: impl Into<String>) -> ProcessError {
+    ProcessError::ExifParse(message.into())
+}
+
+/// How the pixels of an image are moved to make it upright: transposed first, then mirrored
+#[derive(Debug, Clone, Copy)]
+struct Transform {
+    transpose: bool,
+    flip_horizontal: bool,
+    flip_vertical: bool,
+}
+
+impl Transform {
+    /// The transform undoing an EXIF orientation, or None for the upright orientation 1
+    fn for_orientation(orientation: u16) -> Option<Self> {
+        let (transpose, flip_horizontal, flip_vertical) = match orientation {
+            2 => (false, true, false),
+            3 => (false, true, true),
+            4 => (false, false, true),
+            5 => (true, false, false),
+            6 => (true, true, false),
+            7 => (true, true, true),
+            8 => (true, false, true),
+            _ => return None,
+        };
+        Some(Transform { transpose, flip_horizontal, flip_vertical })
+    }
+}
+
+/// A component of the image, such as the luminance, with its quantized DCT coefficients
+/// Baseline coefficients fit in 16 bits, which halves the memory of a large image.
+struct Component {
+    id: u8,
+    horizontal: usize,
+    vertical: usize,
+    quantization_table: u8,
+    blocks_wide: usize,
+    blocks_high: usize,
+    blocks: Vec<[i16; 64]>,
+}
+
+/// The frame of a baseline JPEG image, decoded down to its coefficients
+struct Frame {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+}
+
+impl Frame {
+    fn max_horizontal(&self) -> usize {
+        self.components.iter().map(|component| component.horizontal).max().unwrap_or(1)
+    }
+
+    fn max_vertical(&self) -> usize {
+        self.components.iter().map(|component| component.vertical).max().unwrap_or(1)
+    }
+
+    /// The number of MCUs across and down the image, when the scan interleaves several components
+    fn mcus(&self) -> (usize, usize) {
+        (self.width.div_ceil(8 * self.max_horizontal()), self.height.div_ceil(8 * self.max_vertical()))
+    }
+
+    /// Set the number of blocks of each component, padded to whole MCUs when interleaved
+    /// The blocks themselves are allocated by the caller, one component at a time if it wants.
+    fn size_blocks(&mut self) {
+        let (mcus_wide, mcus_high) = self.mcus();
+        let (max_horizontal, max_vertical) = (self.max_horizontal(), self.max_vertical());
+        let interleaved = self.components.len() > 1;
+        let (width, height) = (self.width, self.height);
+        for component in &mut self.components {
+            if interleaved {
+                component.blocks_wide = mcus_wide * component.horizontal;
+                component.blocks_high = mcus_high * component.vertical;
+            } else {
+                component.blocks_wide = (width * component.horizontal).div_ceil(max_horizontal).div_ceil(8);
+                component.blocks_high = (height * component.vertical).div_ceil(max_vertical).div_ceil(8);
+            }
+        }
+    }
+
+    /// Call the visitor with each block in the order of the scan, and whether it starts a new MCU
+    fn scan_order(&self, mut visit: impl FnMut(usize, usize, bool) -> Result<(), ProcessError>) -> Result<(), ProcessError> {
+        if let [component] = self.components.as_slice() {
+            for index in 0..component.blocks_wide * component.blocks_high {
+                visit(0, index, true)?;
+            }
+            return Ok(());
+        }
+        let (mcus_wide, mcus_high) = self.mcus();
+        for mcu_y in 0..mcus_high {
+            for mcu_x in 0..mcus_wide {
+                let mut first = true;
+                for (index, component) in self.components.iter().enumerate() {
+                    for y in 0..component.vertical {
+                        for x in 0..component.horizontal {
+                            let block = (mcu_y * component.vertical + y) * component.blocks_wide + mcu_x * component.horizontal + x;
+                            visit(index, block, first)?;
+                            first = false;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the image can be transformed without trimming it
+    /// A mirrored side must end on a whole MCU, since its padding would become visible on the other side.
+    fn transforms_losslessly(&self, transform: Transform) -> bool {
+        let (mcu_width, mcu_height) = if self.components.len() > 1 {
+            (8 * self.max_horizontal(), 8 * self.max_vertical())
+        } else {
+            (8, 8)
+        };
+        // The sides are mirrored after the transposition
+        let (mirrored_width, mirrored_height) = if transform.transpose {
+            (transform.flip_vertical, transform.flip_horizontal)
+        } else {
+            (transform.flip_horizontal, transform.flip_vertical)
+        };
+        (!mirrored_width || self.width.is_multiple_of(mcu_width)) && (!mirrored_height || self.height.is_multiple_of(mcu_height))
+    }
+
+    /// Move the blocks and coefficients of the image, which must transform losslessly
+    /// Each component of the image is dropped once moved, so that only one is held twice at a time.
+    fn transform(self, transform: Transform) -> Frame {
+        let (max_horizontal, max_vertical) = if transform.transpose {
+            (self.max_vertical(), self.max_horizontal())
+        } else {
+            (self.max_horizontal(), self.max_vertical())
+        };
+        let (width, height) = if transform.transpose { (self.height, self.width) } else { (self.width, self.height) };
+        let components = self.components.iter()
+            .map(|component| {
+                let (horizontal, vertical) = if transform.transpose {
+                    (component.vertical, component.horizontal)
+                } else {
+                    (component.horizontal, component.vertical)
+                };
+                Component { horizontal, vertical, blocks_wide: 0, blocks_high: 0, blocks: Vec::new(), ..*component }
+            })
+            .collect();
+        let mut frame = Frame { width, height, components };
+        frame.size_blocks();
+
+        for (output, input) in frame.components.iter_mut().zip(self.components) {
+            output.blocks = vec![[0; 64]; output.blocks_wide * output.blocks_high];
+            // The blocks covering the image, which are all the blocks on a mirrored side
+            let valid_wide = (width * output.horizontal).div_ceil(max_horizontal).div_ceil(8);
+            let valid_high = (height * output.vertical).div_ceil(max_vertical).div_ceil(8);
+            for y in 0..output.blocks_high {
+                for x in 0..output.blocks_wide {
+                    let x_before_flip = if transform.flip_horizontal && x < valid_wide { valid_wide - 1 - x } else { x };
+                    let y_before_flip = if transform.flip_vertical && y < valid_high { valid_high - 1 - y } else { y };
+                    let (input_x, input_y) = if transform.transpose { (y_before_flip, x_before_flip) } else { (x_before_flip, y_before_flip) };
+                    if input_x >= input.blocks_wide || input_y >= input.blocks_high {
+                        continue;
+                    }
+                    let block = &input.blocks[input_y * input.blocks_wide + input_x];
+                    output.blocks[y * output.blocks_wide + x] = transform_block(block, transform);
+                }
+            }
+        }
+        frame
+    }
+}
+
+/// Move the coefficients of a block, where mirroring negates the odd frequencies along its axis
+fn transform_block(block: &[i16; 64], transform: Transform) -> [i16; 64] {
+    let mut output = [0; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut coefficient = if transform.transpose { block[u * 8 + v] } else { block[v * 8 + u] };
+            if (transform.flip_horizontal && u % 2 == 1) != (transform.flip_vertical && v % 2 == 1) {
+                coefficient = coefficient.wrapping_neg();
+            }
+            output[v * 8 + u] = coefficient;
+        }
+    }
+    output
+}
+
+/// A Huffman table read from a DHT segment, decoded as in section F.2.2.3 of the JPEG standard
+#[derive(Clone, Default)]
+struct DecodingTable {
+    max_code: [i32; 17],
+    value_offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl DecodingTable {
+    fn new(counts: &[u8], values: &[u8]) -> Self {
+        let mut table = DecodingTable { max_code: [-1; 17], value_offset: [0; 17], values: values.to_vec() };
+        let mut code = 0;
+        let mut index = 0;
+        for length in 1..=16 {
+            let count = counts[length - 1] as i32;
+            if count > 0 {
+                table.value_offset[length] = index - code;
+                code += count;
+                index += count;
+                table.max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        table
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u8, ProcessError> {
+        let mut code = 0;
+        for length in 1..=16 {
+            code = (code << 1) | reader.bit() as i32;
+            if code <= self.max_code[length] {
+                return self.values.get((self.value_offset[length] + code) as usize)
+                    .copied()
+                    .ok_or_else(|| invalid("Invalid Huffman code"));
+            }
+        }
+        Err(invalid("Invalid Huffman code"))
+    }
+}
+
+/// Reads the bits of the entropy-coded data, removing the stuffed zero bytes
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+    /// A marker was reached, after which only zero bits are read until the restart
+    at_marker: bool,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> u32 {
+        if self.count == 0 {
+            let byte = self.next_byte();
+            self.bits = byte as u32;
+            self.count = 8;
+        }
+        self.count -= 1;
+        (self.bits >> self.count) & 1
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        if self.at_marker || self.pos >= self.data.len() {
+            return 0;
+        }
+        let byte = self.data[self.pos];
+        if byte == 0xFF {
+            match self.data.get(self.pos + 1) {
+                Some(0x00) => self.pos += 2,
+                _ => {
+                    self.at_marker = true;
+                    return 0;
+                }
+            }
+        } else {
+            self.pos += 1;
+        }
+        byte
+    }
+
+    fn receive(&mut self, size: u8) -> i32 {
+        let mut value = 0;
+        for _ in 0..size {
+            value = (value << 1) | self.bit() as i32;
+        }
+        value
+    }
+
+    /// Skip the rest of the byte and the restart marker that follows it
+    fn restart(&mut self) -> Result<(), ProcessError> {
+        self.count = 0;
+        self.at_marker = false;
+        match self.data.get(self.pos..self.pos + 2) {
+            Some([0xFF, marker]) if (0xD0..=0xD7).contains(marker) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err(invalid("Missing JPEG restart marker")),
+        }
+    }
+}
+
+/// Turn the bits of a coefficient into its signed value, as in section F.2.2.1 of the JPEG standard
+fn extend(value: i32, size: u8) -> i32 {
+    if size == 0 {
+        0
+    } else if value < 1 << (size - 1) {
+        value - (1 << size) + 1
+    } else {
+        value
+    }
+}
+
+/// The number of bits of the magnitude of a value
+fn magnitude_size(value: i32) -> u8 {
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+/// A Huffman table written in a DHT segment, built for the symbols of the image
+struct EncodingTable {
+    counts: [u8; 16],
+    values: Vec<u8>,
+    codes: [(u16, u8); 256],
+}
+
+impl EncodingTable {
+    /// Build the optimal table for the symbol frequencies, as in section K.2 of the JPEG standard
+    fn optimal(frequencies: &[u32; 256]) -> Self {
+        let mut frequency = [0u64; 257];
+        for (symbol, &count) in frequencies.iter().enumerate() {
+            frequency[symbol] = count as u64;
+        }
+        // A reserved symbol makes sure that no code is all ones
+        frequency[256] = 1;
+        let mut code_size = [0usize; 257];
+        let mut others = [usize::MAX; 257];
+        loop {
+            let smallest = |excluded: Option<usize>| {
+                let mut best: Option<usize> = None;
+                for symbol in 0..257 {
+                    if frequency[symbol] > 0 && Some(symbol) != excluded && best.is_none_or(|best| frequency[symbol] <= frequency[best]) {
+                        best = Some(symbol);
+                    }
+                }
+                best
+            };
+            let Some(mut first) = smallest(None) else { break };
+            let Some(mut second) = smallest(Some(first)) else { break };
+            frequency[first] += frequency[second];
+            frequency[second] = 0;
+            code_size[first] += 1;
+            while others[first] != usize::MAX {
+                first = others[first];
+                code_size[first] += 1;
+            }
+            others[first] = second;
+            code_size[second] += 1;
+            while others[second] != usize::MAX {
+                second = others[second];
+                code_size[second] += 1;
+            }
+        }
+
+        let mut bits = [0usize; 33];
+        for &size in code_size.iter().filter(|&&size| size > 0) {
+            bits[size] += 1;
+        }
+        // Codes can't be longer than 16 bits
+        for length in (17..=32).rev() {
+            while bits[length] > 0 {
+                let mut shorter = length - 2;
+                while bits[shorter] == 0 {
+                    shorter -= 1;
+                }
+                bits[length] -= 2;
+                bits[length - 1] += 1;
+                bits[shorter + 1] += 2;
+                bits[shorter] -= 1;
+            }
+        }
+        // Remove the reserved symbol, which has one of the longest codes
+        let longest = (1..=16).rev().find(|&length| bits[length] > 0).unwrap_or(1);
+        bits[longest] -= 1;
+
+        let mut values = Vec::new();
+        for size in 1..=32 {
+            values.extend((0..256).filter(|&symbol| code_size[symbol] == size).map(|symbol| symbol as u8));
+        }
+        let mut counts = [0u8; 16];
+        for length in 1..=16 {
+            counts[length - 1] = bits[length] as u8;
+        }
+
+        let mut codes = [(0u16, 0u8); 256];
+        let mut code = 0u16;
+        let mut index = 0;
+        for length in 1..=16 {
+            for _ in 0..counts[length - 1] {
+                codes[values[index] as usize] = (code, length as u8);
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+        EncodingTable { counts, values, codes }
+    }
+}
+
+/// Writes the entropy-coded data, stuffing a zero byte after each 0xFF byte
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, size: u8) {
+        for shift in (0..size).rev() {
+            self.bits = (self.bits << 1) | ((value >> shift) & 1);
+            self.count += 1;
+            if self.count == 8 {
+                self.data.push(self.bits as u8);
+                if self.bits == 0xFF {
+                    self.data.push(0);
+                }
+                self.bits = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Pad the last byte with ones
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.write((1 << (8 - self.count)) - 1, (8 - self.count) as u8);
+        }
+        self.data
+    }
+}
+
+/// The Huffman symbols of a block, as the DC symbol with its bits and the AC symbols with theirs
+fn block_symbols(block: &[i16; 64], predictor: &mut i32, mut emit: impl FnMut(bool, u8, i32)) {
+    let difference = block[0] as i32 - *predictor;
+    *predictor = block[0] as i32;
+    emit(true, magnitude_size(difference), difference);
+    let mut run = 0;
+    for &position in &ZIGZAG[1..] {
+        let coefficient = block[position] as i32;
+        if coefficient == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 {
+            emit(false, 0xF0, 0);
+            run -= 16;
+        }
+        emit(false, (run << 4) | magnitude_size(coefficient), coefficient);
+        run = 0;
+    }
+    if run > 0 {
+        emit(false, 0x00, 0);
+    }
+}
+
+/// The bits of the magnitude of a coefficient, where negative values are stored minus one
+fn magnitude_bits(value: i32, size: u8) -> u32 {
+    let value = if value < 0 { value - 1 } else { value };
+    (value as u32) & ((1u32 << size) - 1)
+}
+
+/// A marker segment of a JPEG file
+struct Segment<'a> {
+    marker: u8,
+    payload: &'a [u8],
+}
+
+fn push_segment(output: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    output.extend_from_slice(&[0xFF, marker]);
+    output.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    output.extend_from_slice(payload);
+}
+
+/// Find the value of the orientation tag in the first IFD of EXIF data, with its position
+fn orientation_entry(tiff: &[u8]) -> Option<(usize, u16, bool)> {
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let read_u32 = |pos: usize| -> Option<u32> {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?, *tiff.get(pos + 2)?, *tiff.get(pos + 3)?];
+        Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)? as usize;
+    (0..entries)
+        .map(|index| ifd + 2 + index * 12)
+        .find(|&entry| read_u16(entry) == Some(ORIENTATION_TAG))
+        .and_then(|entry| Some((entry + 8, read_u16(entry + 8)?, little_endian)))
+}
+
+/// Swap the width and height tags of IFD0 and of the Exif IFD, for an image that was transposed
+/// Each tag keeps its own SHORT or LONG type; a JPEG file is never wider than a SHORT.
+fn swap_dimensions(tiff: &mut [u8]) {
+    let little_endian = match tiff.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return,
+    };
+    let read_u16 = |tiff: &[u8], pos: usize| -> Option<u16> {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let read_u32 = |tiff: &[u8], pos: usize| -> Option<u32> {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?, *tiff.get(pos + 2)?, *tiff.get(pos + 3)?];
+        Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+    // The position and value of a SHORT or LONG entry with a single value
+    let find = |tiff: &[u8], ifd: usize, tag: u16| -> Option<(usize, u32)> {
+        let entries = read_u16(tiff, ifd)? as usize;
+        let entry = (0..entries)
+            .map(|index| ifd + 2 + index * 12)
+            .find(|&entry| read_u16(tiff, entry) == Some(tag))?;
+        if read_u32(tiff, entry + 4)? != 1 {
+            return None;
+        }
+        match read_u16(tiff, entry + 2)? {
+            3 => Some((entry, read_u16(tiff, entry + 8)? as u32)),
+            4 => Some((entry, read_u32(tiff, entry + 8)?)),
+            _ => None,
+        }
+    };
+    let write = |tiff: &mut [u8], entry: usize, value: u32| {
+        if read_u16(tiff, entry + 2) == Some(3) {
+            let value = value as u16;
+            let bytes = if little_endian { value.to_le_bytes() } else { value.to_be_bytes() };
+            tiff[entry + 8..entry + 10].copy_from_slice(&bytes);
+        } else {
+            let bytes = if little_endian { value.to_le_bytes() } else { value.to_be_bytes() };
+            tiff[entry + 8..entry + 12].copy_from_slice(&bytes);
+        }
+    };
+    let Some(ifd0) = read_u32(tiff, 4).map(|ifd| ifd as usize) else { return };
+    let exif_ifd = find(tiff, ifd0, EXIF_IFD_POINTER_TAG).map(|(_, ifd)| ifd as usize);
+    let pairs = [(Some(ifd0), IMAGE_WIDTH_TAG, IMAGE_HEIGHT_TAG), (exif_ifd, PIXEL_X_DIMENSION_TAG, PIXEL_Y_DIMENSION_TAG)];
+    for (ifd, width_tag, height_tag) in pairs {
+        let Some(ifd) = ifd else { continue };
+        if let (Some((width_entry, width)), Some((height_entry, height))) = (find(tiff, ifd, width_tag), find(tiff, ifd, height_tag)) {
+            write(tiff, width_entry, height);
+            write(tiff, height_entry, width);
+        }
+    }
+}
+
+/// Read the EXIF orientation of a JPEG file, 1 being upright
+pub fn orientation(data: &[u8]) -> Option<u16> {
+    let (segments, _) = read_segments(data).ok()?;
+    segments.iter()
+        .find(|segment| segment.marker == 0xE1 && segment.payload.starts_with(EXIF_HEADER))
+        .and_then(|segment| orientation_entry(&segment.payload[EXIF_HEADER.len()..]))
+        .map(|(_, orientation, _)| orientation)
+}
+
+/// Split a JPEG file into its marker segments up to the scan, returning the position of the scan data
+fn read_segments(data: &[u8]) -> Result<(Vec<Segment<'_>>, usize), ProcessError> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(invalid("Not a JPEG file"));
+    }
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        if pos + 4 > data.len() || data[pos] != 0xFF {
+            return Err(invalid("Malformed JPEG marker segment"));
+        }
+        let marker = data[pos + 1];
+        // Fill bytes may precede any marker
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > data.len() {
+            return Err(invalid("Truncated JPEG marker segment"));
+        }
+        segments.push(Segment { marker, payload: &data[pos + 4..end] });
+        pos = end;
+        // The start-of-scan segment is followed by the entropy-coded data
+        if marker == 0xDA {
+            return Ok((segments, pos));
+        }
+    }
+}
+
+/// Rotate or mirror the pixels of a JPEG file so that it is upright, and set its orientation to 1
+/// This is lossless, like `jpegtran -perfect`, and only supports baseline JPEG files. Images whose
+/// mirrored side is not a whole number of MCUs are left as they are, since `jpegtran` would trim
+/// that edge. The data after the image, such as the video of a motion photo, is kept. Returns
+/// whether the file was changed.
+pub fn auto_orient(path: &Path) -> Result<bool, ProcessError> {
+    let data = fs::read(path)?;
+    let Some(transform) = orientation(&data).and_then(Transform::for_orientation) else {
+        return Ok(false);
+    };
+    let (segments, scan_start) = read_segments(&data)?;
+
+    let mut frame = None;
+    let mut tables: [DecodingTable; 8] = Default::default();
+    let mut restart_interval = 0;
+    for segment in &segments {
+        let payload = segment.payload;
+        match segment.marker {
+            0xC0 | 0xC1 => {
+                if payload.len() < 6 || payload[0] != 8 {
+                    return Err(invalid("Only 8-bit JPEG files can be rotated"));
+                }
+                let count = payload[5] as usize;
+                let components = (0..count)
+                    .map(|index| {
+                        let component = payload.get(6 + index * 3..9 + index * 3).ok_or_else(|| invalid("Truncated JPEG frame"))?;
+                        let (horizontal, vertical) = ((component[1] >> 4) as usize, (component[1] & 15) as usize);
+                        if !(1..=4).contains(&horizontal) || !(1..=4).contains(&vertical) {
+                            return Err(invalid("Invalid JPEG sampling factors"));
+                        }
+                        Ok(Component { id: component[0], horizontal, vertical, quantization_table: component[2], blocks_wide: 0, blocks_high: 0, blocks: Vec::new() })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                frame = Some(Frame {
+                    width: u16::from_be_bytes([payload[3], payload[4]]) as usize,
+                    height: u16::from_be_bytes([payload[1], payload[2]]) as usize,
+                    components,
+                });
+            }
+            0xC2..=0xCF if segment.marker != 0xC4 && segment.marker != 0xC8 && segment.marker != 0xCC => {
+                return Err(invalid("Only baseline JPEG files can be rotated"));
+            }
+            0xC4 => {
+                let mut pos = 0;
+                while pos + 17 <= payload.len() {
+                    let (class, id) = (payload[pos] >> 4, payload[pos] & 15);
+                    let counts = &payload[pos + 1..pos + 17];
+                    let total: usize = counts.iter().map(|&count| count as usize).sum();
+                    let values = payload.get(pos + 17..pos + 17 + total).ok_or_else(|| invalid("Truncated Huffman table"))?;
+                    tables[(class as usize & 1) * 4 + (id as usize & 3)] = DecodingTable::new(counts, values);
+                    pos += 17 + total;
+                }
+            }
+            0xDD if payload.len() >= 2 => restart_interval = u16::from_be_bytes([payload[0], payload[1]]) as usize,
+            _ => {}
+        }
+    }
+    let mut frame = frame.ok_or_else(|| invalid("No JPEG frame found"))?;
+    if frame.width == 0 || frame.height == 0 {
+        return Err(invalid("JPEG files without a height can't be rotated"));
+    }
+
+    // The scan must hold every component, in the order of the frame
+    let scan = segments.last().map(|segment| segment.payload).unwrap_or_default();
+    let scan_components = *scan.first().unwrap_or(&0) as usize;
+    if scan_components != frame.components.len() {
+        return Err(invalid("JPEG files with several scans can't be rotated"));
+    }
+    let mut scan_tables = Vec::new();
+    for (index, component) in frame.components.iter().enumerate() {
+        let selector = scan.get(1 + index * 2..3 + index * 2).ok_or_else(|| invalid("Truncated JPEG scan header"))?;
+        if selector[0] != component.id {
+            return Err(invalid("JPEG scan components out of order"));
+        }
+        scan_tables.push(((selector[1] >> 4) as usize & 3, 4 + (selector[1] as usize & 3)));
+    }
+
+    frame.size_blocks();
+    if !frame.transforms_losslessly(transform) {
+        return Err(invalid("The JPEG file is not a whole number of MCUs wide or high, so it can't be rotated without trimming its edge"));
+    }
+    // The blocks are moved out of the frame while it lays out the scan, and moved back once decoded
+    let mut blocks: Vec<Vec<[i16; 64]>> = frame.components.iter()
+        .map(|component| vec![[0; 64]; component.blocks_wide * component.blocks_high])
+        .collect();
+    let mut reader = BitReader { data: &data, pos: scan_start, bits: 0, count: 0, at_marker: false };
+    let mut predictors = vec![0i32; frame.components.len()];
+    let mut mcu = 0;
+    frame.scan_order(|index, block_index, starts_mcu| {
+        if starts_mcu {
+            if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+                reader.restart()?;
+                predictors.iter_mut().for_each(|predictor| *predictor = 0);
+            }
+            mcu += 1;
+        }
+        let (dc_table, ac_table) = (&tables[scan_tables[index].0], &tables[scan_tables[index].1]);
+        let block = &mut blocks[index][block_index];
+        let size = dc_table.decode(&mut reader)?;
+        let bits = reader.receive(size);
+        predictors[index] += extend(bits, size);
+        block[0] = i16::try_from(predictors[index]).map_err(|_| invalid("Invalid JPEG DC coefficient"))?;
+        let mut k = 1;
+        while k < 64 {
+            let symbol = ac_table.decode(&mut reader)?;
+            let (run, size) = ((symbol >> 4) as usize, symbol & 15);
+            if size == 0 {
+                if run != 15 {
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+            k += run;
+            if k >= 64 {
+                return Err(invalid("Invalid JPEG coefficient position"));
+            }
+            let bits = reader.receive(size);
+            block[ZIGZAG[k]] = i16::try_from(extend(bits, size)).map_err(|_| invalid("Invalid JPEG AC coefficient"))?;
+            k += 1;
+        }
+        Ok(())
+    })?;
+    for (component, blocks) in frame.components.iter_mut().zip(blocks) {
+        component.blocks = blocks;
+    }
+
+    // Whatever follows the image, such as an appended video, is kept as is
+    let mut end = reader.pos;
+    while end + 1 < data.len() && !(data[end] == 0xFF && data[end + 1] == 0xD9) {
+        if data[end] == 0xFF && !matches!(data[end + 1], 0x00 | 0xD0..=0xD7 | 0xFF) {
+            return Err(invalid("JPEG files with several scans can't be rotated"));
+        }
+        end += 1;
+    }
+    let trailer = data.get(end + 2..).unwrap_or_default();
+
+    let rotated = frame.transform(transform);
+    let mut output = vec![0xFF, 0xD8];
+    for segment in &segments {
+        match segment.marker {
+            0xE1 if segment.payload.starts_with(EXIF_HEADER) => {
+                let mut payload = segment.payload.to_vec();
+                let tiff = &mut payload[EXIF_HEADER.len()..];
+                if let Some((pos, _, little_endian)) = orientation_entry(tiff) {
+                    let upright = if little_endian { 1u16.to_le_bytes() } else { 1u16.to_be_bytes() };
+                    tiff[pos..pos + 2].copy_from_slice(&upright);
+                }
+                if transform.transpose {
+                    swap_dimensions(tiff);
+                }
+                push_segment(&mut output, segment.marker, &payload);
+            }
+            0xDB if transform.transpose => push_segment(&mut output, segment.marker, &transpose_quantization_tables(segment.payload)?),
+            0xDB => push_segment(&mut output, segment.marker, segment.payload),
+            0xE0..=0xEF | 0xFE => push_segment(&mut output, segment.marker, segment.payload),
+            _ => {}
+        }
+    }
+    encode(&rotated, &mut output);
+    output.extend_from_slice(&[0xFF, 0xD9]);
+    output.extend_from_slice(trailer);
+    fs::write(path, output)?;
+    Ok(true)
+}
+
+/// Transpose the quantization tables of a DQT segment, to go with transposed coefficients
+fn transpose_quantization_tables(payload: &[u8]) -> Result<Vec<u8>, ProcessError> {
+    let mut output = payload.to_vec();
+    let mut pos = 0;
+    while pos < payload.len() {
+        let width = if payload[pos] >> 4 == 0 { 1 } else { 2 };
+        let table = payload.get(pos + 1..pos + 1 + 64 * width).ok_or_else(|| invalid("Truncated quantization table"))?;
+        for k in 0..64 {
+            let natural = ZIGZAG[k];
+            let transposed = (natural % 8) * 8 + natural / 8;
+            let source = ZIGZAG.iter().position(|&position| position == transposed).unwrap_or(k);
+            output[pos + 1 + k * width..pos + 1 + (k + 1) * width].copy_from_slice(&table[source * width..(source + 1) * width]);
+        }
+        pos += 1 + 64 * width;
+    }
+    Ok(output)
+}
+
+/// Write the frame, Huffman tables, scan header and entropy-coded data of an image
+/// A single pair of optimal Huffman tables is used for all the components.
+fn encode(frame: &Frame, output: &mut Vec<u8>) {
+    let mut sof = vec![8];
+    sof.extend_from_slice(&(frame.height as u16).to_be_bytes());
+    sof.extend_from_slice(&(frame.width as u16).to_be_bytes());
+    sof.push(frame.components.len() as u8);
+    for component in &frame.components {
+        sof.extend_from_slice(&[component.id, ((component.horizontal << 4) | component.vertical) as u8, component.quantization_table]);
+    }
+    push_segment(output, 0xC0, &sof);
+
+    let mut dc_frequencies = [0u32; 256];
+    let mut ac_frequencies = [0u32; 256];
+    let mut predictors = vec![0; frame.components.len()];
+    let _ = frame.scan_order(|index, block, _| {
+        block_symbols(&frame.components[index].blocks[block], &mut predictors[index], |dc, symbol, _| {
+            if dc { dc_frequencies[symbol as usize] += 1 } else { ac_frequencies[symbol as usize] += 1 }
+        });
+        Ok(())
+    });
+    let dc_table = EncodingTable::optimal(&dc_frequencies);
+    let ac_table = EncodingTable::optimal(&ac_frequencies);
+    for (class, table) in [(0u8, &dc_table), (1, &ac_table)] {
+        let mut dht = vec![class << 4];
+        dht.extend_from_slice(&table.counts);
+        dht.extend_from_slice(&table.values);
+        push_segment(output, 0xC4, &dht);
+    }
+
+    let mut sos = vec![frame.components.len() as u8];
+    for component in &frame.components {
+        sos.extend_from_slice(&[component.id, 0x00]);
+    }
+    sos.extend_from_slice(&[0, 63, 0]);
+    push_segment(output, 0xDA, &sos);
+
+    let mut writer = BitWriter::default();
+    let mut predictors = vec![0; frame.components.len()];
+    let _ = frame.scan_order(|index, block, _| {
+        block_symbols(&frame.components[index].blocks[block], &mut predictors[index], |dc, symbol, value| {
+            let (code, length) = if dc { dc_table.codes[symbol as usize] } else { ac_table.codes[symbol as usize] };
+            writer.write(code as u32, length);
+            let size = if dc { symbol } else { symbol & 15 };
+            writer.write(magnitude_bits(value, size), size);
+        });
+        Ok(())
+    });
+    output.extend_from_slice(&writer.finish());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: usize, height: usize, sampling: &[(usize, usize)]) -> Frame {
+        let components = sampling.iter().enumerate().map(|(index, &(horizontal, vertical))| Component {
+            id: index as u8 + 1,
+            horizontal,
+            vertical,
+            quantization_table: 0,
+            blocks_wide: 0,
+            blocks_high: 0,
+            blocks: Vec::new(),
+        }).collect();
+        Frame { width, height, components }
+    }
+
+    #[test]
+    fn transforms_losslessly_when_the_mirrored_sides_are_whole_mcus() {
+        // 4:2:0 has 16 by 16 MCUs
+        let image = frame(48, 29, &[(2, 2), (1, 1), (1, 1)]);
+        let flip_horizontal = Transform::for_orientation(2).unwrap();
+        let flip_vertical = Transform::for_orientation(4).unwrap();
+        let transpose = Transform::for_orientation(5).unwrap();
+        assert!(image.transforms_losslessly(flip_horizontal));
+        assert!(!image.transforms_losslessly(flip_vertical));
+        assert!(image.transforms_losslessly(transpose));
+        // Rotating by 90 degrees mirrors the height once transposed
+        assert!(!image.transforms_losslessly(Transform::for_orientation(6).unwrap()));
+        assert!(image.transforms_losslessly(Transform::for_orientation(8).unwrap()));
+        // A single component is scanned in 8 by 8 blocks
+        assert!(frame(24, 29, &[(2, 2)]).transforms_losslessly(flip_horizontal));
+        assert!(!frame(24, 29, &[(2, 2), (1, 1), (1, 1)]).transforms_losslessly(flip_horizontal));
+    }
+
+    #[test]
+    fn swaps_the_dimension_tags() {
+        let entry = |tag: u16, kind: u16, value: u32| {
+            let mut entry = [tag.to_le_bytes(), kind.to_le_bytes()].concat();
+            entry.extend(1u32.to_le_bytes());
+            match kind {
+                3 => entry.extend([(value as u16).to_le_bytes(), [0; 2]].concat()),
+                _ => entry.extend(value.to_le_bytes()),
+            }
+            entry
+        };
+        // IFD0 at 8 with three entries ends at 8 + 2 + 36 + 4 = 50, where the Exif IFD starts
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        tiff.extend(3u16.to_le_bytes());
+        tiff.extend(entry(IMAGE_WIDTH_TAG, 4, 4000));
+        tiff.extend(entry(IMAGE_HEIGHT_TAG, 3, 3000));
+        tiff.extend(entry(EXIF_IFD_POINTER_TAG, 4, 50));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(entry(PIXEL_X_DIMENSION_TAG, 3, 4000));
+        tiff.extend(entry(PIXEL_Y_DIMENSION_TAG, 4, 3000));
+        tiff.extend(0u32.to_le_bytes());
+
+        swap_dimensions(&mut tiff);
+
+        let read_u16 = |pos: usize| u16::from_le_bytes([tiff[pos], tiff[pos + 1]]) as u32;
+        let read_u32 = |pos: usize| u32::from_le_bytes(tiff[pos..pos + 4].try_into().unwrap());
+        assert_eq!((read_u32(10 + 8), read_u16(22 + 8)), (3000, 4000));
+        assert_eq!((read_u16(52 + 8), read_u32(64 + 8)), (3000, 4000));
+    }
+}
@@ -0,0 +1,354 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, Utc};
+
+/// The layout used when no pattern is given: `2021/March/jpg`
+pub const DEFAULT_PATTERN: &str = "{year}/{month_name}/{ext}";
+
+/// The layout of `--ext-top`, grouping the files by extension first: `jpg/2021/March`
+pub const EXT_TOP_PATTERN: &str = "{ext}/{year}/{month_name}";
+
+/// A value that can be used as a placeholder in an output pattern
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placeholder {
+    Year,
+    Month,
+    MonthName,
+    Day,
+    Weekday,
+    Season,
+    Extension,
+    Camera,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "year" => Some(Placeholder::Year),
+            "month" => Some(Placeholder::Month),
+            "month_name" => Some(Placeholder::MonthName),
+            "day" => Some(Placeholder::Day),
+            "weekday" => Some(Placeholder::Weekday),
+            "season" => Some(Placeholder::Season),
+            "ext" => Some(Placeholder::Extension),
+            "camera" => Some(Placeholder::Camera),
+            _ => None,
+        }
+    }
+
+    fn is_numeric(self) -> bool {
+        matches!(self, Placeholder::Year | Placeholder::Month | Placeholder::Day)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Placeholder { placeholder: Placeholder, width: usize },
+}
+
+/// A template for the directory structure of the output, such as `{year}/{month:02}/{day:02}`
+///
+/// Supported placeholders are `{year}`, `{month}`, `{month_name}`, `{day}`, `{weekday}`, `{season}`,
+/// `{ext}` and `{camera}`.
+/// Numeric placeholders accept a zero-padded width, as in `{month:02}`.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        if template.starts_with('/') || template.split('/').any(|component| component == "..") {
+            return Err(format!("Pattern must stay inside the output directory: {}", template));
+        }
+
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}')
+                .ok_or_else(|| format!("Unclosed placeholder in pattern: {}", template))?;
+            let spec = &rest[start + 1..start + end];
+            let (name, width) = match spec.split_once(':') {
+                Some((name, format)) => {
+                    let width = format.strip_prefix('0')
+                        .and_then(|width| width.parse::<usize>().ok())
+                        .ok_or_else(|| format!("Invalid format in placeholder {{{}}}, expected a width like :02", spec))?;
+                    (name, width)
+                }
+                None => (spec, 0),
+            };
+            let placeholder = Placeholder::from_name(name)
+                .ok_or_else(|| format!("Unknown placeholder {{{}}} in pattern: {}", name, template))?;
+            if width > 0 && !placeholder.is_numeric() {
+                return Err(format!("Placeholder {{{}}} does not accept a width", name));
+            }
+            segments.push(Segment::Placeholder { placeholder, width });
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("Unmatched '}}' in pattern: {}", template));
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(Pattern { segments })
+    }
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        DEFAULT_PATTERN.parse().expect("The default pattern is valid")
+    }
+}
+
+impl Pattern {
+    /// The same layout inside `Camera/{camera}`, for `--by-camera`
+    pub fn under_camera(&self) -> Pattern {
+        let mut segments = vec![
+            Segment::Literal("Camera/".to_string()),
+            Segment::Placeholder { placeholder: Placeholder::Camera, width: 0 },
+            Segment::Literal("/".to_string()),
+        ];
+        segments.extend(self.segments.iter().cloned());
+        Pattern { segments }
+    }
+
+    /// Whether the pattern has a `{camera}` placeholder, which needs the EXIF data of each file
+    pub fn uses_camera(&self) -> bool {
+        self.segments.iter().any(|segment| matches!(segment, Segment::Placeholder { placeholder: Placeholder::Camera, .. }))
+    }
+
+    /// Build the directory, relative to the output directory, for a file with the given date and camera
+    /// Empty components are left out, so that an empty extension doesn't leave an empty folder name
+    pub fn render(&self, time: DateTime<Utc>, extension: &str, camera: &str, month_format: MonthFormat, locale: Locale, hemisphere: Hemisphere) -> PathBuf {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Placeholder { placeholder, width } => {
+                    let value = match placeholder {
+                        Placeholder::Year => time.year().to_string(),
+                        Placeholder::Month => time.month().to_string(),
+                        Placeholder::MonthName => format_month(time.month(), month_format, locale),
+                        Placeholder::Day => time.day().to_string(),
+                        Placeholder::Weekday => locale.weekday_names()[time.weekday().num_days_from_monday() as usize].to_string(),
+                        Placeholder::Season => locale.season_names()[hemisphere.season(time.month())].to_string(),
+                        Placeholder::Extension => extension.to_string(),
+                        Placeholder::Camera => camera.to_string(),
+                    };
+                    rendered.push_str(&format!("{:0>width$}", value, width = width));
+                }
+            }
+        }
+        rendered.split('/').filter(|component| !component.is_empty()).collect()
+    }
+}
+
+/// How the `{month_name}` placeholder shows a month
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MonthFormat {
+    /// The name of the month, such as `March`
+    #[default]
+    Name,
+    /// The zero-padded number of the month, such as `03`, which sorts in order
+    Number,
+    /// Both, such as `03-March`, which sorts in order and stays readable
+    NameNumber,
+}
+
+impl FromStr for MonthFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(MonthFormat::Name),
+            "number" => Ok(MonthFormat::Number),
+            "name-number" => Ok(MonthFormat::NameNumber),
+            _ => Err(format!("unknown month format {} (expected name, number or name-number)", s)),
+        }
+    }
+}
+
+/// The half of the world whose seasons the `{season}` placeholder follows
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Hemisphere {
+    #[default]
+    North,
+    South,
+}
+
+impl FromStr for Hemisphere {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "north" => Ok(Hemisphere::North),
+            "south" => Ok(Hemisphere::South),
+            _ => Err(format!("unknown hemisphere {} (expected north or south)", s)),
+        }
+    }
+}
+
+impl Hemisphere {
+    /// The meteorological season of a month, starting at 0 for winter, then spring, summer and autumn
+    /// Winter is December to February in the north and June to August in the south.
+    fn season(self, month: u32) -> usize {
+        let northern = (month as usize % 12) / 3;
+        match self {
+            Hemisphere::North => northern,
+            Hemisphere::South => (northern + 2) % 4,
+        }
+    }
+}
+
+/// The language of the month names, from a locale such as `de`, `de_DE` or `de_DE.UTF-8`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Locale {
+    #[default]
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Dutch,
+    Portuguese,
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let language = s.split(['_', '-', '.']).next().unwrap_or_default().to_lowercase();
+        match language.as_str() {
+            "en" | "c" | "posix" => Ok(Locale::English),
+            "de" => Ok(Locale::German),
+            "fr" => Ok(Locale::French),
+            "es" => Ok(Locale::Spanish),
+            "it" => Ok(Locale::Italian),
+            "nl" => Ok(Locale::Dutch),
+            "pt" => Ok(Locale::Portuguese),
+            _ => Err(format!("unsupported locale {} (expected en, de, fr, es, it, nl or pt)", s)),
+        }
+    }
+}
+
+impl Locale {
+    /// The names of the months, from January to December
+    fn month_names(self) -> [&'static str; 12] {
+        match self {
+            Locale::English => ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"],
+            Locale::German => ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Dezember"],
+            Locale::French => ["janvier", "février", "mars", "avril", "mai", "juin", "juillet", "août", "septembre", "octobre", "novembre", "décembre"],
+            Locale::Spanish => ["enero", "febrero", "marzo", "abril", "mayo", "junio", "julio", "agosto", "septiembre", "octubre", "noviembre", "diciembre"],
+            Locale::Italian => ["gennaio", "febbraio", "marzo", "aprile", "maggio", "giugno", "luglio", "agosto", "settembre", "ottobre", "novembre", "dicembre"],
+            Locale::Dutch => ["januari", "februari", "maart", "april", "mei", "juni", "juli", "augustus", "september", "oktober", "november", "december"],
+            Locale::Portuguese => ["janeiro", "fevereiro", "março", "abril", "maio", "junho", "julho", "agosto", "setembro", "outubro", "novembro", "dezembro"],
+        }
+    }
+
+    /// The names of the days of the week, from Monday to Sunday
+    fn weekday_names(self) -> [&'static str; 7] {
+        match self {
+            Locale::English => ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"],
+            Locale::German => ["Montag", "Dienstag", "Mittwoch", "Donnerstag", "Freitag", "Samstag", "Sonntag"],
+            Locale::French => ["lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche"],
+            Locale::Spanish => ["lunes", "martes", "miércoles", "jueves", "viernes", "sábado", "domingo"],
+            Locale::Italian => ["lunedì", "martedì", "mercoledì", "giovedì", "venerdì", "sabato", "domenica"],
+            Locale::Dutch => ["maandag", "dinsdag", "woensdag", "donderdag", "vrijdag", "zaterdag", "zondag"],
+            Locale::Portuguese => ["segunda-feira", "terça-feira", "quarta-feira", "quinta-feira", "sexta-feira", "sábado", "domingo"],
+        }
+    }
+
+    /// The names of the seasons: winter, spring, summer and autumn
+    fn season_names(self) -> [&'static str; 4] {
+        match self {
+            Locale::English => ["Winter", "Spring", "Summer", "Autumn"],
+            Locale::German => ["Winter", "Frühling", "Sommer", "Herbst"],
+            Locale::French => ["hiver", "printemps", "été", "automne"],
+            Locale::Spanish => ["invierno", "primavera", "verano", "otoño"],
+            Locale::Italian => ["inverno", "primavera", "estate", "autunno"],
+            Locale::Dutch => ["winter", "lente", "zomer", "herfst"],
+            Locale::Portuguese => ["inverno", "primavera", "verão", "outono"],
+        }
+    }
+}
+
+/// Get the name of a month in a language, starting at 1 for January
+pub fn month_name(month: u32, locale: Locale) -> &'static str {
+    match month {
+        1..=12 => locale.month_names()[month as usize - 1],
+        _ => "Unknown",
+    }
+}
+
+/// Show a month as the `{month_name}` placeholder, in the given format and language
+fn format_month(month: u32, format: MonthFormat, locale: Locale) -> String {
+    match format {
+        MonthFormat::Name => month_name(month, locale).to_string(),
+        MonthFormat::Number => format!("{:02}", month),
+        MonthFormat::NameNumber => format!("{:02}-{}", month, month_name(month, locale)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 3, 14, 15, 30, 45).unwrap()
+    }
+
+    fn render(pattern: &str, extension: &str) -> String {
+        let pattern: Pattern = pattern.parse().unwrap();
+        let rendered = pattern.render(time(), extension, "Pixel 7", MonthFormat::default(), Locale::default(), Hemisphere::default());
+        rendered.to_string_lossy().replace('\\', "/")
+    }
+
+    #[test]
+    fn renders_the_default_pattern() {
+        assert_eq!(render(DEFAULT_PATTERN, "jpg"), "2021/March/jpg");
+        assert_eq!(render(EXT_TOP_PATTERN, "jpg"), "jpg/2021/March");
+    }
+
+    #[test]
+    fn pads_numeric_placeholders_to_their_width() {
+        assert_eq!(render("{year}/{month:02}/{day:03}", ""), "2021/03/014");
+        assert_eq!(render("{year}-{month}-{day}", ""), "2021-3-14");
+    }
+
+    #[test]
+    fn renders_names_in_the_locale() {
+        let pattern: Pattern = "{month_name}/{weekday}/{season}".parse().unwrap();
+        let rendered = pattern.render(time(), "", "", MonthFormat::NameNumber, Locale::German, Hemisphere::South);
+        assert_eq!(rendered, PathBuf::from("03-März").join("Sonntag").join("Herbst"));
+    }
+
+    #[test]
+    fn leaves_out_empty_components() {
+        assert_eq!(render("{year}/{ext}/{month:02}", ""), "2021/03");
+    }
+
+    #[test]
+    fn puts_the_layout_under_the_camera() {
+        let pattern = Pattern::default().under_camera();
+        assert!(pattern.uses_camera());
+        assert!(!Pattern::default().uses_camera());
+        let rendered = pattern.render(time(), "jpg", "Pixel 7", MonthFormat::default(), Locale::default(), Hemisphere::default());
+        assert_eq!(rendered, PathBuf::from("Camera/Pixel 7/2021/March/jpg"));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for pattern in ["/{year}", "{year}/../{month}", "{year", "{year}}", "{hour}", "{ext:02}", "{month:2}"] {
+            assert!(pattern.parse::<Pattern>().is_err(), "{} was accepted", pattern);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use crate::error::ProcessError;
+use crate::inflate::Inflate;
+
+/// The first bytes of every PNG file
+const PNG_SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// The prefix that some encoders put before the TIFF structure of the `eXIf` chunk, as in JPEG files
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+
+/// The keywords of the text chunks where ImageMagick stores EXIF data, written before PNG had an
+/// `eXIf` chunk
+const RAW_PROFILE_KEYWORDS: &[&[u8]] = &[b"Raw profile type exif", b"Raw profile type APP1"];
+
+/// An error for a PNG file whose EXIF data can't be found
+fn invalid(message: impl Into<String>) -> ProcessError {
+    ProcessError::ExifParse(message.into())
+}
+
+/// Check whether a file is a PNG image, ignoring the case of the extension
+pub fn is_png_file(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+}
+
+/// Extract the EXIF data of a PNG image, as the TIFF structure expected by `exif::Reader::read_raw`
+/// The data is read from the `eXIf` chunk, removing the `Exif\0\0` prefix some encoders write in
+/// it, or from the hex-encoded raw profile of a `tEXt` or `zTXt` chunk written by ImageMagick.
+pub fn read_exif(path: &Path) -> Result<Vec<u8>, ProcessError> {
+    let data = fs::read(path)?;
+    if !data.starts_with(PNG_SIGNATURE) {
+        return Err(invalid("Not a PNG file"));
+    }
+    let mut pos = PNG_SIGNATURE.len();
+    let mut raw_profile = None;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk = data.get(pos + 8..pos + 8 + len).ok_or_else(|| invalid("Truncated PNG chunk"))?;
+        match &header[4..] {
+            b"eXIf" => return Ok(chunk.strip_prefix(EXIF_PREFIX).unwrap_or(chunk).to_vec()),
+            b"tEXt" | b"zTXt" if raw_profile.is_none() => raw_profile = raw_profile_text(&header[4..], chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        // The chunk is followed by its CRC
+        pos += 8 + len + 4;
+    }
+    let text = raw_profile.ok_or_else(|| invalid("No eXIf chunk found"))?;
+    let exif = decode_raw_profile(&text).ok_or_else(|| invalid("Invalid EXIF raw profile"))?;
+    Ok(exif.strip_prefix(EXIF_PREFIX).unwrap_or(&exif).to_vec())
+}
+
+/// Get the text of a `tEXt` or `zTXt` chunk holding an ImageMagick raw profile of EXIF data
+fn raw_profile_text(kind: &[u8], chunk: &[u8]) -> Option<Vec<u8>> {
+    let separator = chunk.iter().position(|&byte| byte == 0)?;
+    if !RAW_PROFILE_KEYWORDS.contains(&&chunk[..separator]) {
+        return None;
+    }
+    let text = &chunk[separator + 1..];
+    if kind == b"tEXt" {
+        return Some(text.to_vec());
+    }
+    // A compression method byte, then a zlib stream: a 2-byte header before the DEFLATE data
+    let mut inflated = Vec::new();
+    Inflate::new(text.get(3..)?).read_to_end(&mut inflated).ok()?;
+    Some(inflated)
+}
+
+/// Decode a raw profile, written as its name, its length in bytes and the bytes in hex, each
+/// separated by whitespace
+fn decode_raw_profile(text: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(text).ok()?;
+    let mut fields = text.split_whitespace();
+    let _name = fields.next()?;
+    let len: usize = fields.next()?.parse().ok()?;
+    let hex: String = fields.collect();
+    let bytes: Vec<u8> = hex.as_bytes()
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<_>>()?;
+    (bytes.len() >= len).then(|| bytes[..len].to_vec())
+}
@@ -0,0 +1,200 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::lock;
+
+/// Width of the bar itself, excluding the counters
+const BAR_WIDTH: usize = 30;
+
+/// Minimum time between two redraws of the bar
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The progress bar currently shown on stderr, if any
+static ACTIVE: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+thread_local! {
+    /// The file being processed by this thread, quoted as `{:?}` quotes it, which its log records are
+    /// prefixed with
+    static CURRENT_FILE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// A guard prefixing the log records of its thread with the path of a file, as in
+/// `"Photos/IMG_1234.jpg": Found EXIF DateTimeOriginal`, until it is dropped
+/// This lets the records of one file be picked out of the interleaved output of a parallel run with
+/// grep. The records that already name the file are left as they are, and the file of an enclosing
+/// guard is restored when it is dropped. It only prefixes the messages of the `log` records, it is
+/// not a tracing span.
+pub struct FileLogPrefix {
+    previous: Option<String>,
+}
+
+/// Prefix the records logged by this thread with the path of a file until the guard is dropped
+pub fn prefix_logs_with(path: &Path) -> FileLogPrefix {
+    let previous = CURRENT_FILE.replace(Some(format!("{:?}", path)));
+    FileLogPrefix { previous }
+}
+
+impl Drop for FileLogPrefix {
+    fn drop(&mut self) {
+        CURRENT_FILE.set(self.previous.take());
+    }
+}
+
+/// A progress bar of the files processed out of the total
+struct ProgressBar {
+    total: usize,
+    done: usize,
+    start: Instant,
+    last_draw: Option<Instant>,
+}
+
+impl ProgressBar {
+    fn render(&self) -> String {
+        let elapsed = self.start.elapsed();
+        let ratio = if self.total == 0 { 1.0 } else { self.done as f64 / self.total as f64 };
+        let filled = ((ratio * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+        let eta = if self.done == 0 {
+            "--:--:--".to_string()
+        } else {
+            let remaining = elapsed.mul_f64((self.total - self.done) as f64 / self.done as f64);
+            format_duration(remaining)
+        };
+        format!(
+            "[{}{}] {}/{} ({}%) elapsed {} ETA {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.done,
+            self.total,
+            (ratio * 100.0) as usize,
+            format_duration(elapsed),
+            eta,
+        )
+    }
+
+    fn draw(&mut self) {
+        self.last_draw = Some(Instant::now());
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", self.render());
+        let _ = stderr.flush();
+    }
+
+    fn clear(&self) {
+        if self.last_draw.is_some() {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+/// Format a duration as `hh:mm:ss`
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Show a progress bar on stderr for the given number of files
+pub fn start(total: usize) {
+    let mut bar = ProgressBar { total, done: 0, start: Instant::now(), last_draw: None };
+    bar.draw();
+    *lock(&ACTIVE) = Some(bar);
+}
+
+/// Count one more file as processed, redrawing the bar if it is shown
+pub fn inc() {
+    if let Some(bar) = lock(&ACTIVE).as_mut() {
+        bar.done += 1;
+        if bar.done == bar.total || bar.last_draw.is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL) {
+            bar.draw();
+        }
+    }
+}
+
+/// Remove the progress bar from stderr
+pub fn finish() {
+    if let Some(bar) = lock(&ACTIVE).take() {
+        bar.clear();
+    }
+}
+
+/// A logger that moves the progress bar out of the way of the log lines
+/// The bar is cleared before each record is written and drawn again below it
+struct ProgressLogger {
+    inner: Box<dyn Log>,
+}
+
+impl Log for ProgressLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let mut active = lock(&ACTIVE);
+        if let Some(bar) = active.as_mut() {
+            bar.clear();
+        }
+        let prefixed = CURRENT_FILE.with_borrow(|file| file.as_deref().and_then(|file| prefixed_message(file, record)));
+        match prefixed {
+            Some(message) => self.inner.log(&Record::builder()
+                .args(format_args!("{}", message))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build()),
+            None => self.inner.log(record),
+        }
+        if let Some(bar) = active.as_mut() {
+            bar.draw();
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// The message of a record prefixed with the quoted path of the file being processed, or None when
+/// the message already names the file, as most of the records about a file do
+fn prefixed_message(file: &str, record: &Record) -> Option<String> {
+    let message = record.args().to_string();
+    (!message.contains(file)).then(|| format!("{}: {}", file, message))
+}
+
+/// Set up the same logger as `pretty_env_logger::init`, wrapped so that it works with the progress bar
+/// Records up to the given level are logged, unless RUST_LOG sets other filters
+pub fn init_logger(level: LevelFilter) {
+    let mut builder = pretty_env_logger::formatted_builder();
+    builder.filter_level(level);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(ProgressLogger { inner: Box::new(logger) }))
+        .expect("The logger was already set");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(file: &Path, args: std::fmt::Arguments) -> Option<String> {
+        prefixed_message(&format!("{:?}", file), &Record::builder().args(args).build())
+    }
+
+    #[test]
+    fn prefixes_only_the_records_that_dont_name_the_file() {
+        let file = Path::new("Photos/IMG_1234.jpg");
+        assert_eq!(message(file, format_args!("Found EXIF DateTimeOriginal")).as_deref(),
+            Some(r#""Photos/IMG_1234.jpg": Found EXIF DateTimeOriginal"#));
+        assert_eq!(message(file, format_args!("Skipping {:?}, already organized", file)), None);
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use log::*;
+use walkdir::WalkDir;
+
+use crate::{is_json_file, lock, numbered_sidecar_photo_name, sidecar_photo_name};
+
+/// The input files moved to the output by this run
+static MOVED: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+/// Remember a file moved out of the input, so that its metadata file can be removed when pruning
+pub fn record_moved(path: &Path) {
+    lock(&MOVED).get_or_insert_with(HashSet::new).insert(path.to_path_buf());
+}
+
+/// Check whether a file is the metadata file of a photo that this run moved out of its directory
+/// Album metadata files such as `metadata.json` don't name a photo, so they are never consumed.
+fn is_consumed_sidecar(path: &Path, moved: &HashSet<PathBuf>) -> bool {
+    if !is_json_file(path) {
+        return false;
+    }
+    numbered_sidecar_photo_name(path)
+        .or_else(|| sidecar_photo_name(path))
+        .filter(|photo_name| photo_name.contains('.'))
+        .is_some_and(|photo_name| moved.contains(&path.with_file_name(photo_name)))
+}
+
+/// Remove the directories of the inputs left empty, deepest first, returning how many there were
+/// A directory that only holds the metadata files of the photos moved out of it counts as empty,
+/// and they are removed with it. The input directories themselves are kept.
+pub fn prune_empty_dirs(inputs: &[PathBuf], dry_run: bool) -> usize {
+    let moved = lock(&MOVED).take().unwrap_or_default();
+    let mut removed = 0;
+    for input in inputs {
+        let directories = WalkDir::new(input)
+            .min_depth(1)
+            .contents_first(true)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_dir());
+        for directory in directories {
+            let Ok(entries) = fs::read_dir(directory.path()) else {
+                continue;
+            };
+            let mut sidecars = Vec::new();
+            let mut empty = true;
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                if entry.file_type().is_ok_and(|file_type| file_type.is_file()) && is_consumed_sidecar(&path, &moved) {
+                    sidecars.push(path);
+                } else {
+                    empty = false;
+                    break;
+                }
+            }
+            if !empty {
+                continue;
+            }
+            if dry_run {
+                info!("Would remove the empty directory {:?}", directory.path());
+                removed += 1;
+                continue;
+            }
+            let result = sidecars.iter()
+                .try_for_each(fs::remove_file)
+                .and_then(|()| fs::remove_dir(directory.path()));
+            match result {
+                Ok(()) => {
+                    debug!("Removed the empty directory {:?}", directory.path());
+                    removed += 1;
+                }
+                Err(e) => warn!("Failed to remove the empty directory {:?}: {}", directory.path(), e),
+            }
+        }
+    }
+    removed
+}
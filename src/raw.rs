@@ -0,0 +1,51 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::ProcessError;
+
+/// The extensions of the RAW images that are not plain TIFF files, whose EXIF data must be
+/// extracted. CR2, NEF, ARW, DNG and PEF files are TIFF files and are read directly.
+const RAW_EXTENSIONS: &[&str] = &["raf", "orf", "rw2"];
+
+/// The first bytes of a Fujifilm RAF file
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW";
+
+/// The offset in a RAF header of the offset and length of its JPEG preview, as big-endian integers
+const RAF_JPEG_OFFSET: usize = 84;
+
+/// The magic numbers that Olympus ORF and Panasonic RW2 files use instead of the 42 of TIFF, after
+/// the byte order
+const TIFF_LIKE_MAGICS: &[&[u8]] = &[b"IIRO", b"IIRS", b"MMOR", b"IIU\0"];
+
+/// An error for a RAW file whose EXIF data can't be found
+fn invalid(message: impl Into<String>) -> ProcessError {
+    ProcessError::ExifParse(message.into())
+}
+
+/// Check whether a file is a RAW image that is not a plain TIFF file, ignoring the case of the extension
+pub fn is_raw_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| RAW_EXTENSIONS.iter().any(|raw| ext.eq_ignore_ascii_case(raw)))
+}
+
+/// Extract the EXIF data of a RAW image, as the TIFF structure expected by `exif::Reader::read_raw`
+/// RAF files hold it in their JPEG preview, and ORF and RW2 files are TIFF files with another
+/// magic number.
+pub fn read_exif(path: &Path) -> Result<Vec<u8>, ProcessError> {
+    let mut data = fs::read(path)?;
+    if data.starts_with(RAF_MAGIC) {
+        let header = data.get(RAF_JPEG_OFFSET..RAF_JPEG_OFFSET + 8).ok_or_else(|| invalid("Truncated RAF header"))?;
+        let offset = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let jpeg = data.get(offset..offset.saturating_add(len)).ok_or_else(|| invalid("Truncated RAF preview"))?;
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(jpeg))?;
+        return Ok(exif.buf().to_vec());
+    }
+    if TIFF_LIKE_MAGICS.iter().any(|magic| data.starts_with(magic)) {
+        let magic: [u8; 2] = if data.starts_with(b"II") { [42, 0] } else { [0, 42] };
+        data[2..4].copy_from_slice(&magic);
+        return Ok(data);
+    }
+    Err(invalid("Unknown RAW format"))
+}
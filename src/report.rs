@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::{DateSource, ProcessError};
+
+/// A file that could not be processed
+#[derive(Debug, Serialize)]
+pub struct ErroredFile {
+    pub path: String,
+    pub kind: &'static str,
+    pub error: String,
+}
+
+/// A summary of a run, written as JSON with `--report`
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub total: usize,
+    /// The files dated from the map file of `--map-file`
+    pub manual: usize,
+    pub metadata: usize,
+    pub exif: usize,
+    pub video: usize,
+    pub file_name: usize,
+    pub creation_time: usize,
+    pub unsorted: usize,
+    /// The images that failed the integrity check of `--validate-images`
+    pub corrupt: usize,
+    /// The files without content, left out or put in the empty directory as `--on-empty` asks
+    pub empty: usize,
+    pub errors: usize,
+    /// The errors where the times of an output file did not stick, with `--verify`
+    pub verification_failures: usize,
+    /// The errors where a copy did not have the checksum of its file, with `--checksum`
+    pub checksum_mismatches: usize,
+    pub skipped: usize,
+    /// The skipped files dated outside the window of `--since` and `--until`
+    pub outside_window: usize,
+    /// The skipped files a previous run already organized
+    pub already_organized: usize,
+    /// The skipped files with the same content as another one, with `--dedup`
+    pub duplicates: usize,
+    /// Whether Ctrl-C stopped the run before every file was processed
+    pub interrupted: bool,
+    pub errored_files: Vec<ErroredFile>,
+    pub creation_time_files: Vec<String>,
+}
+
+impl Report {
+    /// Record a file that was organized using the date from the given source, or skipped for the reason it gives
+    pub fn record_processed(&mut self, path: &Path, source: DateSource) {
+        self.total += 1;
+        match source {
+            DateSource::Manual => self.manual += 1,
+            DateSource::Metadata => self.metadata += 1,
+            DateSource::Exif => self.exif += 1,
+            DateSource::Video => self.video += 1,
+            DateSource::FileName => self.file_name += 1,
+            DateSource::CreationTime => {
+                self.creation_time += 1;
+                self.creation_time_files.push(path.to_string_lossy().to_string());
+            }
+            DateSource::Unsorted => self.unsorted += 1,
+            DateSource::Corrupt => self.corrupt += 1,
+            DateSource::Empty => self.empty += 1,
+            DateSource::OutsideWindow => {
+                self.skipped += 1;
+                self.outside_window += 1;
+            }
+            DateSource::AlreadyOrganized => {
+                self.skipped += 1;
+                self.already_organized += 1;
+            }
+            DateSource::Duplicate => {
+                self.skipped += 1;
+                self.duplicates += 1;
+            }
+        }
+    }
+
+    /// Record a file that failed to be processed
+    pub fn record_error(&mut self, path: &Path, error: &ProcessError) {
+        self.total += 1;
+        self.errors += 1;
+        match error {
+            ProcessError::Verification(_) => self.verification_failures += 1,
+            ProcessError::Checksum(_) => self.checksum_mismatches += 1,
+            _ => {}
+        }
+        self.errored_files.push(ErroredFile {
+            path: path.to_string_lossy().to_string(),
+            kind: error.kind(),
+            error: error.to_string(),
+        });
+    }
+
+    /// Record a file that was intentionally not processed
+    pub fn record_skipped(&mut self) {
+        self.total += 1;
+        self.skipped += 1;
+    }
+
+    /// Record a file that was left out for having no content
+    pub fn record_empty(&mut self) {
+        self.total += 1;
+        self.empty += 1;
+    }
+
+    /// The number of files that were organized (or would have been, in a dry run)
+    pub fn organized(&self) -> usize {
+        self.manual + self.metadata + self.exif + self.video + self.file_name + self.creation_time
+    }
+
+    /// A one-line summary of the counts
+    pub fn summary(&self) -> String {
+        let summary = format!(
+            "{} files: {} organized ({} from the map file, {} from metadata, {} from EXIF, {} from videos, {} from file names, {} from creation time), {} unsorted, {} corrupt, {} empty, {} skipped ({} outside the date window, {} already organized, {} duplicates), {} errors ({} failed verification, {} checksum mismatches)",
+            self.total, self.organized(), self.manual, self.metadata, self.exif, self.video, self.file_name, self.creation_time, self.unsorted, self.corrupt, self.empty,
+            self.skipped, self.outside_window, self.already_organized, self.duplicates, self.errors, self.verification_failures, self.checksum_mismatches,
+        );
+        if self.interrupted {
+            format!("{}, interrupted before the end", summary)
+        } else {
+            summary
+        }
+    }
+
+    /// Write the report as JSON to the given path
+    pub fn write(&self, path: &Path) -> Result<(), ProcessError> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
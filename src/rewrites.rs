@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::lock;
+
+/// The name of the list of rewritten files, at the top of the output directory
+pub const INDEX_NAME: &str = ".takeout-exif-fix-rewrites";
+
+/// The size of a file before and after it was rewritten
+#[derive(Debug, Clone, Copy)]
+struct Sizes {
+    source: u64,
+    output: u64,
+}
+
+/// The files of an output directory that were changed after being copied, by path relative to it
+#[derive(Debug, Default)]
+struct Index {
+    sizes: HashMap<String, Sizes>,
+    file: Option<File>,
+}
+
+/// The index of each output directory, loaded when first needed
+static INDEXES: Mutex<Option<HashMap<PathBuf, Index>>> = Mutex::new(None);
+
+/// The key of an output file in the index of its output directory, with `/` separators
+fn key(output_root: &Path, output_path: &Path) -> Option<String> {
+    let relative = output_path.strip_prefix(output_root).ok()?;
+    Some(relative.to_string_lossy().replace('\\', "/"))
+}
+
+/// Read the index of an output directory, where each line is `<source size>\t<output size>\t<path>`
+/// A path listed again was rewritten again, so its last line wins.
+fn load(output_root: &Path) -> Index {
+    let mut index = Index::default();
+    let Ok(file) = File::open(output_root.join(INDEX_NAME)) else {
+        return index;
+    };
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        let mut fields = line.splitn(3, '\t');
+        if let (Some(Ok(source)), Some(Ok(output)), Some(path)) = (
+            fields.next().map(str::parse),
+            fields.next().map(str::parse),
+            fields.next(),
+        ) {
+            index.sizes.insert(path.to_string(), Sizes { source, output });
+        }
+    }
+    index
+}
+
+/// Run a function on the index of an output directory, loading it first if needed
+fn with_index<T>(output_root: &Path, f: impl FnOnce(&mut Index) -> T) -> T {
+    let mut indexes = lock(&INDEXES);
+    let index = indexes.get_or_insert_with(HashMap::new)
+        .entry(output_root.to_path_buf())
+        .or_insert_with(|| load(output_root));
+    f(index)
+}
+
+/// Remember that an output file no longer has the size of its source, since metadata was written into it
+/// Writing EXIF tags or rotating a JPEG changes its size, which would otherwise make a re-run take it
+/// for another file of the same name.
+pub fn record(output_root: &Path, output_path: &Path, source_size: u64, output_size: u64) -> std::io::Result<()> {
+    let Some(key) = key(output_root, output_path) else {
+        return Ok(());
+    };
+    with_index(output_root, |index| {
+        if index.file.is_none() {
+            index.file = Some(OpenOptions::new().create(true).append(true).open(output_root.join(INDEX_NAME))?);
+        }
+        if let Some(file) = index.file.as_mut() {
+            writeln!(file, "{}\t{}\t{}", source_size, output_size, key)?;
+        }
+        index.sizes.insert(key, Sizes { source: source_size, output: output_size });
+        Ok(())
+    })
+}
+
+/// The size the source of an output file had, if the file was rewritten and still has the size it
+/// was left with
+pub fn source_size(output_root: &Path, output_path: &Path, output_size: u64) -> Option<u64> {
+    let key = key(output_root, output_path)?;
+    with_index(output_root, |index| {
+        index.sizes.get(&key)
+            .filter(|sizes| sizes.output == output_size)
+            .map(|sizes| sizes.source)
+    })
+}
@@ -0,0 +1,194 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use chrono::{DateTime, Utc};
+
+use crate::error::ProcessError;
+
+/// The extensions of the QuickTime and MP4 files whose creation date can be read
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "m4v"];
+
+/// The metadata key Apple devices use to store the local creation date of a video
+const QUICKTIME_CREATION_DATE_KEY: &[u8] = b"com.apple.quicktime.creationdate";
+
+/// Seconds between the QuickTime epoch (1904-01-01) and the Unix epoch
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Largest `moov` atom that will be read into memory
+const MAX_MOOV_LEN: u64 = 64 * 1024 * 1024;
+
+/// An error for a video whose atoms can't be read
+fn malformed(message: impl Into<String>) -> ProcessError {
+    ProcessError::VideoParse(message.into())
+}
+
+/// An atom of a QuickTime file, as a type and the range of bytes of its content
+struct Atom {
+    kind: [u8; 4],
+    start: usize,
+    end: usize,
+}
+
+/// Check whether a file is a video whose creation date can be read, ignoring the case of the extension
+pub fn is_video_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| VIDEO_EXTENSIONS.iter().any(|video| ext.eq_ignore_ascii_case(video)))
+}
+
+/// Read the creation date of a QuickTime or MP4 file
+/// The `com.apple.quicktime.creationdate` metadata is preferred since it is what the camera
+/// recorded, otherwise the creation time of the `mvhd` atom is used. Returns None when neither is set.
+pub fn read_creation_time(path: &Path) -> Result<Option<DateTime<Utc>>, ProcessError> {
+    let moov = read_moov(path)?;
+    let atoms = read_atoms(&moov, 0, moov.len())?;
+
+    if let Some(meta) = atoms.iter().find(|atom| &atom.kind == b"meta")
+        && let Some(creation_date) = quicktime_creation_date(&moov, meta)
+    {
+        return Ok(Some(creation_date));
+    }
+
+    let mvhd = atoms.iter().find(|atom| &atom.kind == b"mvhd").ok_or_else(|| malformed("No mvhd atom found"))?;
+    mvhd_creation_time(&moov[mvhd.start..mvhd.end])
+}
+
+/// Find the top-level `moov` atom of a file and read its content
+/// The atom may be at the end of the file, so the other atoms are skipped without reading them
+fn read_moov(path: &Path) -> Result<Vec<u8>, ProcessError> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut pos = 0;
+    while pos + 8 <= file_len {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut header = [0; 8];
+        file.read_exact(&mut header)?;
+        let mut header_len = 8;
+        let mut len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        if len == 1 {
+            let mut large_len = [0; 8];
+            file.read_exact(&mut large_len)?;
+            len = u64::from_be_bytes(large_len);
+            header_len = 16;
+        } else if len == 0 {
+            // The last atom extends to the end of the file
+            len = file_len - pos;
+        }
+        if len < header_len || pos + len > file_len {
+            return Err(malformed("Malformed QuickTime atom"));
+        }
+        if &header[4..] == b"moov" {
+            let content_len = len - header_len;
+            if content_len > MAX_MOOV_LEN {
+                return Err(malformed("The moov atom is too large"));
+            }
+            let mut moov = vec![0; content_len as usize];
+            file.read_exact(&mut moov)?;
+            return Ok(moov);
+        }
+        pos += len;
+    }
+    Err(malformed("No moov atom found"))
+}
+
+/// Split a range of bytes into the atoms it contains
+fn read_atoms(data: &[u8], start: usize, end: usize) -> Result<Vec<Atom>, ProcessError> {
+    let mut atoms = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end {
+        let mut header_len = 8;
+        let mut len = read_u32(data, pos)? as usize;
+        if len == 1 {
+            len = usize::try_from(read_u64(data, pos + 8)?).map_err(|_| malformed("QuickTime atom too large"))?;
+            header_len = 16;
+        } else if len == 0 {
+            len = end - pos;
+        }
+        if len < header_len || pos + len > end {
+            return Err(malformed("Malformed QuickTime atom"));
+        }
+        let mut kind = [0; 4];
+        kind.copy_from_slice(&data[pos + 4..pos + 8]);
+        atoms.push(Atom { kind, start: pos + header_len, end: pos + len });
+        pos += len;
+    }
+    Ok(atoms)
+}
+
+/// Read the creation time of an `mvhd` atom, stored as seconds since 1904 in UTC
+fn mvhd_creation_time(mvhd: &[u8]) -> Result<Option<DateTime<Utc>>, ProcessError> {
+    let version = *mvhd.first().ok_or_else(|| malformed("Truncated mvhd atom"))?;
+    let seconds = match version {
+        0 => read_u32(mvhd, 4)? as u64,
+        1 => read_u64(mvhd, 4)?,
+        _ => return Err(malformed(format!("Unsupported mvhd version {}", version))),
+    };
+    // Many encoders leave the creation time unset
+    if seconds == 0 {
+        return Ok(None);
+    }
+    let timestamp = i64::try_from(seconds).ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds - QUICKTIME_EPOCH_OFFSET, 0))
+        .ok_or_else(|| ProcessError::TimestampParse(format!("mvhd creation time {} is out of range", seconds)))?;
+    Ok(Some(timestamp))
+}
+
+/// Read the `com.apple.quicktime.creationdate` entry of a `meta` atom
+/// The keys are listed in the `keys` atom and the values in the `ilst` atom, indexed from 1
+fn quicktime_creation_date(data: &[u8], meta: &Atom) -> Option<DateTime<Utc>> {
+    // A QuickTime meta atom starts with its children, an MP4 one has version and flags first
+    let start = if data.get(meta.start + 4..meta.start + 8) == Some(&b"hdlr"[..]) {
+        meta.start
+    } else {
+        meta.start + 4
+    };
+    let children = read_atoms(data, start, meta.end).ok()?;
+    let keys = children.iter().find(|atom| &atom.kind == b"keys")?;
+    let ilst = children.iter().find(|atom| &atom.kind == b"ilst")?;
+
+    // Skip the version, flags and entry count
+    let mut pos = keys.start + 8;
+    let mut index = 1u32;
+    let key_index = loop {
+        if pos + 8 > keys.end {
+            return None;
+        }
+        let len = read_u32(data, pos).ok()? as usize;
+        if len < 8 || pos + len > keys.end {
+            return None;
+        }
+        if &data[pos + 8..pos + len] == QUICKTIME_CREATION_DATE_KEY {
+            break index;
+        }
+        pos += len;
+        index += 1;
+    };
+
+    let items = read_atoms(data, ilst.start, ilst.end).ok()?;
+    let item = items.iter().find(|item| item.kind == key_index.to_be_bytes())?;
+    let value = read_atoms(data, item.start, item.end).ok()?
+        .into_iter()
+        .find(|atom| &atom.kind == b"data")?;
+    // Skip the type indicator and the locale
+    let text = std::str::from_utf8(data.get(value.start + 8..value.end)?).ok()?;
+    parse_creation_date(text.trim_end_matches('\0'))
+}
+
+/// Parse a creation date such as `2021-03-14T10:22:33+0100`
+/// The local time is kept as is, the same way as the EXIF DateTimeOriginal of photos
+fn parse_creation_date(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%z")
+        .or_else(|_| DateTime::parse_from_rfc3339(text))
+        .ok()
+        .map(|date| date.naive_local().and_utc())
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, ProcessError> {
+    let bytes = data.get(pos..pos + 4).ok_or_else(|| malformed("Truncated QuickTime atom"))?;
+    Ok(u32::from_be_bytes(bytes.try_into().expect("4 bytes")))
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Result<u64, ProcessError> {
+    let bytes = data.get(pos..pos + 8).ok_or_else(|| malformed("Truncated QuickTime atom"))?;
+    Ok(u64::from_be_bytes(bytes.try_into().expect("8 bytes")))
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::lock;
+
+/// The number of paths shown for each kind of warning at the end of a run
+const EXAMPLES: usize = 3;
+
+/// A kind of warning about a file, for grouping the warnings at the end of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Category {
+    UnreadableMetadata,
+    BogusTimestamp,
+    NoExifDate,
+    InvalidLocalTime,
+    DatesDisagree,
+    NoVideoDate,
+    CreationTime,
+    Corrupt,
+    Empty,
+    Unchecked,
+    Transfer,
+    OutputNotUpdated,
+    PersonFolder,
+}
+
+impl Category {
+    /// What happened to the files, after their count
+    fn description(self) -> &'static str {
+        match self {
+            Category::UnreadableMetadata => "could not be read as metadata",
+            Category::BogusTimestamp => "had a bogus metadata timestamp",
+            Category::NoExifDate => "had no EXIF date",
+            Category::InvalidLocalTime => "had an EXIF date that is ambiguous or doesn't exist in the time zone",
+            Category::DatesDisagree => "had a metadata date and an EXIF date that disagree",
+            Category::NoVideoDate => "had no readable video creation date",
+            Category::CreationTime => "fell to creation time",
+            Category::Corrupt => "went to the corrupt directory",
+            Category::Empty => "had no content",
+            Category::Unchecked => "could not be checked for corruption",
+            Category::Transfer => "had trouble being moved, linked or copied",
+            Category::OutputNotUpdated => "had an error updating the output",
+            Category::PersonFolder => "could not be put in a person folder",
+        }
+    }
+}
+
+/// The warnings about files, in the order they were emitted
+static WARNINGS: Mutex<Vec<(Category, PathBuf)>> = Mutex::new(Vec::new());
+
+/// Remember a warning about a file for the digest printed at the end of the run
+/// The warning is still logged as it happens by the caller.
+pub fn record(category: Category, path: &Path) {
+    lock(&WARNINGS).push((category, path.to_path_buf()));
+}
+
+/// The warnings of the run grouped by kind, each with its number of files and the first few of
+/// them, or None if there were none. A file warned about twice for the same reason counts once.
+pub fn digest() -> Option<String> {
+    let warnings = lock(&WARNINGS);
+    if warnings.is_empty() {
+        return None;
+    }
+    let mut seen = HashSet::new();
+    let mut grouped: HashMap<Category, Vec<&Path>> = HashMap::new();
+    for (category, path) in warnings.iter() {
+        if seen.insert((category, path)) {
+            grouped.entry(*category).or_default().push(path);
+        }
+    }
+    let mut categories: Vec<_> = grouped.into_iter().collect();
+    categories.sort_by_key(|(category, _)| *category);
+
+    let mut lines = vec!["Warnings:".to_string()];
+    for (category, paths) in categories {
+        let noun = if paths.len() == 1 { "file" } else { "files" };
+        lines.push(format!("  {} {} {}", paths.len(), noun, category.description()));
+        lines.extend(paths.iter().take(EXAMPLES).map(|path| format!("    {}", path.display())));
+        if paths.len() > EXAMPLES {
+            lines.push(format!("    and {} more", paths.len() - EXAMPLES));
+        }
+    }
+    Some(lines.join("\n"))
+}
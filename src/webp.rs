@@ -0,0 +1,44 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::ProcessError;
+
+/// The first bytes of a RIFF file, before the size of its content
+const RIFF_MAGIC: &[u8] = b"RIFF";
+
+/// The form type of a WebP file, after the RIFF size
+const WEBP_FORM: &[u8] = b"WEBP";
+
+/// The prefix that some encoders put before the TIFF structure of the EXIF chunk, as in JPEG files
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+
+/// An error for a WebP file whose EXIF data can't be found
+fn invalid(message: impl Into<String>) -> ProcessError {
+    ProcessError::ExifParse(message.into())
+}
+
+/// Check whether a file is a WebP image, ignoring the case of the extension
+pub fn is_webp_file(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("webp"))
+}
+
+/// Extract the EXIF data of a WebP image, as the TIFF structure expected by `exif::Reader::read_raw`
+/// The `EXIF` chunk is found among the chunks of the RIFF file, and the `Exif\0\0` prefix that
+/// some encoders write in it is removed.
+pub fn read_exif(path: &Path) -> Result<Vec<u8>, ProcessError> {
+    let data = fs::read(path)?;
+    if !data.starts_with(RIFF_MAGIC) || data.get(8..12) != Some(WEBP_FORM) {
+        return Err(invalid("Not a WebP file"));
+    }
+    let mut pos = 12;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let chunk = data.get(pos + 8..pos + 8 + len).ok_or_else(|| invalid("Truncated WebP chunk"))?;
+        if header[..4].eq_ignore_ascii_case(b"EXIF") {
+            return Ok(chunk.strip_prefix(EXIF_PREFIX).unwrap_or(chunk).to_vec());
+        }
+        // Chunks are padded to an even length
+        pos += 8 + len + len % 2;
+    }
+    Err(invalid("No EXIF chunk found"))
+}
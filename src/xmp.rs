@@ -0,0 +1,67 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::GeoData;
+
+/// Format a coordinate the way XMP expects it, as degrees and decimal minutes followed by the
+/// reference, such as `48,51.3966N`
+fn format_coordinate(decimal: f64, positive: char, negative: char) -> String {
+    let reference = if decimal < 0.0 { negative } else { positive };
+    let decimal = decimal.abs();
+    let degrees = decimal.trunc();
+    let minutes = (decimal - degrees) * 60.0;
+    format!("{},{:.4}{}", degrees as u32, minutes, reference)
+}
+
+/// Escape the characters of a text that have a meaning in XML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Build an XMP sidecar holding the date a file was taken and its location and caption, if known
+pub fn render(taken_time: DateTime<Utc>, geo_data: Option<&GeoData>, description: Option<&str>) -> String {
+    let date = taken_time.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut properties = vec![
+        format!("xmp:CreateDate=\"{}\"", date),
+        format!("photoshop:DateCreated=\"{}\"", date),
+    ];
+    if let Some(geo_data) = geo_data {
+        properties.push("exif:GPSVersionID=\"2.3.0.0\"".to_string());
+        properties.push(format!("exif:GPSLatitude=\"{}\"", format_coordinate(geo_data.latitude, 'N', 'S')));
+        properties.push(format!("exif:GPSLongitude=\"{}\"", format_coordinate(geo_data.longitude, 'E', 'W')));
+        properties.push(format!("exif:GPSAltitudeRef=\"{}\"", u8::from(geo_data.altitude < 0.0)));
+        properties.push(format!("exif:GPSAltitude=\"{}/100\"", (geo_data.altitude.abs() * 100.0).round() as u32));
+    }
+
+    let mut xmp = String::new();
+    xmp.push_str("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    xmp.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+    xmp.push_str(" <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+    xmp.push_str("  <rdf:Description rdf:about=\"\"\n");
+    xmp.push_str("    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n");
+    xmp.push_str("    xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\"\n");
+    xmp.push_str("    xmlns:exif=\"http://ns.adobe.com/exif/1.0/\"");
+    if description.is_some() {
+        xmp.push_str("\n    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"");
+    }
+    for property in properties {
+        xmp.push_str("\n    ");
+        xmp.push_str(&property);
+    }
+    match description {
+        // The description is a list of alternatives by language, which can't be an attribute
+        Some(description) => {
+            xmp.push_str(">\n");
+            xmp.push_str("   <dc:description>\n");
+            xmp.push_str("    <rdf:Alt>\n");
+            xmp.push_str(&format!("     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n", escape(description)));
+            xmp.push_str("    </rdf:Alt>\n");
+            xmp.push_str("   </dc:description>\n");
+            xmp.push_str("  </rdf:Description>\n");
+        }
+        None => xmp.push_str("/>\n"),
+    }
+    xmp.push_str(" </rdf:RDF>\n");
+    xmp.push_str("</x:xmpmeta>\n");
+    xmp.push_str("<?xpacket end=\"w\"?>\n");
+    xmp
+}
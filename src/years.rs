@@ -0,0 +1,38 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::lock;
+
+/// The number of files put in the output for each year they were taken
+static COUNTS: Mutex<BTreeMap<i32, usize>> = Mutex::new(BTreeMap::new());
+
+/// Count a file put in the output (or that would be, in a dry run) with the year it was taken
+pub fn record(year: i32) {
+    *lock(&COUNTS).entry(year).or_default() += 1;
+}
+
+/// Write a count with a comma between each group of three digits
+fn with_separators(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+/// A table of the files per year, from the oldest, or None if no file was put in the output
+/// An unusually big year such as 1970 usually means dates that were not read correctly.
+pub fn table() -> Option<String> {
+    let counts = lock(&COUNTS);
+    if counts.is_empty() {
+        return None;
+    }
+    let rows: Vec<String> = counts.iter()
+        .map(|(year, count)| format!("  {}: {} {}", year, with_separators(*count), if *count == 1 { "file" } else { "files" }))
+        .collect();
+    Some(format!("Files per year:\n{}", rows.join("\n")))
+}
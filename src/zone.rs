@@ -0,0 +1,505 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use chrono::{DateTime, Datelike, FixedOffset, MappedLocalTime, NaiveDate, NaiveDateTime, Offset, TimeZone};
+
+/// The directories searched for the time zone database when the TZDIR variable is not set
+const ZONEINFO_DIRECTORIES: &[&str] = &["/usr/share/zoneinfo", "/usr/lib/zoneinfo", "/usr/share/lib/zoneinfo"];
+
+/// The rule of a POSIX TZ string with daylight saving time but no dates, which glibc takes as the US one
+const DEFAULT_DST_RULE: &str = "M3.2.0,M11.1.0";
+
+/// The time of day of the transitions of a POSIX TZ rule that doesn't give one
+const DEFAULT_TRANSITION_TIME: i64 = 2 * 60 * 60;
+
+/// A time zone the clocks of cameras were set to, for the dates that have no offset
+/// Parsed from an IANA name such as `Europe/Paris`, read from the time zone database of the system,
+/// a POSIX TZ rule such as `CET-1CEST,M3.5.0,M10.5.0/3`, or an offset such as `+02:00` or `UTC`.
+/// The last two need no database, which Windows and minimal containers don't have.
+#[derive(Clone)]
+pub struct Zone {
+    name: Arc<str>,
+    rules: Arc<Rules>,
+}
+
+/// The offsets of a zone, in seconds east of UTC
+#[derive(Debug)]
+struct Rules {
+    /// The instants in UTC seconds at which the offset changes, with the offset from then on
+    transitions: Vec<(i64, i32)>,
+    /// The offset before the first transition
+    initial: i32,
+    /// The rule after the last transition, which is the whole zone for a POSIX TZ rule
+    rule: Option<PosixRule>,
+}
+
+/// A POSIX TZ rule, with the offsets of standard and daylight saving time and when the latter starts and ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PosixRule {
+    Fixed(i32),
+    Daylight { standard: i32, daylight: i32, start: Transition, end: Transition },
+}
+
+/// The day and time of day, in the local time before it, of a transition of a POSIX TZ rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transition {
+    day: RuleDay,
+    time: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleDay {
+    /// `Jn`, the day from 1 to 365, not counting February 29
+    Julian(u32),
+    /// `n`, the day from 0 to 365, counting February 29
+    Ordinal(u32),
+    /// `Mm.w.d`, the weekday `d` from 0 for Sunday of week `w` of month `m`, where week 5 is the last one
+    MonthWeekDay { month: u32, week: u32, weekday: u32 },
+}
+
+impl RuleDay {
+    /// The start of the day in a year, in seconds since the epoch of the local time
+    fn start(self, year: i32) -> Option<i64> {
+        let date = match self {
+            RuleDay::Julian(day) => {
+                let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+                NaiveDate::from_yo_opt(year, day + u32::from(leap && day >= 60))?
+            }
+            RuleDay::Ordinal(day) => NaiveDate::from_yo_opt(year, day + 1)?,
+            RuleDay::MonthWeekDay { month, week, weekday } => {
+                let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+                let first_weekday = (weekday + 7 - first.weekday().num_days_from_sunday()) % 7;
+                // Week 5 is the fourth week in the months that have no fifth one
+                (1..=week).rev()
+                    .map(|week| first + chrono::Days::new((first_weekday + (week - 1) * 7) as u64))
+                    .find(|day| day.month() == month)?
+            }
+        };
+        Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp())
+    }
+}
+
+impl PosixRule {
+    fn offset_at(self, utc: i64) -> i32 {
+        let (standard, daylight, start, end) = match self {
+            PosixRule::Fixed(offset) => return offset,
+            PosixRule::Daylight { standard, daylight, start, end } => (standard, daylight, start, end),
+        };
+        let Some(year) = DateTime::from_timestamp(utc + standard as i64, 0).map(|time| time.year()) else {
+            return standard;
+        };
+        // The start is in standard time and the end in daylight saving time
+        let (Some(start_day), Some(end_day)) = (start.day.start(year), end.day.start(year)) else {
+            return standard;
+        };
+        let start = start_day + start.time - standard as i64;
+        let end = end_day + end.time - daylight as i64;
+        // Daylight saving time spans the new year in the southern hemisphere
+        let daylight_saving = if start < end { start <= utc && utc < end } else { utc < end || start <= utc };
+        if daylight_saving { daylight } else { standard }
+    }
+
+    fn offsets(self) -> [i32; 2] {
+        match self {
+            PosixRule::Fixed(offset) => [offset, offset],
+            PosixRule::Daylight { standard, daylight, .. } => [standard, daylight],
+        }
+    }
+}
+
+impl Rules {
+    fn offset_at(&self, utc: i64) -> i32 {
+        match self.transitions.partition_point(|&(time, _)| time <= utc) {
+            0 if !self.transitions.is_empty() => self.initial,
+            after if after == self.transitions.len() => match self.rule {
+                Some(rule) => rule.offset_at(utc),
+                None => self.transitions.last().map_or(self.initial, |&(_, offset)| offset),
+            },
+            after => self.transitions[after - 1].1,
+        }
+    }
+
+    /// The offsets a local time has, from its earliest instant to its latest one
+    /// There are none for a time skipped by a transition, and two for a time repeated by one.
+    fn local_offsets(&self, local: i64) -> Vec<i32> {
+        let mut offsets: Vec<i32> = std::iter::once(self.initial)
+            .chain(self.transitions.iter().map(|&(_, offset)| offset))
+            .chain(self.rule.iter().flat_map(|rule| rule.offsets()))
+            .collect();
+        // The earliest instant has the largest offset
+        offsets.sort_unstable_by(|a, b| b.cmp(a));
+        offsets.dedup();
+        offsets.retain(|&offset| self.offset_at(local - offset as i64) == offset);
+        offsets
+    }
+}
+
+/// A reader of the fields of a POSIX TZ rule
+struct RuleCursor<'a> {
+    rule: &'a str,
+}
+
+impl RuleCursor<'_> {
+    fn eat(&mut self, prefix: char) -> bool {
+        match self.rule.strip_prefix(prefix) {
+            Some(rest) => {
+                self.rule = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let digits = self.rule.find(|c: char| !c.is_ascii_digit()).unwrap_or(self.rule.len());
+        let number = self.rule[..digits].parse().ok()?;
+        self.rule = &self.rule[digits..];
+        Some(number)
+    }
+
+    /// Read the name of an offset, either letters such as `CET` or quoted such as `<+03>`
+    fn name(&mut self) -> Option<()> {
+        let len = if self.eat('<') {
+            self.rule.find('>')? + 1
+        } else {
+            self.rule.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(self.rule.len())
+        };
+        if len < 3 {
+            return None;
+        }
+        self.rule = &self.rule[len..];
+        Some(())
+    }
+
+    /// Read a signed duration of the form `[+-]hh[:mm[:ss]]`, in seconds
+    fn duration(&mut self) -> Option<i64> {
+        let sign = if self.eat('-') { -1 } else { self.eat('+'); 1 };
+        let mut seconds = self.number()? as i64 * 3600;
+        for unit in [60, 1] {
+            if !self.eat(':') {
+                break;
+            }
+            seconds += self.number()? as i64 * unit;
+        }
+        Some(sign * seconds)
+    }
+
+    fn transition(&mut self) -> Option<Transition> {
+        let day = if self.eat('J') {
+            RuleDay::Julian(self.number().filter(|day| (1..=365).contains(day))?)
+        } else if self.eat('M') {
+            let month = self.number().filter(|month| (1..=12).contains(month))?;
+            self.eat('.').then_some(())?;
+            let week = self.number().filter(|week| (1..=5).contains(week))?;
+            self.eat('.').then_some(())?;
+            let weekday = self.number().filter(|weekday| *weekday <= 6)?;
+            RuleDay::MonthWeekDay { month, week, weekday }
+        } else {
+            RuleDay::Ordinal(self.number().filter(|day| *day <= 365)?)
+        };
+        let time = if self.eat('/') { self.duration()? } else { DEFAULT_TRANSITION_TIME };
+        Some(Transition { day, time })
+    }
+}
+
+/// Parse a POSIX TZ rule, whose offsets are in seconds west of UTC, unlike everywhere else
+fn parse_posix_rule(rule: &str) -> Option<PosixRule> {
+    let mut cursor = RuleCursor { rule };
+    cursor.name()?;
+    let standard = -cursor.duration()? as i32;
+    if cursor.rule.is_empty() {
+        return Some(PosixRule::Fixed(standard));
+    }
+    cursor.name()?;
+    let daylight = if cursor.rule.is_empty() || cursor.rule.starts_with(',') {
+        standard + 3600
+    } else {
+        -cursor.duration()? as i32
+    };
+    if cursor.rule.is_empty() {
+        cursor.rule = DEFAULT_DST_RULE;
+    } else {
+        cursor.eat(',').then_some(())?;
+    }
+    let start = cursor.transition()?;
+    cursor.eat(',').then_some(())?;
+    let end = cursor.transition()?;
+    cursor.rule.is_empty().then_some(PosixRule::Daylight { standard, daylight, start, end })
+}
+
+/// Parse a fixed offset such as `UTC`, `+02:00`, `-0530` or `+9`
+fn parse_fixed_offset(offset: &str) -> Option<i32> {
+    if ["UTC", "GMT", "Z"].iter().any(|utc| offset.eq_ignore_ascii_case(utc)) {
+        return Some(0);
+    }
+    let (sign, rest) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let (hours, minutes): (i32, i32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if !(0..=14).contains(&hours) || !(0..60).contains(&minutes) || !rest.bytes().all(|byte| byte.is_ascii_digit() || byte == b':') {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Read the big-endian number of `N` bytes at a position of a TZif file
+fn be_at<const N: usize>(data: &[u8], pos: usize) -> Option<i64> {
+    let bytes: [u8; N] = data.get(pos..pos + N)?.try_into().ok()?;
+    Some(bytes.iter().fold(if bytes[0] & 0x80 != 0 { -1 } else { 0 }, |value, &byte| value << 8 | byte as i64))
+}
+
+/// Parse a file of the time zone database, in the TZif format of RFC 8536
+/// Version 2 and later files repeat the data with 64-bit times after the 32-bit one, followed by the
+/// POSIX TZ rule of the times after the last transition.
+fn parse_tzif(data: &[u8]) -> Option<Rules> {
+    let counts = |header: usize| -> Option<[usize; 6]> {
+        if data.get(header..header + 4)? != b"TZif" {
+            return None;
+        }
+        let mut counts = [0; 6];
+        for (index, count) in counts.iter_mut().enumerate() {
+            *count = be_at::<4>(data, header + 20 + index * 4)? as u32 as usize;
+        }
+        Some(counts)
+    };
+    let [utc_count, standard_count, leap_count, transition_count, type_count, char_count] = counts(0)?;
+    let version = *data.get(4)?;
+    let (header, time_size) = if version >= b'2' {
+        let v1_len = transition_count * 5 + type_count * 6 + char_count + leap_count * 8 + standard_count + utc_count;
+        (44 + v1_len, 8)
+    } else {
+        (0, 4)
+    };
+    let [utc_count, standard_count, leap_count, transition_count, type_count, char_count] = counts(header)?;
+    if type_count == 0 {
+        return None;
+    }
+    let times = header + 44;
+    let indices = times + transition_count * time_size;
+    let types = indices + transition_count;
+    let type_offset = |index: usize| -> Option<i32> {
+        (index < type_count).then_some(())?;
+        Some(be_at::<4>(data, types + index * 6)? as i32)
+    };
+    let transitions = (0..transition_count)
+        .map(|index| {
+            let time = if time_size == 8 { be_at::<8>(data, times + index * 8)? } else { be_at::<4>(data, times + index * 4)? };
+            Some((time, type_offset(*data.get(indices + index)? as usize)?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let footer = types + type_count * 6 + char_count + leap_count * (time_size + 4) + standard_count + utc_count;
+    let rule = match data.get(footer..) {
+        Some([b'\n', rest @ ..]) if version >= b'2' => {
+            let end = rest.iter().position(|&byte| byte == b'\n')?;
+            match std::str::from_utf8(&rest[..end]).ok()? {
+                "" => None,
+                rule => Some(parse_posix_rule(rule)?),
+            }
+        }
+        _ => None,
+    };
+    Some(Rules { transitions, initial: type_offset(0)?, rule })
+}
+
+/// Find the file of a zone in the time zone database, from the TZDIR variable or its usual places
+fn zoneinfo_file(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.starts_with('/') || name.split('/').any(|part| part == "..") {
+        return None;
+    }
+    std::env::var_os("TZDIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(ZONEINFO_DIRECTORIES.iter().map(PathBuf::from))
+        .map(|directory| directory.join(name))
+        .find(|path| path.is_file())
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules = if let Some(offset) = parse_fixed_offset(s) {
+            Rules { transitions: Vec::new(), initial: offset, rule: None }
+        } else if let Some(path) = zoneinfo_file(s) {
+            let data = fs::read(&path).map_err(|e| format!("failed to read the time zone {} from {:?}: {}", s, path, e))?;
+            parse_tzif(&data).ok_or_else(|| format!("{:?} is not a valid time zone file", path))?
+        } else if let Some(rule) = parse_posix_rule(s) {
+            Rules { transitions: Vec::new(), initial: rule.offsets()[0], rule: Some(rule) }
+        } else {
+            return Err(format!(
+                "unknown time zone {} (expected an IANA name found in the time zone database of TZDIR or {}, \
+                 a POSIX TZ rule such as CET-1CEST,M3.5.0,M10.5.0/3, or an offset such as +02:00)",
+                s, ZONEINFO_DIRECTORIES[0],
+            ));
+        };
+        Ok(Zone { name: s.into(), rules: Arc::new(rules) })
+    }
+}
+
+impl fmt::Debug for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Zone({})", self.name)
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// The offset of a zone at an instant, which keeps its zone as chrono requires
+#[derive(Clone)]
+pub struct ZoneOffset {
+    zone: Zone,
+    offset: FixedOffset,
+}
+
+impl ZoneOffset {
+    fn new(zone: &Zone, seconds: i32) -> ZoneOffset {
+        let offset = FixedOffset::east_opt(seconds).unwrap_or_else(|| FixedOffset::east_opt(0).expect("UTC is a valid offset"));
+        ZoneOffset { zone: zone.clone(), offset }
+    }
+}
+
+impl Offset for ZoneOffset {
+    fn fix(&self) -> FixedOffset {
+        self.offset
+    }
+}
+
+impl fmt::Debug for ZoneOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.offset)
+    }
+}
+
+impl fmt::Display for ZoneOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.offset)
+    }
+}
+
+impl TimeZone for Zone {
+    type Offset = ZoneOffset;
+
+    fn from_offset(offset: &ZoneOffset) -> Zone {
+        offset.zone.clone()
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<ZoneOffset> {
+        match local.and_hms_opt(0, 0, 0) {
+            Some(midnight) => self.offset_from_local_datetime(&midnight),
+            None => MappedLocalTime::None,
+        }
+    }
+
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> MappedLocalTime<ZoneOffset> {
+        match self.rules.local_offsets(local.and_utc().timestamp()).as_slice() {
+            [] => MappedLocalTime::None,
+            [offset] => MappedLocalTime::Single(ZoneOffset::new(self, *offset)),
+            [earliest, .., latest] => MappedLocalTime::Ambiguous(ZoneOffset::new(self, *earliest), ZoneOffset::new(self, *latest)),
+        }
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> ZoneOffset {
+        let midnight = utc.and_hms_opt(0, 0, 0).map_or(0, |midnight| midnight.and_utc().timestamp());
+        ZoneOffset::new(self, self.rules.offset_at(midnight))
+    }
+
+    fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> ZoneOffset {
+        ZoneOffset::new(self, self.rules.offset_at(utc.and_utc().timestamp()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn local(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    /// The UTC instants of a local time in a zone
+    fn to_utc(zone: &str, date: &str) -> Vec<String> {
+        let zone: Zone = zone.parse().unwrap();
+        let instants = match zone.from_local_datetime(&local(date)) {
+            MappedLocalTime::Single(time) => vec![time],
+            MappedLocalTime::Ambiguous(earliest, latest) => vec![earliest, latest],
+            MappedLocalTime::None => vec![],
+        };
+        instants.iter().map(|time| time.with_timezone(&Utc).format("%Y-%m-%d %H:%M").to_string()).collect()
+    }
+
+    #[test]
+    fn parses_fixed_offsets() {
+        assert_eq!(to_utc("+02:00", "2021-07-01 12:00"), ["2021-07-01 10:00"]);
+        assert_eq!(to_utc("-0530", "2021-07-01 12:00"), ["2021-07-01 17:30"]);
+        assert_eq!(to_utc("UTC", "2021-07-01 12:00"), ["2021-07-01 12:00"]);
+        for invalid in ["+25:00", "+02:60", "Nowhere/Special", "+2x", ""] {
+            assert!(invalid.parse::<Zone>().is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn follows_posix_rules() {
+        let paris = "CET-1CEST,M3.5.0,M10.5.0/3";
+        assert_eq!(to_utc(paris, "2021-01-15 12:00"), ["2021-01-15 11:00"]);
+        assert_eq!(to_utc(paris, "2021-07-01 12:00"), ["2021-07-01 10:00"]);
+        // Clocks go from 02:00 to 03:00 on March 28, and from 03:00 back to 02:00 on October 31
+        assert_eq!(to_utc(paris, "2021-03-28 02:30"), Vec::<String>::new());
+        assert_eq!(to_utc(paris, "2021-10-31 02:30"), ["2021-10-31 00:30", "2021-10-31 01:30"]);
+        assert_eq!(to_utc("<-03>3", "2021-07-01 12:00"), ["2021-07-01 15:00"]);
+        assert_eq!(to_utc("EST5EDT", "2021-07-01 12:00").len(), 1);
+    }
+
+    #[test]
+    fn follows_southern_posix_rules() {
+        let sydney = "AEST-10AEDT,M10.1.0,M4.1.0/3";
+        assert_eq!(to_utc(sydney, "2021-01-15 12:00"), ["2021-01-15 01:00"]);
+        assert_eq!(to_utc(sydney, "2021-07-01 12:00"), ["2021-07-01 02:00"]);
+    }
+
+    /// A TZif version 2 file with a 32-bit block without data, then two transitions and a rule
+    fn tzif() -> Vec<u8> {
+        let header = |counts: [u32; 6]| {
+            let mut header = b"TZif2".to_vec();
+            header.extend_from_slice(&[0; 15]);
+            counts.iter().for_each(|count| header.extend_from_slice(&count.to_be_bytes()));
+            header
+        };
+        let mut data = header([0, 0, 0, 0, 1, 4]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(b"LMT\0");
+        data.extend(header([0, 0, 0, 2, 2, 8]));
+        // 1900-01-01 and 2000-01-01, after which the rule applies
+        data.extend_from_slice(&(-2_208_988_800i64).to_be_bytes());
+        data.extend_from_slice(&946_684_800i64.to_be_bytes());
+        data.extend_from_slice(&[1, 1]);
+        data.extend_from_slice(&[0, 0, 0x02, 0x30, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0x0E, 0x10, 0, 4]);
+        data.extend_from_slice(b"LMT\0CET\0");
+        data.extend_from_slice(b"\nCET-1CEST,M3.5.0,M10.5.0/3\n");
+        data
+    }
+
+    #[test]
+    fn reads_the_time_zone_database() {
+        let rules = parse_tzif(&tzif()).unwrap();
+        assert_eq!(rules.offset_at(-3_000_000_000), 560);
+        assert_eq!(rules.offset_at(0), 3600);
+        let summer = local("2021-07-01 12:00").and_utc().timestamp();
+        assert_eq!(rules.offset_at(summer), 7200);
+        assert_eq!(rules.local_offsets(summer), [7200]);
+    }
+}
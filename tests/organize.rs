@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A fresh directory for a test, under the temporary directory of the integration tests
+fn test_directory(name: &str) -> PathBuf {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(directory.join("input")).unwrap();
+    fs::create_dir_all(directory.join("output")).unwrap();
+    directory
+}
+
+/// Write a photo with the metadata file Takeout exports next to it, taken at a Unix timestamp
+fn write_photo(input: &Path, name: &str, timestamp: i64) {
+    fs::write(input.join(name), format!("not really a photo named {}", name)).unwrap();
+    let metadata = format!(r#"{{"title": "{}", "photoTakenTime": {{"timestamp": "{}"}}}}"#, name, timestamp);
+    fs::write(input.join(format!("{}.json", name)), metadata).unwrap();
+}
+
+/// Run the tool on the input and output directories of a test, with extra arguments
+fn run(directory: &Path, arguments: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_takeout-exif-fix"))
+        .arg("--input").arg(directory.join("input"))
+        .arg("--output").arg(directory.join("output"))
+        .args(arguments)
+        .output()
+        .unwrap()
+}
+
+/// Run the tool like `run`, checking that it succeeded
+fn organize(directory: &Path, arguments: &[&str]) -> Output {
+    let output = run(directory, arguments);
+    assert!(output.status.success(), "the run failed: {}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// The paths of the files of a directory, relative to it and sorted
+fn files_in(directory: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(current) = directories.pop() {
+        for entry in fs::read_dir(&current).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                directories.push(path);
+            } else {
+                files.push(path.strip_prefix(directory).unwrap().to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Build a small export, with an album, a video and a photo dated by its name only
+fn write_export(input: &Path) {
+    fs::create_dir_all(input.join("Photos from 2019")).unwrap();
+    fs::create_dir_all(input.join("Vacation")).unwrap();
+    write_photo(&input.join("Photos from 2019"), "IMG_0001.jpg", 1_557_126_489);
+    write_photo(&input.join("Photos from 2019"), "VID_0002.mp4", 1_600_000_000);
+    write_photo(&input.join("Vacation"), "IMG_0003.jpg", 1_557_126_489);
+    fs::write(input.join("Photos from 2019/IMG_20210314_153045.jpg"), "a photo without metadata").unwrap();
+}
+
+#[test]
+fn organizes_an_export_by_date() {
+    let directory = test_directory("organizes_an_export_by_date");
+    write_export(&directory.join("input"));
+
+    organize(&directory, &[]);
+
+    let output = directory.join("output");
+    assert_eq!(files_in(&output), [
+        "2019/May/jpg/IMG_0001.jpg",
+        "2019/May/jpg/IMG_0003.jpg",
+        "2020/September/mp4/VID_0002.mp4",
+        "2021/March/jpg/IMG_20210314_153045.jpg",
+    ]);
+    let modified = filetime::FileTime::from_last_modification_time(&fs::metadata(output.join("2019/May/jpg/IMG_0001.jpg")).unwrap());
+    assert_eq!(modified.unix_seconds(), 1_557_126_489);
+    // The input is left alone when copying
+    assert!(directory.join("input/Vacation/IMG_0003.jpg").exists());
+}
+
+#[test]
+fn rerun_leaves_the_output_unchanged() {
+    let directory = test_directory("rerun_leaves_the_output_unchanged");
+    let input = directory.join("input");
+    write_export(&input);
+    // A JPEG its location is written into, which makes its copy larger than it
+    fs::write(input.join("Vacation/IMG_0004.jpg"), [0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9]).unwrap();
+    let metadata = r#"{"title": "IMG_0004.jpg", "photoTakenTime": {"timestamp": "1557126489"}, "geoData": {"latitude": 48.85, "longitude": 2.35, "altitude": 35.0}}"#;
+    fs::write(input.join("Vacation/IMG_0004.jpg.json"), metadata).unwrap();
+    let output = directory.join("output");
+
+    organize(&directory, &[]);
+    let first_run = files_in(&output);
+    let modified = fs::metadata(output.join("2019/May/jpg/IMG_0001.jpg")).unwrap().modified().unwrap();
+    let located = fs::read(output.join("2019/May/jpg/IMG_0004.jpg")).unwrap();
+    assert!(located.len() > 8);
+    organize(&directory, &[]);
+
+    assert_eq!(files_in(&output), first_run);
+    assert_eq!(first_run, [
+        ".takeout-exif-fix-rewrites",
+        "2019/May/jpg/IMG_0001.jpg",
+        "2019/May/jpg/IMG_0003.jpg",
+        "2019/May/jpg/IMG_0004.jpg",
+        "2020/September/mp4/VID_0002.mp4",
+        "2021/March/jpg/IMG_20210314_153045.jpg",
+    ]);
+    assert_eq!(fs::metadata(output.join("2019/May/jpg/IMG_0001.jpg")).unwrap().modified().unwrap(), modified);
+    assert_eq!(fs::read(output.join("2019/May/jpg/IMG_0004.jpg")).unwrap(), located);
+}
+
+#[test]
+fn rerun_with_rounded_timestamps_skips_organized_files() {
+    let directory = test_directory("rerun_with_rounded_timestamps");
+    // 2019-05-06 07:08:09 UTC, which is not on a whole minute
+    write_photo(&directory.join("input"), "IMG_0001.jpg", 1_557_126_489);
+
+    organize(&directory, &["--round-timestamps", "minute"]);
+    organize(&directory, &["--round-timestamps", "minute"]);
+
+    let output = directory.join("output");
+    assert_eq!(files_in(&output), ["2019/May/jpg/IMG_0001.jpg"]);
+    let modified = filetime::FileTime::from_last_modification_time(&fs::metadata(output.join("2019/May/jpg/IMG_0001.jpg")).unwrap());
+    assert_eq!(modified.unix_seconds(), 1_557_126_480);
+}
+
+#[test]
+fn reports_files_organized_before_and_duplicates_as_skipped() {
+    let directory = test_directory("reports_files_organized_before");
+    let input = directory.join("input");
+    // The same photo in two albums, which `--dedup` puts in the output once
+    for album in ["Photos from 2019", "Vacation"] {
+        fs::create_dir_all(input.join(album)).unwrap();
+        write_photo(&input.join(album), "IMG_0001.jpg", 1_557_126_489);
+    }
+    let report_path = directory.join("report.json");
+    let arguments = ["--dedup", "--report", report_path.to_str().unwrap()];
+    let report = || -> serde_json::Value { serde_json::from_slice(&fs::read(&report_path).unwrap()).unwrap() };
+
+    organize(&directory, &arguments);
+    let first_run = report();
+    organize(&directory, &arguments);
+    let second_run = report();
+
+    assert_eq!(files_in(&directory.join("output")), ["2019/May/jpg/IMG_0001.jpg"]);
+    assert_eq!((first_run["metadata"].as_u64(), first_run["skipped"].as_u64(), first_run["duplicates"].as_u64()), (Some(1), Some(1), Some(1)));
+    assert_eq!((second_run["metadata"].as_u64(), second_run["skipped"].as_u64(), second_run["already_organized"].as_u64()), (Some(0), Some(2), Some(2)));
+}
+
+#[test]
+fn stops_without_a_terminal_to_confirm_on() {
+    let directory = test_directory("stops_without_a_terminal");
+    write_export(&directory.join("input"));
+
+    // The standard input of the run is not a terminal
+    let output = run(&directory, &["--confirm-threshold", "1"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no terminal to confirm on"));
+    assert!(files_in(&directory.join("output")).is_empty());
+
+    organize(&directory, &["--confirm-threshold", "1", "--yes"]);
+    assert_eq!(files_in(&directory.join("output")).len(), 4);
+}
+
+#[test]
+fn names_xmp_sidecars_apart_for_files_of_the_same_name() {
+    let directory = test_directory("names_xmp_sidecars_apart");
+    write_photo(&directory.join("input"), "IMG_0001.jpg", 1_557_126_489);
+    write_photo(&directory.join("input"), "IMG_0001.CR2", 1_557_126_489);
+
+    organize(&directory, &["--write-xmp", "--no-ext-folders"]);
+
+    // `IMG_0001.CR2` sorts before `IMG_0001.jpg`, so its sidecar gets the name without an extension
+    assert_eq!(files_in(&directory.join("output")), [
+        "2019/May/IMG_0001.CR2",
+        "2019/May/IMG_0001.jpg",
+        "2019/May/IMG_0001.jpg.xmp",
+        "2019/May/IMG_0001.xmp",
+    ]);
+}